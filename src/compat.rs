@@ -0,0 +1,140 @@
+//! プラットフォームごとの既知の再生制限のチェック
+//!
+//! ルールは [`COMPAT_RULES`] に追加するだけで拡張できる。
+
+use crate::i18n::{Lang, lang};
+use crate::mp4::TrackInfo;
+use crate::tr;
+
+/// チェックに対応しているプラットフォームの名前（`--compat-check` の値）と表示名
+pub const PLATFORMS: &[(&str, &str)] = &[
+    ("safari", "Safari"),
+    ("chrome", "Chrome"),
+    ("firefox", "Firefox"),
+];
+
+/// 再生制限のルール
+pub struct CompatRule {
+    /// 対象のプラットフォームの名前
+    pub platform: &'static str,
+    /// トラックがルールに該当するかどうか
+    pub matches: fn(&TrackInfo) -> bool,
+    pub message: &'static str,
+    /// `message` の英語版
+    pub message_en: &'static str,
+}
+
+/// 既知の再生制限の一覧
+pub const COMPAT_RULES: &[CompatRule] = &[
+    CompatRule {
+        platform: "safari",
+        matches: |track| is_entry(track, "hev1"),
+        message: "HEVC はサンプルエントリーが hvc1 でないと再生できません（hev1 は再生できません）",
+        message_en: "HEVC plays only with an hvc1 sample entry (hev1 is not supported)",
+    },
+    CompatRule {
+        platform: "safari",
+        matches: |track| is_entry(track, "Opus"),
+        message: "MP4 内の Opus は Safari 17 より前のバージョンでは再生できません",
+        message_en: "Opus in MP4 does not play before Safari 17",
+    },
+    CompatRule {
+        platform: "safari",
+        matches: |track| is_entry(track, "vp08"),
+        message: "MP4 内の VP8 は再生できません",
+        message_en: "VP8 in MP4 is not supported",
+    },
+    CompatRule {
+        platform: "safari",
+        matches: |track| is_entry(track, "av01"),
+        message: "AV1 はハードウェアデコーダーを持つ端末（Apple M3 / A17 Pro 以降）でのみ再生できます",
+        message_en: "AV1 plays only on devices with a hardware decoder (Apple M3 / A17 Pro or later)",
+    },
+    CompatRule {
+        platform: "chrome",
+        matches: |track| is_entry(track, "hev1") || is_entry(track, "hvc1"),
+        message: "HEVC は HEVC のハードウェアデコーダーがある環境でのみ再生できます",
+        message_en: "HEVC plays only where a hardware HEVC decoder is available",
+    },
+    CompatRule {
+        platform: "firefox",
+        matches: |track| is_entry(track, "hev1") || is_entry(track, "hvc1"),
+        message: "HEVC は Windows でハードウェアデコーダーがある場合など、一部の環境でのみ再生できます",
+        message_en: "HEVC plays only in some environments, such as Windows with a hardware decoder",
+    },
+];
+
+impl CompatRule {
+    /// 表示言語に合わせたメッセージ
+    pub fn message(&self) -> &'static str {
+        match lang() {
+            Lang::Ja => self.message,
+            Lang::En => self.message_en,
+        }
+    }
+}
+
+fn is_entry(track: &TrackInfo, box_type: &str) -> bool {
+    track.sample_entry_type.as_deref() == Some(box_type)
+}
+
+/// `--compat-check` の値からプラットフォームの表示名を取得する
+pub fn platform_label(name: &str) -> Result<&'static str, String> {
+    PLATFORMS
+        .iter()
+        .find(|(platform, _)| platform.eq_ignore_ascii_case(name))
+        .map(|&(_, label)| label)
+        .ok_or_else(|| {
+            let names: Vec<&str> = PLATFORMS.iter().map(|&(platform, _)| platform).collect();
+            tr!(
+                "未対応のプラットフォームです: {}（対応しているのは {}）",
+                "Unsupported platform: {} (supported: {})",
+                name,
+                names.join(", ")
+            )
+        })
+}
+
+/// 指定したプラットフォームで該当するルールを、`(1 始まりのトラック番号, メッセージ)` の一覧で返す
+pub fn check(platform: &str, tracks: &[TrackInfo]) -> Vec<(usize, &'static str)> {
+    tracks
+        .iter()
+        .enumerate()
+        .flat_map(|(i, track)| {
+            COMPAT_RULES
+                .iter()
+                .filter(move |rule| {
+                    rule.platform.eq_ignore_ascii_case(platform) && (rule.matches)(track)
+                })
+                .map(move |rule| (i + 1, rule.message()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(sample_entry_type: &str) -> TrackInfo {
+        TrackInfo {
+            track_id: 1,
+            media_type: "ビデオ".to_string(),
+            duration: 10.0,
+            sample_entry_type: Some(sample_entry_type.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        let tracks = [track("avc1"), track("hev1"), track("Opus")];
+        let warnings = check("Safari", &tracks);
+        assert_eq!(warnings.iter().map(|&(i, _)| i).collect::<Vec<_>>(), [2, 3]);
+        assert_eq!(check("chrome", &tracks).len(), 1);
+        assert_eq!(check("chrome", &[track("hvc1")]).len(), 1);
+        assert!(check("safari", &[track("hvc1")]).is_empty());
+
+        assert_eq!(platform_label("SAFARI"), Ok("Safari"));
+        assert!(platform_label("netscape").is_err());
+    }
+}
@@ -0,0 +1,854 @@
+//! フラグメント MP4（fMP4）の初期化セグメントとメディアセグメントの組み立てと、フラグメント MP4 の読み込み
+//!
+//! shiguredo_mp4 はフラグメント関連のボックス（mvex / moof など）に対応していないため、
+//! それらのボックスはここでバイト列として直接組み立て・解析する。
+
+use std::io::{Read, Seek, SeekFrom};
+use std::num::NonZeroU32;
+
+use shiguredo_mp4::{
+    BoxSize, BoxType, Either, Encode,
+    aux::SampleTableAccessor,
+    boxes::{Co64Box, MoovBox, StcoBox, StscBox, StscEntry, StssBox, StszBox, SttsBox, UnknownBox},
+};
+
+use crate::mp4::{
+    ResourceLimits, RootBoxLocation, build_ctts_box, child_boxes, composition_offsets,
+    parse_ctts_entries,
+};
+use crate::tr;
+
+/// trun の sample_flags: 他のサンプルに依存しない（キーフレーム）
+const SYNC_SAMPLE_FLAGS: u32 = 0x0200_0000;
+
+/// trun の sample_flags: 他のサンプルに依存し、同期サンプルではない
+const NON_SYNC_SAMPLE_FLAGS: u32 = 0x0101_0000;
+
+/// 通常の MP4 の moov ボックスから、初期化セグメント（ftyp + moov）を組み立てる
+///
+/// サンプルテーブル（ctts を含む）は空にし、各トラックの trex を含む mvex ボックスを moov に追加する
+pub fn build_init_segment(ftyp_bytes: &[u8], moov_box: &MoovBox) -> Result<Vec<u8>, String> {
+    let mut moov_box = moov_box.clone();
+    moov_box.mvhd_box.duration = 0;
+
+    let mut mvex_payload = Vec::new();
+    for trak_box in &mut moov_box.trak_boxes {
+        trak_box.tkhd_box.duration = 0;
+        trak_box.mdia_box.mdhd_box.duration = 0;
+
+        let stbl_box = &mut trak_box.mdia_box.minf_box.stbl_box;
+        stbl_box.stts_box.entries.clear();
+        stbl_box.stsc_box.entries.clear();
+        stbl_box.stsz_box = StszBox::Variable {
+            entry_sizes: Vec::new(),
+        };
+        stbl_box.stco_or_co64_box = Either::A(StcoBox {
+            chunk_offsets: Vec::new(),
+        });
+        stbl_box.stss_box = None;
+        stbl_box
+            .unknown_boxes
+            .retain(|b| b.box_type != BoxType::Normal(*b"ctts"));
+
+        // trex: デフォルト値はすべて trun で上書きするため、サンプルエントリー以外は 0 にする
+        let mut trex_payload = Vec::new();
+        trex_payload.extend_from_slice(&trak_box.tkhd_box.track_id.to_be_bytes());
+        trex_payload.extend_from_slice(&1u32.to_be_bytes());
+        trex_payload.extend_from_slice(&[0; 12]);
+        mvex_payload.extend_from_slice(&full_box(b"trex", 0, 0, &trex_payload));
+    }
+    moov_box.unknown_boxes.push(UnknownBox {
+        box_type: BoxType::Normal(*b"mvex"),
+        box_size: BoxSize::U32(8 + mvex_payload.len() as u32),
+        payload: mvex_payload,
+    });
+
+    let mut segment = ftyp_bytes.to_vec();
+    segment.extend_from_slice(&moov_box.encode_to_vec().map_err(|e| {
+        tr!(
+            "moov ボックスのエンコードに失敗しました: {}",
+            "Failed to encode the moov box: {}",
+            e
+        )
+    })?);
+    Ok(segment)
+}
+
+/// 通常の MP4 の moov ボックスとファイルの内容から、全サンプルを含むメディアセグメント（styp + moof + mdat）を組み立てる
+///
+/// `data` はチャンクオフセットが指すファイル全体の内容。mdat 内のサンプルはトラックごとにまとめて並べる
+pub fn build_media_segment(moov_box: &MoovBox, data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut tracks = Vec::new();
+    let mut mdat_payload = Vec::new();
+    for trak_box in &moov_box.trak_boxes {
+        let sample_table =
+            SampleTableAccessor::new(&trak_box.mdia_box.minf_box.stbl_box).map_err(|e| {
+                tr!(
+                    "サンプルテーブルの解析に失敗しました: {}",
+                    "Failed to parse the sample table: {}",
+                    e
+                )
+            })?;
+        let ctts_entries =
+            parse_ctts_entries(&trak_box.mdia_box.minf_box.stbl_box).unwrap_or_default();
+        let mut composition_offsets = composition_offsets(&ctts_entries);
+        let mut samples = Vec::new();
+        for sample in sample_table.samples() {
+            let sample_data = usize::try_from(sample.data_offset())
+                .ok()
+                .and_then(|start| data.get(start..start + sample.data_size() as usize))
+                .ok_or_else(|| {
+                    tr!(
+                        "サンプルデータの位置がファイルの範囲外です",
+                        "The sample data position is outside the file"
+                    )
+                })?;
+            mdat_payload.extend_from_slice(sample_data);
+            let flags = if sample.is_sync_sample() {
+                SYNC_SAMPLE_FLAGS
+            } else {
+                NON_SYNC_SAMPLE_FLAGS
+            };
+            let composition_offset = composition_offsets.next().unwrap_or(0);
+            samples.push(TrunSample {
+                duration: sample.duration(),
+                size: sample.data_size(),
+                flags,
+                composition_offset: i32::try_from(composition_offset).map_err(|_| {
+                    tr!(
+                        "コンポジションオフセット {} が大きすぎます",
+                        "Composition offset {} is too large",
+                        composition_offset
+                    )
+                })?,
+            });
+        }
+        let base_decode_time = sample_table
+            .samples()
+            .next()
+            .map_or(0, |sample| sample.timestamp());
+        tracks.push(TrackFragment {
+            track_id: trak_box.tkhd_box.track_id,
+            base_decode_time,
+            samples,
+        });
+    }
+
+    // 4 GiB を超える場合は 64 ビットのサイズを使う
+    let mdat_size = 8 + mdat_payload.len() as u64;
+    let mdat_header = match u32::try_from(mdat_size) {
+        Ok(mdat_size) => [&mdat_size.to_be_bytes()[..], b"mdat"].concat(),
+        Err(_) => [
+            &1u32.to_be_bytes()[..],
+            b"mdat",
+            &(mdat_size + 8).to_be_bytes(),
+        ]
+        .concat(),
+    };
+
+    // trun のデータオフセットは moof の先頭からの位置なので、moof のサイズを先に求める
+    let moof_size = build_moof(&tracks, 0)?.len();
+    let moof = build_moof(&tracks, moof_size + mdat_header.len())?;
+
+    let mut segment = plain_box(b"styp", &[b"msdh", &[0; 4][..], b"msdh", b"msix"].concat());
+    segment.extend_from_slice(&moof);
+    segment.extend_from_slice(&mdat_header);
+    segment.extend_from_slice(&mdat_payload);
+    Ok(segment)
+}
+
+/// moof 内の一つのトラックのフラグメント（traf）の内容
+struct TrackFragment {
+    track_id: u32,
+    base_decode_time: u64,
+    samples: Vec<TrunSample>,
+}
+
+/// trun に記録するサンプルの情報
+struct TrunSample {
+    duration: u32,
+    size: u32,
+    flags: u32,
+    composition_offset: i32,
+}
+
+/// moof ボックスを組み立てる
+///
+/// `mdat_payload_offset` は moof の先頭から mdat のペイロードまでの距離
+fn build_moof(tracks: &[TrackFragment], mdat_payload_offset: usize) -> Result<Vec<u8>, String> {
+    // mfhd: シーケンス番号は 1 から始まる
+    let mut moof_payload = full_box(b"mfhd", 0, 0, &1u32.to_be_bytes());
+
+    let mut data_offset = mdat_payload_offset;
+    for track in tracks {
+        // tfhd: default-base-is-moof
+        let tfhd = full_box(b"tfhd", 0, 0x02_0000, &track.track_id.to_be_bytes());
+        let tfdt = full_box(b"tfdt", 1, 0, &track.base_decode_time.to_be_bytes());
+
+        // trun: data-offset / sample-duration / sample-size / sample-flags
+        // （コンポジションオフセットがある場合は、符号付きで扱えるバージョン 1 で sample-composition-time-offset も含める）
+        let has_composition_offsets = track.samples.iter().any(|s| s.composition_offset != 0);
+        let mut trun_payload = Vec::new();
+        trun_payload.extend_from_slice(&(track.samples.len() as u32).to_be_bytes());
+        trun_payload.extend_from_slice(
+            &i32::try_from(data_offset)
+                .map_err(|_| {
+                    tr!(
+                        "メディアセグメントのサイズが大きすぎます",
+                        "The media segment is too large"
+                    )
+                })?
+                .to_be_bytes(),
+        );
+        for sample in &track.samples {
+            trun_payload.extend_from_slice(&sample.duration.to_be_bytes());
+            trun_payload.extend_from_slice(&sample.size.to_be_bytes());
+            trun_payload.extend_from_slice(&sample.flags.to_be_bytes());
+            if has_composition_offsets {
+                trun_payload.extend_from_slice(&sample.composition_offset.to_be_bytes());
+            }
+            data_offset += sample.size as usize;
+        }
+        let trun = if has_composition_offsets {
+            full_box(b"trun", 1, 0x00_0F01, &trun_payload)
+        } else {
+            full_box(b"trun", 0, 0x00_0701, &trun_payload)
+        };
+
+        moof_payload.extend_from_slice(&plain_box(b"traf", &[tfhd, tfdt, trun].concat()));
+    }
+    Ok(plain_box(b"moof", &moof_payload))
+}
+
+/// moov ボックスが mvex ボックスを含む（フラグメント MP4 である）かどうか
+pub fn is_fragmented(moov_box: &MoovBox) -> bool {
+    moov_box
+        .unknown_boxes
+        .iter()
+        .any(|b| b.box_type == BoxType::Normal(*b"mvex"))
+}
+
+/// フラグメント MP4 の moof ボックスに含まれるサンプルを、moov ボックスのサンプルテーブルに書き戻す
+///
+/// 各 traf の trun から読み取ったサンプルを 1 サンプル 1 チャンクとして stbl に設定し、mvex ボックスを取り除く。
+/// これにより、通常の MP4 と同じく `SampleTableAccessor` でサンプルを辿れるようになる。
+/// チャンクオフセットはファイル先頭からの位置なので、サンプルデータは元のファイルから読み込むこと
+pub fn defragment_moov_box<R: Read + Seek>(
+    reader: &mut R,
+    moov_box: &mut MoovBox,
+    root_boxes: &[RootBoxLocation],
+    limits: &ResourceLimits,
+) -> Result<(), String> {
+    let mut defaults = Vec::new();
+    for mvex_box in moov_box
+        .unknown_boxes
+        .iter()
+        .filter(|b| b.box_type == BoxType::Normal(*b"mvex"))
+    {
+        for child in child_boxes(&mvex_box.payload) {
+            let (box_type, payload) = child?;
+            if &box_type == b"trex" {
+                defaults.push(parse_trex(payload)?);
+            }
+        }
+    }
+
+    let mut tracks: Vec<(u32, FragmentedTrack)> = moov_box
+        .trak_boxes
+        .iter()
+        .map(|trak_box| (trak_box.tkhd_box.track_id, FragmentedTrack::default()))
+        .collect();
+    for location in root_boxes
+        .iter()
+        .filter(|b| b.box_type == BoxType::Normal(*b"moof"))
+    {
+        // ボックスヘッダーのサイズをそのまま確保せず、実際に読み込めた分だけをバッファに入れる
+        let payload_size = location.box_size - location.header_size;
+        let mut payload = Vec::new();
+        reader
+            .seek(SeekFrom::Start(location.payload_offset()))
+            .map_err(|e| {
+                tr!(
+                    "ファイルのシークに失敗しました: {}",
+                    "Failed to seek the file: {}",
+                    e
+                )
+            })?;
+        reader
+            .by_ref()
+            .take(payload_size)
+            .read_to_end(&mut payload)
+            .map_err(|e| {
+                tr!(
+                    "ファイルの読み込みに失敗しました: {}",
+                    "Failed to read the file: {}",
+                    e
+                )
+            })?;
+        if payload.len() as u64 != payload_size {
+            return Err(tr!(
+                "オフセット 0x{:X} の moof ボックスのデータが途中で切れています",
+                "The moof box data at offset 0x{:X} is truncated",
+                location.offset
+            ));
+        }
+        read_moof(&payload, location.offset, &defaults, limits, &mut tracks).map_err(|e| {
+            tr!(
+                "オフセット 0x{:X} の moof ボックス: {}",
+                "moof box at offset 0x{:X}: {}",
+                location.offset,
+                e
+            )
+        })?;
+    }
+
+    let movie_timescale = moov_box.mvhd_box.timescale.get() as u128;
+    let mut movie_duration = 0;
+    for (trak_box, (_, track)) in moov_box.trak_boxes.iter_mut().zip(tracks) {
+        let samples = track.samples;
+        let media_duration: u64 = samples.iter().map(|s| s.duration as u64).sum();
+        let media_timescale = trak_box.mdia_box.mdhd_box.timescale.get() as u128;
+        let duration = (media_duration as u128 * movie_timescale / media_timescale) as u64;
+        trak_box.mdia_box.mdhd_box.duration = media_duration;
+        trak_box.tkhd_box.duration = duration;
+        movie_duration = movie_duration.max(duration);
+
+        let stbl_box = &mut trak_box.mdia_box.minf_box.stbl_box;
+        stbl_box.stts_box = SttsBox::from_sample_deltas(samples.iter().map(|s| s.duration));
+        stbl_box.stsc_box = StscBox {
+            entries: samples
+                .iter()
+                .enumerate()
+                .filter(|(i, s)| *i == 0 || samples[i - 1].description_index != s.description_index)
+                .map(|(i, s)| StscEntry {
+                    first_chunk: NonZeroU32::MIN.saturating_add(i as u32),
+                    sample_per_chunk: 1,
+                    sample_description_index: s.description_index,
+                })
+                .collect(),
+        };
+        stbl_box.stsz_box = StszBox::Variable {
+            entry_sizes: samples.iter().map(|s| s.size).collect(),
+        };
+        stbl_box.stco_or_co64_box = Either::B(Co64Box {
+            chunk_offsets: samples.iter().map(|s| s.offset).collect(),
+        });
+        stbl_box.stss_box = (!samples.iter().all(|s| s.is_sync)).then(|| StssBox {
+            sample_numbers: samples
+                .iter()
+                .enumerate()
+                .filter(|(_, s)| s.is_sync)
+                .map(|(i, _)| NonZeroU32::MIN.saturating_add(i as u32))
+                .collect(),
+        });
+        stbl_box
+            .unknown_boxes
+            .retain(|b| b.box_type != BoxType::Normal(*b"ctts"));
+        let composition_offsets: Vec<i64> = samples
+            .iter()
+            .map(|s| s.composition_offset as i64)
+            .collect();
+        stbl_box
+            .unknown_boxes
+            .extend(build_ctts_box(&composition_offsets)?);
+    }
+    moov_box.mvhd_box.duration = movie_duration;
+    moov_box
+        .unknown_boxes
+        .retain(|b| b.box_type != BoxType::Normal(*b"mvex"));
+    Ok(())
+}
+
+/// trex ボックスで指定されるトラックごとのサンプルのデフォルト値
+#[derive(Debug, Clone, Copy, Default)]
+struct SampleDefaults {
+    track_id: u32,
+    description_index: u32,
+    duration: u32,
+    size: u32,
+    flags: u32,
+}
+
+/// moof ボックスから読み取ったトラックのサンプル
+#[derive(Debug, Default)]
+struct FragmentedTrack {
+    samples: Vec<FragmentSample>,
+    /// 次のサンプルのデコード時刻
+    next_decode_time: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FragmentSample {
+    duration: u32,
+    size: u32,
+    /// ファイル先頭からのサンプルデータの位置
+    offset: u64,
+    is_sync: bool,
+    composition_offset: i32,
+    description_index: NonZeroU32,
+}
+
+/// sample_flags の sample_is_non_sync_sample ビット
+const SAMPLE_IS_NON_SYNC_SAMPLE: u32 = 0x0001_0000;
+
+fn parse_trex(payload: &[u8]) -> Result<SampleDefaults, String> {
+    let mut reader = PayloadReader::new(payload, "trex");
+    reader.skip(4)?;
+    Ok(SampleDefaults {
+        track_id: reader.u32()?,
+        description_index: reader.u32()?,
+        duration: reader.u32()?,
+        size: reader.u32()?,
+        flags: reader.u32()?,
+    })
+}
+
+/// moof ボックスのペイロードを解析して、各トラックのサンプルを追加する
+///
+/// `moof_offset` はファイル先頭からの moof ボックスの位置。
+/// 読み取ったサンプル数の合計が `limits` の上限を超える場合はエラーになる
+fn read_moof(
+    payload: &[u8],
+    moof_offset: u64,
+    trex_defaults: &[SampleDefaults],
+    limits: &ResourceLimits,
+    tracks: &mut [(u32, FragmentedTrack)],
+) -> Result<(), String> {
+    let mut total_sample_count: u64 = tracks
+        .iter()
+        .map(|(_, track)| track.samples.len() as u64)
+        .sum();
+    // tfhd にベースデータオフセットの指定がない場合、2 つ目以降の traf は直前の traf のデータの末尾から始まる
+    let mut previous_data_end = moof_offset;
+    for child in child_boxes(payload) {
+        let (box_type, traf_payload) = child?;
+        if &box_type != b"traf" {
+            continue;
+        }
+        let traf_boxes = child_boxes(traf_payload).collect::<Result<Vec<_>, _>>()?;
+        let tfhd_payload = traf_boxes
+            .iter()
+            .find(|(box_type, _)| box_type == b"tfhd")
+            .map(|&(_, payload)| payload)
+            .ok_or_else(|| {
+                tr!(
+                    "traf ボックスに tfhd ボックスがありません",
+                    "The traf box has no tfhd box"
+                )
+            })?;
+
+        let mut tfhd = PayloadReader::new(tfhd_payload, "tfhd");
+        let tfhd_flags = tfhd.u32()? & 0xFF_FFFF;
+        let track_id = tfhd.u32()?;
+        let mut defaults = trex_defaults
+            .iter()
+            .find(|d| d.track_id == track_id)
+            .copied()
+            // trex ボックスがない場合は、最初のサンプルエントリーを使うものとみなす
+            .unwrap_or(SampleDefaults {
+                description_index: 1,
+                ..SampleDefaults::default()
+            });
+        let base_data_offset = if tfhd_flags & 0x01 != 0 {
+            tfhd.u64()?
+        } else if tfhd_flags & 0x02_0000 != 0 {
+            moof_offset
+        } else {
+            previous_data_end
+        };
+        if tfhd_flags & 0x02 != 0 {
+            defaults.description_index = tfhd.u32()?;
+        }
+        if tfhd_flags & 0x08 != 0 {
+            defaults.duration = tfhd.u32()?;
+        }
+        if tfhd_flags & 0x10 != 0 {
+            defaults.size = tfhd.u32()?;
+        }
+        if tfhd_flags & 0x20 != 0 {
+            defaults.flags = tfhd.u32()?;
+        }
+        let description_index = NonZeroU32::new(defaults.description_index).ok_or_else(|| {
+            tr!(
+                "サンプルエントリーのインデックスが 0 です",
+                "The sample description index is 0"
+            )
+        })?;
+
+        let track = &mut tracks
+            .iter_mut()
+            .find(|(id, _)| *id == track_id)
+            .ok_or_else(|| {
+                tr!(
+                    "トラック ID {} の trak ボックスがありません",
+                    "No trak box for track ID {}",
+                    track_id
+                )
+            })?
+            .1;
+
+        let mut data_offset = base_data_offset;
+        for &(box_type, payload) in &traf_boxes {
+            match &box_type {
+                b"tfdt" => {
+                    let mut tfdt = PayloadReader::new(payload, "tfdt");
+                    let version = tfdt.u32()? >> 24;
+                    let decode_time = if version == 1 {
+                        tfdt.u64()?
+                    } else {
+                        tfdt.u32()? as u64
+                    };
+                    // 前のフラグメントとの間に隙間がある場合は、直前のサンプルの長さを延ばして時刻を合わせる
+                    if let Some(last) = track.samples.last_mut()
+                        && decode_time > track.next_decode_time
+                    {
+                        let gap =
+                            u32::try_from(decode_time - track.next_decode_time).map_err(|_| {
+                                tr!(
+                                    "フラグメント間の隙間が大きすぎます",
+                                    "The gap between fragments is too large"
+                                )
+                            })?;
+                        last.duration = last.duration.saturating_add(gap);
+                    }
+                    track.next_decode_time = decode_time;
+                }
+                b"trun" => {
+                    let mut trun = PayloadReader::new(payload, "trun");
+                    let version_and_flags = trun.u32()?;
+                    let version = version_and_flags >> 24;
+                    let trun_flags = version_and_flags & 0xFF_FFFF;
+                    let sample_count = trun.u32()?;
+                    if trun_flags & 0x01 != 0 {
+                        data_offset = base_data_offset
+                            .checked_add_signed(trun.u32()? as i32 as i64)
+                            .ok_or_else(|| {
+                                tr!(
+                                    "trun のデータオフセットが不正です",
+                                    "Invalid trun data offset"
+                                )
+                            })?;
+                    }
+                    let first_sample_flags =
+                        (trun_flags & 0x04 != 0).then(|| trun.u32()).transpose()?;
+                    // サンプルごとのフィールドがある場合、サンプル数は残りのペイロードに収まる数まで
+                    let sample_fields_size = [0x100, 0x200, 0x400, 0x800]
+                        .iter()
+                        .filter(|&&flag| trun_flags & flag != 0)
+                        .count()
+                        * 4;
+                    if sample_fields_size > 0
+                        && sample_count as usize > trun.remaining() / sample_fields_size
+                    {
+                        return Err(tr!(
+                            "trun ボックスのサンプル数 {} がボックスのサイズに収まりません",
+                            "The trun box sample count {} does not fit in the box size",
+                            sample_count
+                        ));
+                    }
+                    total_sample_count += sample_count as u64;
+                    limits.check_sample_count(total_sample_count)?;
+                    for i in 0..sample_count {
+                        let duration = if trun_flags & 0x100 != 0 {
+                            trun.u32()?
+                        } else {
+                            defaults.duration
+                        };
+                        let size = if trun_flags & 0x200 != 0 {
+                            trun.u32()?
+                        } else {
+                            defaults.size
+                        };
+                        let flags = if trun_flags & 0x400 != 0 {
+                            trun.u32()?
+                        } else if i == 0
+                            && let Some(first_sample_flags) = first_sample_flags
+                        {
+                            first_sample_flags
+                        } else {
+                            defaults.flags
+                        };
+                        let composition_offset = if trun_flags & 0x800 != 0 {
+                            let offset = trun.u32()?;
+                            if version == 0 {
+                                i32::try_from(offset).map_err(|_| {
+                                    tr!(
+                                        "コンポジションオフセットが大きすぎます",
+                                        "The composition offset is too large"
+                                    )
+                                })?
+                            } else {
+                                offset as i32
+                            }
+                        } else {
+                            0
+                        };
+                        track.samples.push(FragmentSample {
+                            duration,
+                            size,
+                            offset: data_offset,
+                            is_sync: flags & SAMPLE_IS_NON_SYNC_SAMPLE == 0,
+                            composition_offset,
+                            description_index,
+                        });
+                        track.next_decode_time = track
+                            .next_decode_time
+                            .checked_add(duration as u64)
+                            .ok_or_else(|| {
+                                tr!(
+                                    "サンプルのデコード時刻が大きすぎます",
+                                    "The sample decode time is too large"
+                                )
+                            })?;
+                        data_offset = data_offset.checked_add(size as u64).ok_or_else(|| {
+                            tr!(
+                                "サンプルデータの位置が大きすぎます",
+                                "The sample data position is too large"
+                            )
+                        })?;
+                    }
+                }
+                _ => {}
+            }
+        }
+        previous_data_end = data_offset;
+    }
+    Ok(())
+}
+
+/// ボックスのペイロードをビッグエンディアンで順に読み込む
+struct PayloadReader<'a> {
+    payload: &'a [u8],
+    box_name: &'static str,
+}
+
+impl<'a> PayloadReader<'a> {
+    fn new(payload: &'a [u8], box_name: &'static str) -> Self {
+        Self { payload, box_name }
+    }
+
+    fn bytes(&mut self, size: usize) -> Result<&'a [u8], String> {
+        if self.payload.len() < size {
+            return Err(tr!(
+                "{} ボックスのデータが途中で切れています",
+                "The {} box data is truncated",
+                self.box_name
+            ));
+        }
+        let (bytes, rest) = self.payload.split_at(size);
+        self.payload = rest;
+        Ok(bytes)
+    }
+
+    fn remaining(&self) -> usize {
+        self.payload.len()
+    }
+
+    fn skip(&mut self, size: usize) -> Result<(), String> {
+        self.bytes(size).map(|_| ())
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_be_bytes(
+            self.bytes(4)?.try_into().expect("4 bytes"),
+        ))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_be_bytes(
+            self.bytes(8)?.try_into().expect("8 bytes"),
+        ))
+    }
+}
+
+fn plain_box(box_type: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(8 + payload.len());
+    bytes.extend_from_slice(&(8 + payload.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(box_type);
+    bytes.extend_from_slice(payload);
+    bytes
+}
+
+fn full_box(box_type: &[u8; 4], version: u8, flags: u32, payload: &[u8]) -> Vec<u8> {
+    let mut full_payload = ((version as u32) << 24 | flags).to_be_bytes().to_vec();
+    full_payload.extend_from_slice(payload);
+    plain_box(box_type, &full_payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use shiguredo_mp4::TrackKind;
+
+    use super::*;
+    use crate::test_util::Mp4Builder;
+
+    const LIMITS: ResourceLimits = ResourceLimits {
+        max_tracks: 10,
+        max_samples: 1000,
+    };
+
+    #[test]
+    fn test_build_media_segment() {
+        let (data, moov_box) = Mp4Builder::new()
+            .sample(TrackKind::Video, 30, 1, true, b"video")
+            .sample(TrackKind::Audio, 30, 1, true, b"audio")
+            .sample(TrackKind::Video, 30, 1, false, b"delta")
+            .build_with_moov_box();
+
+        let segment = build_media_segment(&moov_box, &data).unwrap();
+        let moof_offset = 24;
+        assert_eq!(&segment[4..8], b"styp");
+        assert_eq!(&segment[moof_offset + 4..moof_offset + 8], b"moof");
+
+        // mdat 内のサンプルはトラックごとにまとめて並ぶ
+        let first_track_is_video =
+            moov_box.trak_boxes[0].mdia_box.hdlr_box.handler_type == *b"vide";
+        if first_track_is_video {
+            assert!(segment.ends_with(b"videodeltaaudio"));
+        } else {
+            assert!(segment.ends_with(b"audiovideodelta"));
+        }
+        let mdat_payload_offset = segment.len() - 15;
+        assert_eq!(
+            &segment[mdat_payload_offset - 4..mdat_payload_offset],
+            b"mdat"
+        );
+
+        // 最初の trun のデータオフセットは moof の先頭から mdat のペイロードまでの距離
+        let trun_offset = segment.windows(4).position(|w| w == b"trun").expect("trun") - 4;
+        let data_offset = i32::from_be_bytes(segment[trun_offset + 16..][..4].try_into().unwrap());
+        assert_eq!(moof_offset + data_offset as usize, mdat_payload_offset);
+
+        let init_segment = build_init_segment(&data[..0], &moov_box).unwrap();
+        assert!(init_segment.windows(4).any(|w| w == b"mvex"));
+
+        // 初期化セグメントとメディアセグメントをつなげたファイルから、元のサンプルを読み戻せる
+        let fragmented = [init_segment, segment].concat();
+        let mut reader = std::io::Cursor::new(&fragmented);
+        let mut defragmented = crate::mp4::read_moov_box(&mut reader).unwrap();
+        assert!(is_fragmented(&defragmented));
+        let root_boxes = crate::mp4::scan_root_boxes(&mut reader).unwrap();
+        defragment_moov_box(&mut reader, &mut defragmented, &root_boxes, &LIMITS).unwrap();
+        assert!(!is_fragmented(&defragmented));
+        for (original, defragmented) in moov_box.trak_boxes.iter().zip(&defragmented.trak_boxes) {
+            let original = SampleTableAccessor::new(&original.mdia_box.minf_box.stbl_box).unwrap();
+            let defragmented =
+                SampleTableAccessor::new(&defragmented.mdia_box.minf_box.stbl_box).unwrap();
+            assert_eq!(original.sample_count(), defragmented.sample_count());
+            for (a, b) in original.samples().zip(defragmented.samples()) {
+                assert_eq!(a.timestamp(), b.timestamp());
+                assert_eq!(a.is_sync_sample(), b.is_sync_sample());
+                let sample_data = |data: &[u8], offset: u64, size: u32| {
+                    data[offset as usize..][..size as usize].to_vec()
+                };
+                assert_eq!(
+                    sample_data(&data, a.data_offset(), a.data_size()),
+                    sample_data(&fragmented, b.data_offset(), b.data_size())
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_moof_with_defaults() {
+        // tfhd・trex のデフォルト値を使い、ベースデータオフセットの指定がない 2 つの traf
+        let trex_defaults = [SampleDefaults {
+            track_id: 2,
+            description_index: 1,
+            duration: 10,
+            size: 0,
+            flags: SAMPLE_IS_NON_SYNC_SAMPLE,
+        }];
+        let tfhd = |track_id: u32, flags: u32, defaults: &[u32]| {
+            let mut payload = track_id.to_be_bytes().to_vec();
+            for value in defaults {
+                payload.extend_from_slice(&value.to_be_bytes());
+            }
+            full_box(b"tfhd", 0, flags, &payload)
+        };
+        let first_traf = [
+            tfhd(1, 0x08 | 0x10, &[20, 3]),
+            full_box(b"tfdt", 0, 0, &100u32.to_be_bytes()),
+            full_box(b"trun", 0, 0x01, &[2u32, 16].map(u32::to_be_bytes).concat()),
+        ]
+        .concat();
+        let second_traf = [
+            tfhd(2, 0x10, &[4]),
+            full_box(b"trun", 0, 0x04, &[1u32, 0].map(u32::to_be_bytes).concat()),
+        ]
+        .concat();
+        let moof_payload = [
+            plain_box(b"traf", &first_traf),
+            plain_box(b"traf", &second_traf),
+        ]
+        .concat();
+
+        let mut tracks = vec![
+            (1, FragmentedTrack::default()),
+            (2, FragmentedTrack::default()),
+        ];
+        read_moof(&moof_payload, 1000, &trex_defaults, &LIMITS, &mut tracks).unwrap();
+
+        let first = &tracks[0].1;
+        assert_eq!(first.next_decode_time, 140);
+        assert_eq!(
+            first.samples.iter().map(|s| s.offset).collect::<Vec<_>>(),
+            [1016, 1019]
+        );
+        assert!(first.samples.iter().all(|s| s.is_sync && s.size == 3));
+
+        // 2 つ目の traf のデータは 1 つ目の traf のデータの直後から始まる
+        let second = &tracks[1].1;
+        assert_eq!(
+            second.samples,
+            [FragmentSample {
+                duration: 10,
+                size: 4,
+                offset: 1022,
+                is_sync: true,
+                composition_offset: 0,
+                description_index: NonZeroU32::MIN,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_read_moof_rejects_excessive_sample_count() {
+        let tfhd = full_box(
+            b"tfhd",
+            0,
+            0x08 | 0x10,
+            &[1u32, 10, 3].map(u32::to_be_bytes).concat(),
+        );
+
+        // サンプルごとのサイズが 1 つ分しかないのに 2 サンプルを宣言している
+        let trun = full_box(b"trun", 0, 0x200, &[2u32, 3].map(u32::to_be_bytes).concat());
+        let moof_payload = plain_box(b"traf", &[tfhd.clone(), trun].concat());
+        let mut tracks = vec![(1, FragmentedTrack::default())];
+        let error = read_moof(&moof_payload, 0, &[], &LIMITS, &mut tracks).unwrap_err();
+        assert!(error.contains("収まりません"), "{error}");
+
+        // デフォルト値を使う trun は数バイトで任意のサンプル数を宣言できるため、上限で止める
+        let trun = full_box(b"trun", 0, 0, &600u32.to_be_bytes());
+        let moof_payload = plain_box(b"traf", &[tfhd, trun].concat());
+        let mut tracks = vec![(1, FragmentedTrack::default())];
+        read_moof(&moof_payload, 0, &[], &LIMITS, &mut tracks).unwrap();
+        let error = read_moof(&moof_payload, 0, &[], &LIMITS, &mut tracks).unwrap_err();
+        assert!(error.contains("--max-samples"), "{error}");
+        assert_eq!(tracks[0].1.samples.len(), 600);
+    }
+
+    #[test]
+    fn test_read_moof_rejects_overflowing_data_offset() {
+        // ベースデータオフセットがファイル位置の上限付近にあり、サンプルデータの末尾が u64 を超える
+        let mut tfhd_payload = 1u32.to_be_bytes().to_vec();
+        tfhd_payload.extend_from_slice(&(u64::MAX - 1).to_be_bytes());
+        tfhd_payload.extend_from_slice(&[10u32, 3].map(u32::to_be_bytes).concat());
+        let tfhd = full_box(b"tfhd", 0, 0x01 | 0x08 | 0x10, &tfhd_payload);
+        let trun = full_box(b"trun", 0, 0, &1u32.to_be_bytes());
+        let moof_payload = plain_box(b"traf", &[tfhd, trun].concat());
+        let mut tracks = vec![(1, FragmentedTrack::default())];
+        let error = read_moof(&moof_payload, 0, &[], &LIMITS, &mut tracks).unwrap_err();
+        assert!(error.contains("サンプルデータの位置"), "{error}");
+    }
+}
@@ -0,0 +1,91 @@
+//! 表示言語の切り替え
+//!
+//! メッセージは呼び出し側で日本語と英語の組として [`tr!`](crate::tr) に渡し、
+//! 実行時に選択された言語のものを使う。
+
+use std::sync::OnceLock;
+
+/// 表示言語
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Lang {
+    #[default]
+    Ja,
+    En,
+}
+
+static LANG: OnceLock<Lang> = OnceLock::new();
+
+impl Lang {
+    /// `ja` / `en` の指定を解釈する
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "ja" => Ok(Self::Ja),
+            "en" => Ok(Self::En),
+            // 表示言語を決める前なので、ロケールの環境変数から決まる言語で表示する
+            _ => Err(match Self::from_env() {
+                Self::Ja => format!(
+                    "不明な言語です: {}（ja / en のいずれかを指定してください）",
+                    value
+                ),
+                Self::En => format!("Unknown language: {} (specify ja or en)", value),
+            }),
+        }
+    }
+
+    /// ロケールの環境変数（LC_ALL、LC_MESSAGES、LANG の順に参照）から表示言語を決める
+    ///
+    /// 英語のロケール（「en_US.UTF-8」など）の場合のみ英語にし、それ以外は日本語にする
+    pub fn from_env() -> Self {
+        let locale = ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .find_map(|name| std::env::var(name).ok().filter(|value| !value.is_empty()));
+        Self::from_locale(locale.as_deref())
+    }
+
+    fn from_locale(locale: Option<&str>) -> Self {
+        match locale {
+            Some(locale) if locale.starts_with("en") => Self::En,
+            _ => Self::Ja,
+        }
+    }
+}
+
+/// 表示言語を設定する（2 回目以降の呼び出しは無視される）
+pub fn set_lang(lang: Lang) {
+    let _ = LANG.set(lang);
+}
+
+/// 現在の表示言語（未設定の場合は日本語）
+pub fn lang() -> Lang {
+    LANG.get().copied().unwrap_or_default()
+}
+
+/// 日本語と英語のメッセージから、現在の表示言語のものを選んでフォーマットする
+///
+/// ```ignore
+/// println!("{}", tr!("トラック数: {}", "Tracks: {}", tracks.len()));
+/// ```
+#[macro_export]
+macro_rules! tr {
+    ($ja:literal, $en:literal $(, $arg:expr)* $(,)?) => {
+        match $crate::i18n::lang() {
+            $crate::i18n::Lang::Ja => format!($ja $(, $arg)*),
+            $crate::i18n::Lang::En => format!($en $(, $arg)*),
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lang_from_locale() {
+        assert_eq!(Lang::from_locale(Some("en_US.UTF-8")), Lang::En);
+        assert_eq!(Lang::from_locale(Some("ja_JP.UTF-8")), Lang::Ja);
+        assert_eq!(Lang::from_locale(Some("C")), Lang::Ja);
+        assert_eq!(Lang::from_locale(None), Lang::Ja);
+        assert_eq!(Lang::parse("en"), Ok(Lang::En));
+        assert!(Lang::parse("fr").is_err());
+    }
+}
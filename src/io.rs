@@ -2,9 +2,18 @@
 //!
 //! stdin/stdout 対応と TTY 検出を提供する。
 
-use std::fs::File;
-use std::io::{self, IsTerminal, Read, Write};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, IsTerminal, Read, Seek, SeekFrom, Write};
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::tr;
+
+/// シーク可能な Reader
+pub trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
 
 /// 入力ソースの抽象化
 #[derive(Debug)]
@@ -45,6 +54,27 @@ impl InputSource {
         }
     }
 
+    /// 入力からデータを読み込むシーク可能な Reader を取得
+    ///
+    /// stdin はシークできないため、一時ファイルに退避した [`SpillReader`] を返す
+    pub fn seekable_reader(&self) -> io::Result<Box<dyn ReadSeek>> {
+        match self {
+            InputSource::File(path) => Ok(Box::new(File::open(path)?)),
+            InputSource::Stdin => Ok(Box::new(SpillReader::new(io::stdin().lock())?)),
+        }
+    }
+
+    /// [`seekable_reader`](Self::seekable_reader) を最大 `cache_size` バイトの [`CachedReader`] で包んだ Reader を取得
+    ///
+    /// `cache_size` が 0 の場合はキャッシュしない
+    pub fn cached_reader(&self, cache_size: u64) -> io::Result<Box<dyn ReadSeek>> {
+        let reader = self.seekable_reader()?;
+        if cache_size == 0 {
+            return Ok(reader);
+        }
+        Ok(Box::new(CachedReader::new(reader, cache_size)?))
+    }
+
     /// 入力ソースの説明を取得（エラーメッセージ用）
     pub fn description(&self) -> String {
         match self {
@@ -54,6 +84,193 @@ impl InputSource {
     }
 }
 
+/// シーク不可な入力を一時ファイルに退避して、シーク可能にする Reader
+///
+/// 一時ファイルは OS の一時ディレクトリに作成され、ドロップ時に削除される。
+#[derive(Debug)]
+pub struct SpillReader {
+    file: File,
+    path: PathBuf,
+}
+
+impl SpillReader {
+    /// `reader` の内容をすべて一時ファイルに書き出して、先頭から読み込める状態にする
+    pub fn new<R: Read>(mut reader: R) -> io::Result<Self> {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+        let path = std::env::temp_dir().join(format!(
+            "mp4util-{}-{}.tmp",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+
+        // 以降でエラーになった場合もドロップ時に一時ファイルが削除される
+        let mut this = Self { file, path };
+        io::copy(&mut reader, &mut this.file)?;
+        this.file.seek(SeekFrom::Start(0))?;
+        Ok(this)
+    }
+}
+
+impl Read for SpillReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.file.read(buf)
+    }
+}
+
+impl Seek for SpillReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.file.seek(pos)
+    }
+}
+
+impl Drop for SpillReader {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// 読み込んだ範囲をブロック単位で保持し、同じ範囲の再読み込みを省く Reader
+///
+/// 容量を超えた場合は最も長く使われていないブロックから破棄する（LRU）。
+/// moov ボックスや近くのサンプルを何度も参照するランダムアクセス向けで、
+/// 先頭から順に一度だけ読む処理では [`std::io::BufReader`] を使う
+#[derive(Debug)]
+pub struct CachedReader<R> {
+    inner: R,
+    position: u64,
+    /// ブロックの先頭位置 → (最後に使用した時点, データ)
+    blocks: HashMap<u64, (u64, Vec<u8>)>,
+    max_blocks: usize,
+    clock: u64,
+}
+
+impl<R: Read + Seek> CachedReader<R> {
+    /// キャッシュする単位のサイズ
+    pub const BLOCK_SIZE: usize = 64 * 1024;
+
+    /// 最大 `cache_size` バイト（ブロック単位に切り上げ）を保持する Reader を作る
+    pub fn new(mut inner: R, cache_size: u64) -> io::Result<Self> {
+        let position = inner.stream_position()?;
+        Ok(Self {
+            inner,
+            position,
+            blocks: HashMap::new(),
+            max_blocks: cache_size.div_ceil(Self::BLOCK_SIZE as u64).max(1) as usize,
+            clock: 0,
+        })
+    }
+
+    /// `block_start` から始まるブロックを、キャッシュになければ読み込んで返す
+    fn block(&mut self, block_start: u64) -> io::Result<&[u8]> {
+        self.clock += 1;
+        if let Some((last_used, _)) = self.blocks.get_mut(&block_start) {
+            *last_used = self.clock;
+        } else {
+            if self.blocks.len() >= self.max_blocks
+                && let Some(&oldest) = self
+                    .blocks
+                    .iter()
+                    .min_by_key(|(_, (last_used, _))| *last_used)
+                    .map(|(start, _)| start)
+            {
+                self.blocks.remove(&oldest);
+            }
+            self.inner.seek(SeekFrom::Start(block_start))?;
+            let mut data = Vec::with_capacity(Self::BLOCK_SIZE);
+            (&mut self.inner)
+                .take(Self::BLOCK_SIZE as u64)
+                .read_to_end(&mut data)?;
+            self.blocks.insert(block_start, (self.clock, data));
+        }
+        Ok(&self.blocks[&block_start].1)
+    }
+}
+
+impl<R: Read + Seek> Read for CachedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let block_start = self.position - self.position % Self::BLOCK_SIZE as u64;
+        let offset = (self.position - block_start) as usize;
+        let block = self.block(block_start)?;
+        // ブロックが途中で終わっている場合はファイルの末尾
+        let available = block.get(offset..).unwrap_or_default();
+        let size = available.len().min(buf.len());
+        buf[..size].copy_from_slice(&available[..size]);
+        self.position += size as u64;
+        Ok(size)
+    }
+}
+
+impl<R: Read + Seek> Seek for CachedReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(position) => Some(position),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+            SeekFrom::End(_) => Some(self.inner.seek(pos)?),
+        };
+        self.position = position.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.position)
+    }
+}
+
+/// ランダムアクセスする入力のキャッシュサイズのデフォルト値
+pub const DEFAULT_READ_CACHE_SIZE: &str = "1M";
+
+/// 入力の読み込みに使うキャッシュのサイズを指定する `--read-cache` オプション
+pub const READ_CACHE_OPT: noargs::OptSpec = noargs::opt("read-cache")
+    .doc("入力の読み込みに使うキャッシュのサイズ（K/M/G の接尾辞に対応。0 でキャッシュしない）")
+    .ty("BYTES")
+    .default(DEFAULT_READ_CACHE_SIZE);
+
+/// シーク可能な Writer
+pub trait WriteSeek: Write + Seek {}
+
+impl<T: Write + Seek> WriteSeek for T {}
+
+/// 開いた出力先の Writer
+pub enum OutputWriter {
+    /// 書き込み後に先頭側へ戻って書き換えられる出力先（通常のファイル）
+    Seekable(Box<dyn WriteSeek>),
+    /// 先頭から順に書き込むことしかできない出力先（stdout、パイプなど）
+    Sequential(Box<dyn Write>),
+}
+
+/// 出力先の決定に失敗した理由
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputSinkError {
+    /// stdout がターミナルのため、バイナリの書き出しを拒否した
+    ///
+    /// `explicit` は出力先として `-` が明示的に指定されていたかどうか
+    StdoutIsTerminal { explicit: bool },
+}
+
+impl std::fmt::Display for OutputSinkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OutputSinkError::StdoutIsTerminal { explicit: true } => f.write_str(&tr!(
+                "stdout がターミナルです。ファイルまたはパイプにリダイレクトしてください",
+                "stdout is a terminal; redirect it to a file or a pipe"
+            )),
+            OutputSinkError::StdoutIsTerminal { explicit: false } => f.write_str(&tr!(
+                "stdout がターミナルです。-o オプションで出力ファイルを指定するか、パイプにリダイレクトしてください",
+                "stdout is a terminal; specify an output file with -o or redirect it to a pipe"
+            )),
+        }
+    }
+}
+
+impl std::error::Error for OutputSinkError {}
+
 /// 出力先の抽象化
 #[derive(Debug)]
 pub enum OutputSink {
@@ -70,25 +287,19 @@ impl OutputSink {
     /// - `Some(path)` → File
     /// - `None` → Stdout（TTY チェックあり）
     ///
-    /// stdout が TTY の場合はエラーを返す（バイナリ出力の防止）
-    pub fn from_arg(arg: Option<String>, allow_tty: bool) -> Result<Self, String> {
+    /// stdout が TTY の場合は `OutputSinkError::StdoutIsTerminal` を返す（バイナリ出力の防止）
+    pub fn from_arg(arg: Option<String>, allow_tty: bool) -> Result<Self, OutputSinkError> {
         match arg {
             Some(path) if path == "-" => {
                 if !allow_tty && io::stdout().is_terminal() {
-                    return Err(
-                        "stdout がターミナルです。ファイルまたはパイプにリダイレクトしてください"
-                            .into(),
-                    );
+                    return Err(OutputSinkError::StdoutIsTerminal { explicit: true });
                 }
                 Ok(OutputSink::Stdout)
             }
             Some(path) => Ok(OutputSink::File(PathBuf::from(path))),
             None => {
                 if !allow_tty && io::stdout().is_terminal() {
-                    return Err(
-                        "stdout がターミナルです。-o オプションで出力ファイルを指定するか、パイプにリダイレクトしてください"
-                            .into(),
-                    );
+                    return Err(OutputSinkError::StdoutIsTerminal { explicit: false });
                 }
                 Ok(OutputSink::Stdout)
             }
@@ -103,6 +314,27 @@ impl OutputSink {
         }
     }
 
+    /// 出力先を開く
+    ///
+    /// ファイルへの書き込みは `buffer_size` バイトのバッファを介して行う。
+    /// stdout やパイプ・FIFO などシークできない出力先の場合は `Sequential` を返す
+    pub fn open(&self, buffer_size: usize) -> io::Result<OutputWriter> {
+        match self {
+            OutputSink::File(path) => {
+                let mut file = File::create(path)?;
+                if file.stream_position().is_ok() {
+                    Ok(OutputWriter::Seekable(Box::new(BufWriter::with_capacity(
+                        buffer_size,
+                        file,
+                    ))))
+                } else {
+                    Ok(OutputWriter::Sequential(Box::new(file)))
+                }
+            }
+            OutputSink::Stdout => Ok(OutputWriter::Sequential(Box::new(io::stdout().lock()))),
+        }
+    }
+
     /// 出力先の説明を取得（メッセージ用）
     pub fn description(&self) -> String {
         match self {
@@ -117,6 +349,21 @@ impl OutputSink {
     }
 }
 
+/// 「64M」のような接尾辞付きのサイズ指定をバイト数に変換する
+pub fn parse_byte_size(value: &str) -> Result<u64, String> {
+    let (number, multiplier) = match value.char_indices().last() {
+        Some((i, 'K' | 'k')) => (&value[..i], 1 << 10),
+        Some((i, 'M' | 'm')) => (&value[..i], 1 << 20),
+        Some((i, 'G' | 'g')) => (&value[..i], 1 << 30),
+        _ => (value, 1),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| tr!("サイズの指定が不正です: {}", "Invalid size: {}", value))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -133,9 +380,65 @@ mod tests {
         assert!(matches!(source, Some(InputSource::Stdin)));
     }
 
+    #[test]
+    fn test_spill_reader_seek_and_cleanup() {
+        let mut reader = SpillReader::new(&b"0123456789"[..]).unwrap();
+        let path = reader.path.clone();
+        assert!(path.exists());
+
+        let mut buf = [0; 3];
+        reader.seek(SeekFrom::Start(4)).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"456");
+
+        drop(reader);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_cached_reader() {
+        let data: Vec<u8> = (0..200_000u32).map(|i| i as u8).collect();
+        // 2 ブロック分のキャッシュ
+        let block_size = CachedReader::<io::Cursor<Vec<u8>>>::BLOCK_SIZE;
+        let mut reader =
+            CachedReader::new(io::Cursor::new(data.clone()), 2 * block_size as u64).unwrap();
+
+        // ブロックの境界をまたぐ読み込み
+        let mut buf = vec![0; 100];
+        reader
+            .seek(SeekFrom::Start(block_size as u64 - 50))
+            .unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, data[block_size - 50..block_size + 50]);
+        assert_eq!(reader.blocks.len(), 2);
+
+        // 3 ブロック目を読むと、最も長く使われていない先頭のブロックが破棄される
+        reader.seek(SeekFrom::Start(2 * block_size as u64)).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, data[2 * block_size..2 * block_size + 100]);
+        assert_eq!(reader.blocks.len(), 2);
+        assert!(!reader.blocks.contains_key(&0));
+
+        // 末尾
+        let mut rest = Vec::new();
+        reader.seek(SeekFrom::End(-10)).unwrap();
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, data[data.len() - 10..]);
+        assert!(reader.seek(SeekFrom::Current(-1_000_000)).is_err());
+    }
+
     #[test]
     fn test_output_sink_from_arg_with_file() {
         let sink = OutputSink::from_arg(Some("output.mp4".to_string()), false);
         assert!(matches!(sink, Ok(OutputSink::File(_))));
     }
+
+    #[test]
+    fn test_parse_byte_size() {
+        assert_eq!(parse_byte_size("1024"), Ok(1024));
+        assert_eq!(parse_byte_size("64M"), Ok(64 * 1024 * 1024));
+        assert_eq!(parse_byte_size("2k"), Ok(2048));
+        assert!(parse_byte_size("M").is_err());
+        assert!(parse_byte_size("1.5G").is_err());
+    }
 }
@@ -0,0 +1,50 @@
+//! JSON 出力用の補助関数
+//!
+//! 出力する JSON は小さく構造も固定なので、シリアライザーは使わずに組み立てる。
+
+/// 文字列を JSON の文字列リテラル（ダブルクォート付き）に変換する
+pub fn string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+/// 浮動小数点数を JSON の数値に変換する（NaN や無限大は null にする）
+pub fn number(value: f64) -> String {
+    if value.is_finite() {
+        value.to_string()
+    } else {
+        "null".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_string() {
+        assert_eq!(string("out.mp4"), r#""out.mp4""#);
+        assert_eq!(string("a\"b\\c\n\u{1}"), r#""a\"b\\c\n\u0001""#);
+        assert_eq!(string("出力.mp4"), "\"出力.mp4\"");
+    }
+
+    #[test]
+    fn test_number() {
+        assert_eq!(number(1.5), "1.5");
+        assert_eq!(number(2.0), "2");
+        assert_eq!(number(f64::NAN), "null");
+    }
+}
@@ -1,4 +1,17 @@
+pub mod compat;
+pub mod fragment;
+pub mod i18n;
 pub mod io;
+pub mod json;
 pub mod mp4;
 pub mod subcommand_extract;
+pub mod subcommand_index;
 pub mod subcommand_info;
+pub mod subcommand_probe;
+pub mod subcommand_raw;
+pub mod subcommand_repair;
+pub mod subcommand_stats;
+pub mod subcommand_trim;
+pub mod subcommand_validate;
+#[cfg(test)]
+mod test_util;
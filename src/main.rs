@@ -1,12 +1,39 @@
+use mp4util::i18n::{Lang, set_lang};
+use mp4util::tr;
+
 // 共通フラグ
 const HELP_FLAG: noargs::FlagSpec = noargs::HELP_FLAG
     .doc("ヘルプメッセージを表示します ('--help' なら詳細、'-h' なら簡易版を表示)");
 const VERSION_FLAG: noargs::FlagSpec = noargs::VERSION_FLAG.doc("バージョン情報を表示します");
+const LIST_FORMATS_FLAG: noargs::FlagSpec =
+    noargs::flag("list-formats").doc("対応しているコーデックの一覧を表示します");
+const LOGLEVEL_OPT: noargs::OptSpec = noargs::opt("loglevel")
+    .doc("標準エラー出力に表示するログのレベル（off / error / warn / info / debug / trace）")
+    .ty("LEVEL")
+    .default("info");
+const LANG_OPT: noargs::OptSpec = noargs::opt("lang")
+    .doc("メッセージの表示言語（ja / en）。省略時は環境変数 LANG などのロケールが英語なら英語、それ以外は日本語")
+    .ty("LANG");
 
 // サブコマンド
 const INFO_COMMAND: noargs::CmdSpec = noargs::cmd("info").doc("MP4 ファイルの情報を取得します");
 const EXTRACT_COMMAND: noargs::CmdSpec =
     noargs::cmd("extract").doc("MP4 ファイルから指定秒数範囲を抽出します");
+const RAW_COMMAND: noargs::CmdSpec =
+    noargs::cmd("raw").doc("MP4 ファイルのトラックのサンプルデータをそのまま書き出します");
+const STATS_COMMAND: noargs::CmdSpec =
+    noargs::cmd("stats").doc("MP4 ファイルのチャンク構成などの統計情報を表示します");
+const VALIDATE_COMMAND: noargs::CmdSpec =
+    noargs::cmd("validate").doc("MP4 ファイルの構造に問題がないか検証します");
+const INDEX_COMMAND: noargs::CmdSpec =
+    noargs::cmd("index").doc("トラックのサンプルの表示時刻とバイトオフセットを CSV で出力します");
+const TRIM_COMMAND: noargs::CmdSpec = noargs::cmd("trim")
+    .doc("開始・終了位置を時刻で指定して MP4 ファイルを切り出します（extract の簡易版）");
+const PROBE_COMMAND: noargs::CmdSpec = noargs::cmd("probe").doc(
+    "MP4 ファイルの情報を「key=value」形式で 1 行に 1 項目ずつ出力します（シェルでの処理向け）",
+);
+const REPAIR_COMMAND: noargs::CmdSpec = noargs::cmd("repair")
+    .doc("mdat 内のサンプル配置からチャンクオフセットを再計算して MP4 ファイルを修復します");
 
 fn main() -> noargs::Result<()> {
     let mut args = noargs::raw_args();
@@ -16,19 +43,64 @@ fn main() -> noargs::Result<()> {
     // 共通系のフラグ処理
     HELP_FLAG.take_help(&mut args);
 
+    let loglevel: log::LevelFilter = LOGLEVEL_OPT.take(&mut args).then(|o| o.value().parse())?;
+    let lang: Option<Lang> = LANG_OPT
+        .take(&mut args)
+        .present_and_then(|o| Lang::parse(o.value()))?;
+    set_lang(lang.unwrap_or_else(Lang::from_env));
+    init_logger(loglevel);
+
     if VERSION_FLAG.take(&mut args).is_present() {
         println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
         return Ok(());
     }
 
+    if LIST_FORMATS_FLAG.take(&mut args).is_present() {
+        println!("{}", tr!("対応コーデック:", "Supported codecs:"));
+        for (box_type, name) in mp4util::mp4::SUPPORTED_CODECS {
+            println!("  {:<4}  {}", box_type, name);
+        }
+        return Ok(());
+    }
+
     // サブコマンドで分岐する
     if INFO_COMMAND.take(&mut args).is_present() {
         mp4util::subcommand_info::run(args)?;
     } else if EXTRACT_COMMAND.take(&mut args).is_present() {
         mp4util::subcommand_extract::run(args)?;
+    } else if RAW_COMMAND.take(&mut args).is_present() {
+        mp4util::subcommand_raw::run(args)?;
+    } else if STATS_COMMAND.take(&mut args).is_present() {
+        mp4util::subcommand_stats::run(args)?;
+    } else if VALIDATE_COMMAND.take(&mut args).is_present() {
+        mp4util::subcommand_validate::run(args)?;
+    } else if INDEX_COMMAND.take(&mut args).is_present() {
+        mp4util::subcommand_index::run(args)?;
+    } else if TRIM_COMMAND.take(&mut args).is_present() {
+        mp4util::subcommand_trim::run(args)?;
+    } else if PROBE_COMMAND.take(&mut args).is_present() {
+        mp4util::subcommand_probe::run(args)?;
+    } else if REPAIR_COMMAND.take(&mut args).is_present() {
+        mp4util::subcommand_repair::run(args)?;
     } else if let Some(help) = args.finish()? {
         print!("{help}");
     }
 
     Ok(())
 }
+
+/// ステータスや警告を標準エラー出力に表示するロガーを初期化する
+///
+/// 結果の出力（stdout）と混ざらないよう、ログはすべて stderr に書き出す
+fn init_logger(level: log::LevelFilter) {
+    use std::io::Write;
+
+    env_logger::Builder::new()
+        .filter_level(level)
+        .format(|buf, record| match record.level() {
+            log::Level::Error => writeln!(buf, "{}", tr!("エラー: {}", "Error: {}", record.args())),
+            log::Level::Warn => writeln!(buf, "{}", tr!("警告: {}", "Warning: {}", record.args())),
+            _ => writeln!(buf, "{}", record.args()),
+        })
+        .init();
+}
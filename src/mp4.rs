@@ -1,55 +1,174 @@
 use shiguredo_mp4::{
-    Decode, Mp4File,
+    BaseBox, BoxHeader, BoxSize, BoxType, Decode, Either, Mp4File,
     aux::SampleTableAccessor,
-    boxes::{RootBox, SampleEntry, TrakBox},
+    boxes::{
+        AudioSampleEntryFields, AvccBox, FtypBox, HvccBox, MdhdBox, MoovBox, MvhdBox, RootBox,
+        SampleEntry, StblBox, StszBox, SttsBox, TrakBox, UnknownBox,
+    },
 };
-use std::io::Read;
+use std::io::{Read, Seek, SeekFrom};
+use std::num::NonZeroU32;
+
+use crate::tr;
 
 pub struct InputMp4 {
-    tracks: Vec<TrakBox>,
+    /// ストリーミング解析で ftyp ボックスより前に moov ボックスがあった場合などは `None`
+    ftyp: Option<FtypBox>,
+    moov: MoovBox,
 }
 
 impl InputMp4 {
     pub fn parse<R: Read>(mut reader: R) -> Result<Self, String> {
         // Read all data into a buffer
         let mut buffer = Vec::new();
-        reader
-            .read_to_end(&mut buffer)
-            .map_err(|e| format!("ファイルの読み込みに失敗しました: {}", e))?;
+        reader.read_to_end(&mut buffer).map_err(|e| {
+            tr!(
+                "ファイルの読み込みに失敗しました: {}",
+                "Failed to read the file: {}",
+                e
+            )
+        })?;
 
-        // Decode returns (Mp4File, usize) tuple
-        let (mp4_file, _) = match Mp4File::decode(&buffer) {
-            Ok(result) => result,
-            Err(e) => return Err(format!("MP4 ファイルの解析に失敗しました: {}", e)),
+        // ダウンロードが途中で止まったファイルなどは、途中で切れたボックスより前の部分だけを解析する
+        let truncated_at = truncated_box_offset(&buffer);
+        let truncated_error = |offset: usize| {
+            tr!(
+                "moov ボックスを読み込む前にファイルが途中で切れています（オフセット 0x{:X} の {} ボックス）",
+                "The file is truncated before the moov box (offset 0x{:X}, {} box)",
+                offset,
+                truncated_box_name(&buffer[offset..])
+            )
         };
-        let moov_box = mp4_file.boxes.iter().find_map(|box_item| {
+        if truncated_at == Some(0) {
+            return Err(truncated_error(0));
+        }
+        let Mp4File { ftyp_box, boxes } =
+            decode_mp4_file(&buffer[..truncated_at.unwrap_or(buffer.len())])?;
+        let moov_box = boxes.into_iter().find_map(|box_item| {
             if let RootBox::Moov(moov_box) = box_item {
                 Some(moov_box)
             } else {
                 None
             }
         });
-        if moov_box.is_none() {
-            return Err("moov box not found".to_string());
+        let Some(moov_box) = moov_box else {
+            return Err(match truncated_at {
+                Some(offset) => truncated_error(offset),
+                None => "moov box not found".to_string(),
+            });
+        };
+        if let Some(offset) = truncated_at {
+            log::warn!(
+                "{}",
+                tr!(
+                    "ファイルが途中で切れています（オフセット 0x{:X} の {} ボックス）。moov ボックスは読み込めたので、その情報を使います",
+                    "The file is truncated (offset 0x{:X}, {} box); using the moov box, which was read successfully",
+                    offset,
+                    truncated_box_name(&buffer[offset..])
+                )
+            );
         }
-        let moov_box = moov_box.unwrap();
 
-        let mut tracks = Vec::new();
-        for trak in moov_box.trak_boxes.iter() {
-            // トラック情報を取得
-            tracks.push(trak.clone());
-        }
+        Ok(InputMp4 {
+            ftyp: Some(ftyp_box),
+            moov: moov_box,
+        })
+    }
+
+    /// moov ボックスのみをメモリに読み込んで解析する
+    ///
+    /// mdat などのボックスは読み捨てるため、メモリ使用量は moov ボックスのサイズ程度に収まる。
+    /// moov ボックスのサイズが `max_memory` バイトを超える場合はエラーを返す
+    pub fn parse_streaming<R: Read>(mut reader: R, max_memory: u64) -> Result<Self, String> {
+        let (ftyp_box, moov_box) = scan_moov_box(&mut reader, max_memory, |reader, size| {
+            let copied = std::io::copy(&mut reader.by_ref().take(size), &mut std::io::sink())?;
+            if copied < size {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+            Ok(())
+        })?;
+        Ok(InputMp4 {
+            ftyp: ftyp_box,
+            moov: moov_box,
+        })
+    }
+
+    /// ftyp ボックス
+    pub fn ftyp(&self) -> Option<&FtypBox> {
+        self.ftyp.as_ref()
+    }
 
-        Ok(InputMp4 { tracks })
+    /// moov ボックス全体
+    pub fn moov(&self) -> &MoovBox {
+        &self.moov
+    }
+
+    /// mvhd ボックス（ムービー全体のタイムスケールや長さ）
+    pub fn mvhd(&self) -> &MvhdBox {
+        &self.moov.mvhd_box
+    }
+
+    /// 全トラックの trak ボックス
+    pub fn tracks(&self) -> &[TrakBox] {
+        &self.moov.trak_boxes
+    }
+
+    /// トラックのサンプル情報を先頭から順に返すイテレーターを作る
+    ///
+    /// `track_index` は 0 始まりのトラック番号。サンプルデータ自体は読み込まない
+    pub fn samples(
+        &self,
+        track_index: usize,
+    ) -> Result<impl '_ + Iterator<Item = SampleMeta>, String> {
+        let trak = self.tracks().get(track_index).ok_or_else(|| {
+            tr!(
+                "トラック {} が見つかりません（トラック数: {}）",
+                "Track {} not found (number of tracks: {})",
+                track_index + 1,
+                self.tracks().len()
+            )
+        })?;
+        let stbl_box = &trak.mdia_box.minf_box.stbl_box;
+        let sample_table = SampleTableAccessor::new(stbl_box).map_err(|e| {
+            tr!(
+                "サンプルテーブルの解析に失敗しました: {}",
+                "Failed to parse the sample table: {}",
+                e
+            )
+        })?;
+        let mut offsets = composition_offsets(&parse_ctts_entries(stbl_box).unwrap_or_default())
+            .collect::<Vec<_>>()
+            .into_iter();
+
+        Ok((1..=sample_table.sample_count()).filter_map(move |i| {
+            let sample = sample_table.get_sample(NonZeroU32::new(i)?)?;
+            let composition_offset = offsets.next().unwrap_or(0);
+            Some(SampleMeta {
+                index: sample.index(),
+                pts: sample.timestamp() as i64 + composition_offset,
+                dts: sample.timestamp(),
+                duration: sample.duration(),
+                is_sync: sample.is_sync_sample(),
+                data_offset: sample.data_offset(),
+                data_size: sample.data_size(),
+            })
+        }))
     }
 
     /// MP4 ファイルのトラック情報を取得する
     pub fn get_track_infos(&self) -> Option<Vec<TrackInfo>> {
         let mut tracks = Vec::new();
-        for trak in self.tracks.iter() {
+        for trak in self.tracks() {
             // トラック情報を取得
             tracks.push(self.get_track_info(trak));
         }
+
+        // iTunes のギャップレス情報はファイル単位なので、最初のオーディオトラックのものとして扱う
+        if let Some(gapless) = itunes_gapless_info(&self.moov().unknown_boxes)
+            && let Some(track) = tracks.iter_mut().find(|t| t.media_type == "オーディオ")
+        {
+            track.encoder_delay = Some(gapless);
+        }
         Some(tracks)
     }
 
@@ -70,51 +189,2460 @@ impl InputMp4 {
         // サンプルエントリからコーデック情報を取得
         let codec = match trak.mdia_box.minf_box.stbl_box.stsd_box.entries.first() {
             Some(sample_entry) => self.get_codec_name(sample_entry),
-            None => "不明 (サンプルエントリなし)".to_string(),
+            None => tr!("不明 (サンプルエントリなし)", "Unknown (no sample entry)"),
         };
+        let sample_entry_type = trak
+            .mdia_box
+            .minf_box
+            .stbl_box
+            .stsd_box
+            .entries
+            .first()
+            .map(|entry| String::from_utf8_lossy(entry.box_type().as_bytes()).into_owned());
+        let codec_details = trak
+            .mdia_box
+            .minf_box
+            .stbl_box
+            .stsd_box
+            .entries
+            .first()
+            .and_then(codec_details);
 
         // サンプルテーブルから詳細情報を取得
-        let (sample_count, chunk_count) =
-            match SampleTableAccessor::new(&trak.mdia_box.minf_box.stbl_box) {
-                Ok(sample_table) => (
-                    Some(sample_table.sample_count()),
-                    Some(sample_table.chunk_count()),
-                ),
-                Err(_) => (None, None),
+        let stbl_box = &trak.mdia_box.minf_box.stbl_box;
+        let (sample_count, chunk_count, presentation_duration) =
+            match SampleTableAccessor::new(stbl_box) {
+                Ok(sample_table) => {
+                    let ctts_entries = parse_ctts_entries(stbl_box).unwrap_or_default();
+                    let presentation_duration =
+                        composition_time_range(&sample_table, &ctts_entries).map(|range| {
+                            presentation_duration(trak, self.moov().mvhd_box.timescale, range)
+                        });
+                    (
+                        Some(sample_table.sample_count()),
+                        Some(sample_table.chunk_count()),
+                        presentation_duration,
+                    )
+                }
+                Err(_) => (None, None, None),
             };
 
+        // vmhd / smhd のメディアヘッダー
+        let media_header = trak
+            .mdia_box
+            .minf_box
+            .smhd_or_vmhd_box
+            .as_ref()
+            .map(|header| match header {
+                Either::A(smhd_box) => {
+                    // balance は符号付きの 8.8 固定小数点数
+                    let raw =
+                        i16::from_be_bytes([smhd_box.balance.integer, smhd_box.balance.fraction]);
+                    MediaHeader::Sound {
+                        balance: raw as f64 / 256.0,
+                    }
+                }
+                Either::B(vmhd_box) => MediaHeader::Video {
+                    graphics_mode: vmhd_box.graphicsmode,
+                    opcolor: vmhd_box.opcolor,
+                },
+            });
+
         TrackInfo {
+            track_id: trak.tkhd_box.track_id,
             media_type,
             duration: track_duration,
+            timescale: trak.mdia_box.mdhd_box.timescale.get(),
+            media_duration: trak.mdia_box.mdhd_box.duration,
+            presentation_duration,
             codec,
+            sample_entry_type,
             sample_count,
             chunk_count,
+            media_header,
+            codec_details,
+            presentation_start: initial_empty_edit_duration(trak)
+                .map(|duration| duration as f64 / self.mvhd().timescale.get() as f64),
+            external_data_reference: external_data_reference(trak),
+            has_negative_cts_offsets: parse_ctts_entries(stbl_box)
+                .is_some_and(|entries| entries.iter().any(|e| e.sample_offset < 0)),
+            handler_name: handler_name(&trak.mdia_box.hdlr_box.name),
+            encoder_delay: (handler_type == b"soun")
+                .then(|| edit_list_encoder_delay(trak, self.mvhd().timescale))
+                .flatten(),
+            language: language_code(trak.mdia_box.mdhd_box.language),
+            channel_count: trak
+                .mdia_box
+                .minf_box
+                .stbl_box
+                .stsd_box
+                .entries
+                .first()
+                .and_then(audio_fields)
+                .map(|audio| audio.channelcount),
         }
     }
 
     fn get_codec_name(&self, sample_entry: &SampleEntry) -> String {
-        match sample_entry {
-            SampleEntry::Avc1(_) => "AVC(H.264)".to_string(),
-            SampleEntry::Hev1(_) => "HEVC(H.265)".to_string(),
-            SampleEntry::Vp08(_) => "VP8".to_string(),
-            SampleEntry::Vp09(_) => "VP9".to_string(),
-            SampleEntry::Av01(_) => "AV1".to_string(),
-            SampleEntry::Opus(_) => "Opus".to_string(),
-            SampleEntry::Mp4a(_) => "MPEG AAC Audio (mp4a)".to_string(),
-            SampleEntry::Flac(_) => "FLAC".to_string(),
-            SampleEntry::Unknown(unknown) => {
-                let box_type = String::from_utf8_lossy(unknown.box_type.as_bytes());
-                format!("不明 ({})", box_type)
-            }
+        if let SampleEntry::Unknown(unknown) = sample_entry {
+            let box_type = String::from_utf8_lossy(unknown.box_type.as_bytes());
+            return tr!("不明 ({})", "Unknown ({})", box_type);
         }
+
+        let box_type = sample_entry.box_type();
+        SUPPORTED_CODECS
+            .iter()
+            .find(|(ty, _)| box_type.as_bytes() == ty.as_bytes())
+            .map(|(_, name)| name.to_string())
+            .unwrap_or_else(|| {
+                tr!(
+                    "不明 ({})",
+                    "Unknown ({})",
+                    String::from_utf8_lossy(box_type.as_bytes())
+                )
+            })
     }
 }
 
+/// 解析に対応しているサンプルエントリーのボックス種別と、表示用のコーデック名
+pub const SUPPORTED_CODECS: &[(&str, &str)] = &[
+    ("avc1", "AVC(H.264)"),
+    ("hev1", "HEVC(H.265)"),
+    ("vp08", "VP8"),
+    ("vp09", "VP9"),
+    ("av01", "AV1"),
+    ("Opus", "Opus"),
+    ("mp4a", "MPEG AAC Audio (mp4a)"),
+    ("fLaC", "FLAC"),
+];
+
+/// サンプルのメタデータ（タイムスタンプはトラックのタイムスケール単位）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SampleMeta {
+    /// 1 始まりのサンプル番号
+    pub index: NonZeroU32,
+    /// 表示時刻（コンポジションオフセットを考慮したもの）
+    pub pts: i64,
+    /// デコード時刻
+    pub dts: u64,
+    pub duration: u32,
+    /// キーフレーム（同期サンプル）かどうか
+    pub is_sync: bool,
+    pub data_offset: u64,
+    pub data_size: u32,
+}
+
 /// トラック情報を格納する構造体
+#[derive(Default)]
 pub struct TrackInfo {
+    /// tkhd ボックスのトラック ID（連番や 1 始まりとは限らない）
+    pub track_id: u32,
     pub media_type: String,
     pub duration: f64,
+    /// メディアのタイムスケール（mdhd ボックスの timescale）
+    pub timescale: u32,
+    /// mdhd ボックスの duration（メディアのタイムスケール単位）
+    pub media_duration: u64,
+    /// コンポジションオフセットと編集リストを考慮した表示上の長さ（メディアのタイムスケール単位）
+    pub presentation_duration: Option<u64>,
     pub codec: String,
+    /// 先頭のサンプルエントリーのボックス種別（「hev1」など）
+    pub sample_entry_type: Option<String>,
     pub sample_count: Option<u32>,
     pub chunk_count: Option<u32>,
+    pub media_header: Option<MediaHeader>,
+    /// AV1 / VP9 のコーデック設定から取得したプロファイルなどの情報
+    pub codec_details: Option<CodecDetails>,
+    /// 編集リストの先頭の空の編集による表示開始の遅れ（秒、遅れがない場合は `None`）
+    pub presentation_start: Option<f64>,
+    /// サンプルデータが別ファイルにある場合の参照先（dref のエントリ）
+    pub external_data_reference: Option<String>,
+    /// バージョン 1 の ctts に負のコンポジションオフセットが含まれるかどうか
+    pub has_negative_cts_offsets: bool,
+    /// hdlr ボックスのハンドラ名（空の場合は `None`）
+    pub handler_name: Option<String>,
+    /// オーディオトラックのエンコーダ遅延とパディング（情報がない場合は `None`）
+    pub encoder_delay: Option<EncoderDelay>,
+    /// mdhd ボックスの ISO 639-2/T 言語コード（「und」の場合は `None`）
+    pub language: Option<String>,
+    /// オーディオのサンプルエントリーのチャンネル数
+    pub channel_count: Option<u16>,
+}
+
+/// hdlr ボックスの name フィールドを表示用の文字列に変換する
+///
+/// 仕様上は null 終端の UTF-8 文字列だが、先頭に長さのバイトを持つ形式（QuickTime）や
+/// UTF-8 でない文字列も存在するため、長さのバイトと null 以降を取り除き、不正なバイトは置き換える
+fn handler_name(name: &[u8]) -> Option<String> {
+    let name = match name.split_first() {
+        Some((&len, rest)) if len as usize == rest.len() && len != 0 => rest,
+        _ => name,
+    };
+    let name = name.split(|&b| b == 0).next().unwrap_or_default();
+    let name = String::from_utf8_lossy(name).trim().to_string();
+    (!name.is_empty()).then_some(name)
+}
+
+/// mdhd ボックスの言語コードを文字列にする（未定義の「und」や不正な値の場合は `None`）
+fn language_code(language: [u8; 3]) -> Option<String> {
+    (language != MdhdBox::LANGUAGE_UNDEFINED && language.iter().all(u8::is_ascii_lowercase))
+        .then(|| String::from_utf8_lossy(&language).into_owned())
+}
+
+/// オーディオのサンプルエントリーの共通フィールド（オーディオ以外の場合は `None`）
+pub fn audio_fields(sample_entry: &SampleEntry) -> Option<&AudioSampleEntryFields> {
+    match sample_entry {
+        SampleEntry::Opus(b) => Some(&b.audio),
+        SampleEntry::Mp4a(b) => Some(&b.audio),
+        SampleEntry::Flac(b) => Some(&b.audio),
+        _ => None,
+    }
+}
+
+/// オーディオのエンコーダ遅延（先頭で捨てるサンプル数）とパディング（末尾で捨てるサンプル数）
+///
+/// サンプル数はオーディオのサンプリングレート単位
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncoderDelay {
+    pub delay: u64,
+    pub padding: u64,
+}
+
+/// コーデック設定ボックス（av1C / vpcC）から取得した情報
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodecDetails {
+    pub profile: String,
+    pub level: String,
+    pub bit_depth: u8,
+}
+
+impl std::fmt::Display for CodecDetails {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}, Level {}, {}-bit",
+            self.profile, self.level, self.bit_depth
+        )
+    }
+}
+
+/// サンプルエントリーのコーデック設定ボックスからプロファイル・レベル・ビット深度を取得する
+///
+/// AV1 / VP9 以外のサンプルエントリーの場合は `None` を返す
+fn codec_details(sample_entry: &SampleEntry) -> Option<CodecDetails> {
+    match sample_entry {
+        SampleEntry::Av01(av01) => {
+            let av1c = &av01.av1c_box;
+            let profile = match av1c.seq_profile.get() {
+                0 => "Main Profile".to_string(),
+                1 => "High Profile".to_string(),
+                2 => "Professional Profile".to_string(),
+                n => format!("Profile {}", n),
+            };
+            // seq_level_idx は上位 3 ビットがメジャー、下位 2 ビットがマイナーレベル（2.0 始まり）
+            let level_idx = av1c.seq_level_idx_0.get();
+            let level = if level_idx == 31 {
+                "Max".to_string()
+            } else {
+                format!("{}.{}", 2 + (level_idx >> 2), level_idx & 3)
+            };
+            let bit_depth = match (av1c.high_bitdepth.as_bool(), av1c.twelve_bit.as_bool()) {
+                (false, _) => 8,
+                (true, false) => 10,
+                (true, true) => 12,
+            };
+            Some(CodecDetails {
+                profile,
+                level,
+                bit_depth,
+            })
+        }
+        SampleEntry::Vp09(vp09) => {
+            let vpcc = &vp09.vpcc_box;
+            // VP9 のレベルは 10 倍した値で格納されている（例: 31 → 3.1）
+            Some(CodecDetails {
+                profile: format!("Profile {}", vpcc.profile),
+                level: format!("{}.{}", vpcc.level / 10, vpcc.level % 10),
+                bit_depth: vpcc.bit_depth.get(),
+            })
+        }
+        _ => None,
+    }
+}
+
+/// コーデック設定ボックス（avcC / hvcC）に格納されているパラメータセット
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParameterSet {
+    /// NAL ユニットタイプ
+    pub nal_unit_type: u8,
+    /// NAL ユニットタイプの名前（「SPS」など）
+    pub name: &'static str,
+    pub data: Vec<u8>,
+}
+
+/// サンプルエントリーのコーデック設定ボックスからパラメータセットを格納順に取得する
+///
+/// H.264 は avcC の SPS / PPS / SPS 拡張、H.265 は hvcC の各配列の NAL ユニットを返す。
+/// shiguredo_mp4 が未知のサンプルエントリーとして扱う avc3 / hvc1 は、ペイロードの子ボックスから
+/// avcC / hvcC を取り出して解析する。それ以外のサンプルエントリーの場合は空の一覧を返す
+pub fn parameter_sets(sample_entry: &SampleEntry) -> Vec<ParameterSet> {
+    match sample_entry {
+        SampleEntry::Avc1(avc1) => avcc_parameter_sets(&avc1.avcc_box),
+        SampleEntry::Hev1(hev1) => hvcc_parameter_sets(&hev1.hvcc_box),
+        SampleEntry::Unknown(b) => match b.box_type.as_bytes() {
+            b"avc3" => visual_sample_entry_child_box::<AvccBox>(b, b"avcC")
+                .map(|avcc| avcc_parameter_sets(&avcc))
+                .unwrap_or_default(),
+            b"hvc1" => visual_sample_entry_child_box::<HvccBox>(b, b"hvcC")
+                .map(|hvcc| hvcc_parameter_sets(&hvcc))
+                .unwrap_or_default(),
+            _ => Vec::new(),
+        },
+        _ => Vec::new(),
+    }
+}
+
+/// avcC ボックスの SPS / PPS / SPS 拡張を格納順に返す
+fn avcc_parameter_sets(avcc: &AvccBox) -> Vec<ParameterSet> {
+    let parameter_set = |nal_unit_type: u8, name: &'static str, data: &Vec<u8>| ParameterSet {
+        nal_unit_type,
+        name,
+        data: data.clone(),
+    };
+    let sps = avcc.sps_list.iter().map(|d| parameter_set(7, "SPS", d));
+    let pps = avcc.pps_list.iter().map(|d| parameter_set(8, "PPS", d));
+    let sps_ext = avcc
+        .sps_ext_list
+        .iter()
+        .map(|d| parameter_set(13, "SPS Extension", d));
+    sps.chain(pps).chain(sps_ext).collect()
+}
+
+/// hvcC ボックスの各配列の NAL ユニットを格納順に返す
+fn hvcc_parameter_sets(hvcc: &HvccBox) -> Vec<ParameterSet> {
+    hvcc.nalu_arrays
+        .iter()
+        .flat_map(|array| {
+            let nal_unit_type = array.nal_unit_type.get();
+            let name = match nal_unit_type {
+                32 => "VPS",
+                33 => "SPS",
+                34 => "PPS",
+                39 => "Prefix SEI",
+                40 => "Suffix SEI",
+                _ => "Unknown",
+            };
+            array.nalus.iter().map(move |d| ParameterSet {
+                nal_unit_type,
+                name,
+                data: d.clone(),
+            })
+        })
+        .collect()
+}
+
+/// ビジュアルサンプルエントリーの固定長フィールド（予約領域と data_reference_index を含む）のバイト数
+const VISUAL_SAMPLE_ENTRY_FIELDS_SIZE: usize = 78;
+
+/// 未知のビジュアルサンプルエントリーのペイロードから、指定した種類の子ボックスを探してデコードする
+///
+/// 見つからない場合やデコードに失敗した場合は `None` を返す
+fn visual_sample_entry_child_box<T: Decode>(
+    sample_entry: &UnknownBox,
+    box_type: &[u8; 4],
+) -> Option<T> {
+    let children = sample_entry
+        .payload
+        .get(VISUAL_SAMPLE_ENTRY_FIELDS_SIZE..)?;
+    let payload = find_child_box(children, box_type)?;
+    T::decode(&encode_raw_box(box_type, payload))
+        .ok()
+        .map(|(child, _)| child)
+}
+
+/// 編集リストの一つの編集を、表示上の時刻とメディア時刻の対応として表したもの
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Edit {
+    /// 表示上の開始時刻（ムービーのタイムスケール単位。それまでの編集の長さの合計）
+    presentation_start: u64,
+    /// 編集の長さ（ムービーのタイムスケール単位。0 はメディアの末尾までを表す）
+    duration: u64,
+    /// 編集の先頭に対応するメディア時刻（メディアのタイムスケール単位）。空の編集の場合は `None`
+    media_time: Option<u64>,
+}
+
+/// 編集リストの各編集を、表示上の開始時刻を累積しながら先頭から順に返す
+///
+/// 編集リストがない場合は `None` を返す
+fn edits(trak_box: &TrakBox) -> Option<Vec<Edit>> {
+    let elst_box = trak_box.edts_box.as_ref()?.elst_box.as_ref()?;
+    let mut presentation_start = 0;
+    let mut edits = Vec::new();
+    for entry in &elst_box.entries {
+        edits.push(Edit {
+            presentation_start,
+            duration: entry.edit_duration,
+            media_time: u64::try_from(entry.media_time).ok(),
+        });
+        presentation_start += entry.edit_duration;
+    }
+    Some(edits)
+}
+
+/// 最初にメディアが表示される時刻（ムービーのタイムスケール単位）を返す
+///
+/// 編集リストがない場合や、先頭が空の編集（media_time が -1）でない場合は `None` を返す
+fn initial_empty_edit_duration(trak_box: &TrakBox) -> Option<u64> {
+    let first_media_edit = edits(trak_box)?
+        .into_iter()
+        .find(|edit| edit.media_time.is_some())?;
+    (first_media_edit.presentation_start > 0).then_some(first_media_edit.presentation_start)
+}
+
+/// 編集リストの最初のメディアの編集からエンコーダ遅延とパディングを求める
+///
+/// 編集の先頭のメディア時刻を遅延、編集の末尾からメディアの末尾までをパディングとみなす。
+/// 編集リストがない場合や、遅延もパディングもない場合は `None` を返す
+fn edit_list_encoder_delay(
+    trak_box: &TrakBox,
+    movie_timescale: NonZeroU32,
+) -> Option<EncoderDelay> {
+    let edit = edits(trak_box)?
+        .into_iter()
+        .find(|edit| edit.media_time.is_some())?;
+    let media_time = edit.media_time?;
+    let media_timescale = trak_box.mdia_box.mdhd_box.timescale.get() as u128;
+    let padding = if edit.duration == 0 {
+        0
+    } else {
+        let edit_duration = edit.duration as u128 * media_timescale / movie_timescale.get() as u128;
+        (trak_box.mdia_box.mdhd_box.duration as u128)
+            .saturating_sub(media_time as u128 + edit_duration) as u64
+    };
+    if media_time == 0 && padding == 0 {
+        return None;
+    }
+
+    // メディアのタイムスケールがサンプリングレートと異なる場合はサンプル数に換算する
+    let sample_rate = trak_box
+        .mdia_box
+        .minf_box
+        .stbl_box
+        .stsd_box
+        .entries
+        .first()
+        .and_then(audio_sample_rate)
+        .map_or(media_timescale, u128::from);
+    let to_samples = |time: u64| (time as u128 * sample_rate / media_timescale) as u64;
+    Some(EncoderDelay {
+        delay: to_samples(media_time),
+        padding: to_samples(padding),
+    })
+}
+
+/// オーディオのサンプルエントリーのサンプリングレートを返す（0 の場合は `None`）
+fn audio_sample_rate(sample_entry: &SampleEntry) -> Option<u16> {
+    let audio = match sample_entry {
+        SampleEntry::Opus(b) => &b.audio,
+        SampleEntry::Mp4a(b) => &b.audio,
+        SampleEntry::Flac(b) => &b.audio,
+        _ => return None,
+    };
+    (audio.samplerate.integer != 0).then_some(audio.samplerate.integer)
+}
+
+/// moov/udta/meta/ilst の iTunSMPB（iTunes のギャップレス情報）からエンコーダ遅延とパディングを取得する
+///
+/// iTunSMPB は「 00000000 00000840 00000240 ...」のような 16 進数の並びで、2 番目が遅延、3 番目がパディング
+fn itunes_gapless_info(moov_unknown_boxes: &[UnknownBox]) -> Option<EncoderDelay> {
+    let udta = moov_unknown_boxes
+        .iter()
+        .find(|b| b.box_type == BoxType::Normal(*b"udta"))?;
+    let meta = find_child_box(&udta.payload, b"meta")?;
+    // iTunes の meta はフルボックスだが、QuickTime 形式ではバージョンとフラグを持たない
+    let meta_children = if meta.get(4..8) == Some(b"hdlr") {
+        meta
+    } else {
+        meta.get(4..)?
+    };
+    let ilst = find_child_box(meta_children, b"ilst")?;
+
+    let value = child_boxes(ilst)
+        .map_while(Result::ok)
+        .filter(|(box_type, _)| box_type == b"----")
+        .find_map(|(_, item)| {
+            let name = find_child_box(item, b"name")?.get(4..)?;
+            if name != b"iTunSMPB" {
+                return None;
+            }
+            // data ボックスは型（4 バイト）とロケール（4 バイト）の後ろに値を持つ
+            find_child_box(item, b"data")?.get(8..)
+        })?;
+    let value = std::str::from_utf8(value).ok()?;
+    let mut fields = value
+        .split_whitespace()
+        .map(|field| u64::from_str_radix(field, 16));
+    let delay = fields.nth(1)?.ok()?;
+    let padding = fields.next()?.ok()?;
+    Some(EncoderDelay { delay, padding })
+}
+
+/// ボックスのペイロードに含まれる子ボックスの `(ボックス種別, ペイロード)` を先頭から順に返す
+///
+/// サイズが不正なボックスが見つかった場合は、エラーを返して終了する
+pub fn child_boxes(mut payload: &[u8]) -> impl Iterator<Item = Result<([u8; 4], &[u8]), String>> {
+    std::iter::from_fn(move || {
+        if payload.is_empty() {
+            return None;
+        }
+        let Some((box_type, child, size)) = split_first_box(payload) else {
+            let box_type = String::from_utf8_lossy(payload.get(4..8).unwrap_or_default());
+            let error = tr!(
+                "{} ボックスのサイズが不正です",
+                "Invalid {} box size",
+                box_type
+            );
+            payload = &[];
+            return Some(Err(error));
+        };
+        payload = &payload[size..];
+        Some(Ok((box_type, child)))
+    })
+}
+
+/// バイト列の先頭のボックスの `(ボックス種別, ペイロード, ヘッダーを含むサイズ)` を返す
+///
+/// サイズが不正な場合は `None` を返す
+fn split_first_box(bytes: &[u8]) -> Option<([u8; 4], &[u8], usize)> {
+    let size = u32::from_be_bytes(bytes.get(..4)?.try_into().ok()?) as usize;
+    let box_type: [u8; 4] = bytes.get(4..8)?.try_into().ok()?;
+    let (header_size, size) = match size {
+        0 => (8, bytes.len()),
+        1 => (
+            16,
+            u64::from_be_bytes(bytes.get(8..16)?.try_into().ok()?) as usize,
+        ),
+        size => (8, size),
+    };
+    let payload = bytes.get(header_size..size)?;
+    Some((box_type, payload, size))
+}
+
+/// ボックス種別とペイロードからボックスのバイト列を組み立てる
+fn encode_raw_box(box_type: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(16 + payload.len());
+    match u32::try_from(8 + payload.len()) {
+        Ok(size) => bytes.extend_from_slice(&size.to_be_bytes()),
+        Err(_) => {
+            bytes.extend_from_slice(&1u32.to_be_bytes());
+            bytes.extend_from_slice(box_type);
+            bytes.extend_from_slice(&(16 + payload.len() as u64).to_be_bytes());
+            bytes.extend_from_slice(payload);
+            return bytes;
+        }
+    }
+    bytes.extend_from_slice(box_type);
+    bytes.extend_from_slice(payload);
+    bytes
+}
+
+/// moov ボックスのバイト列をデコードする
+///
+/// shiguredo_mp4 は stbl ボックスに stsz ボックスを必須としているため、stz2（コンパクトなサンプルサイズ）
+/// ボックスを持つトラックは、デコード前に stz2 を等価な stsz に置き換える
+pub fn decode_moov_box(bytes: &[u8]) -> Result<MoovBox, String> {
+    let converted;
+    let bytes = if bytes.windows(4).any(|w| w == b"stz2") {
+        let (box_type, payload, _) = split_first_box(bytes)
+            .ok_or_else(|| tr!("moov ボックスのサイズが不正です", "Invalid moov box size"))?;
+        converted = replace_stz2_boxes(box_type, payload)?;
+        &converted
+    } else {
+        bytes
+    };
+    let (moov_box, _) = MoovBox::decode(bytes).map_err(|e| e.to_string())?;
+    Ok(moov_box)
+}
+
+/// stbl ボックスまでのコンテナを辿り、stz2 ボックスを stsz ボックスに置き換えたボックスのバイト列を返す
+fn replace_stz2_boxes(box_type: [u8; 4], payload: &[u8]) -> Result<Vec<u8>, String> {
+    match &box_type {
+        b"moov" | b"trak" | b"mdia" | b"minf" | b"stbl" => {
+            let mut children = Vec::with_capacity(payload.len());
+            for child in child_boxes(payload) {
+                let (child_type, child_payload) = child?;
+                children.extend(replace_stz2_boxes(child_type, child_payload)?);
+            }
+            Ok(encode_raw_box(&box_type, &children))
+        }
+        b"stz2" => Ok(encode_raw_box(b"stsz", &stz2_to_stsz_payload(payload)?)),
+        _ => Ok(encode_raw_box(&box_type, payload)),
+    }
+}
+
+/// stz2 ボックスのペイロードを、同じサンプルサイズを持つ stsz ボックスのペイロードに変換する
+///
+/// stz2 はサンプルサイズを 4 / 8 / 16 ビットのいずれかで格納する（4 ビットの場合は 1 バイトに 2 サンプル分）
+fn stz2_to_stsz_payload(payload: &[u8]) -> Result<Vec<u8>, String> {
+    let invalid = || tr!("stz2 ボックスが不正です", "Invalid stz2 box");
+    let field_size = *payload.get(7).ok_or_else(invalid)?;
+    let sample_count =
+        u32::from_be_bytes(payload.get(8..12).ok_or_else(invalid)?.try_into().unwrap());
+    let entries = &payload[12..];
+    let sizes: Vec<u32> = match field_size {
+        4 => entries
+            .iter()
+            .flat_map(|&b| [b >> 4, b & 0x0F])
+            .map(u32::from)
+            .take(sample_count as usize)
+            .collect(),
+        8 => entries
+            .iter()
+            .map(|&b| u32::from(b))
+            .take(sample_count as usize)
+            .collect(),
+        16 => entries
+            .chunks_exact(2)
+            .map(|b| u32::from(u16::from_be_bytes([b[0], b[1]])))
+            .take(sample_count as usize)
+            .collect(),
+        _ => {
+            return Err(tr!(
+                "stz2 ボックスのフィールドサイズ {} には対応していません",
+                "Unsupported stz2 field size: {}",
+                field_size
+            ));
+        }
+    };
+    if sizes.len() != sample_count as usize {
+        return Err(invalid());
+    }
+
+    // バージョン・フラグと sample_size（0 はサンプルごとにサイズを持つことを表す）
+    let mut stsz_payload = vec![0; 8];
+    stsz_payload.extend_from_slice(&sample_count.to_be_bytes());
+    for size in sizes {
+        stsz_payload.extend_from_slice(&size.to_be_bytes());
+    }
+    Ok(stsz_payload)
+}
+
+/// 子ボックスの中から指定した種別の最初のボックスのペイロードを返す
+fn find_child_box<'a>(payload: &'a [u8], box_type: &[u8; 4]) -> Option<&'a [u8]> {
+    child_boxes(payload)
+        .map_while(Result::ok)
+        .find_map(|(ty, child)| (ty == *box_type).then_some(child))
+}
+
+/// 表示上の時刻（秒）に対応するトラックのメディア時刻（メディアのタイムスケール単位）を返す
+///
+/// 編集リストの編集を先頭から順に辿って対応を求める。空の編集の範囲内の時刻は、その次の編集の先頭の
+/// メディア時刻に対応させ、すべての編集より後ろの時刻は最後の編集を延長して求める。
+/// 編集リストがない場合は、表示上の時刻とメディア時刻が一致するものとみなす
+pub fn presentation_to_media_time(
+    trak_box: &TrakBox,
+    movie_timescale: NonZeroU32,
+    presentation_sec: f64,
+) -> u64 {
+    let media_timescale = trak_box.mdia_box.mdhd_box.timescale.get() as f64;
+    let movie_timescale = movie_timescale.get() as f64;
+    let media_edits: Vec<Edit> = edits(trak_box)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|edit| edit.media_time.is_some())
+        .collect();
+
+    let media_time_in = |edit: &Edit, presentation_sec: f64| {
+        let offset_sec = presentation_sec - edit.presentation_start as f64 / movie_timescale;
+        // 負の値は 0 に丸められる
+        edit.media_time.unwrap_or(0) + seconds_to_ticks(offset_sec, media_timescale)
+    };
+    for edit in &media_edits {
+        let edit_end_sec = (edit.presentation_start + edit.duration) as f64 / movie_timescale;
+        if edit.duration == 0 || presentation_sec < edit_end_sec {
+            return media_time_in(edit, presentation_sec);
+        }
+    }
+    match media_edits.last() {
+        Some(edit) => media_time_in(edit, presentation_sec),
+        None => seconds_to_ticks(presentation_sec, media_timescale),
+    }
+}
+
+/// 秒数をタイムスケール単位の時刻に変換する（負の値は 0 になる）
+///
+/// 端数は切り捨てるが、`4.1 * 30 = 122.99999999999999` のような浮動小数点数の誤差で
+/// 整数値を下回っているだけの場合は、最も近い整数に丸める
+fn seconds_to_ticks(sec: f64, timescale: f64) -> u64 {
+    const EPSILON: f64 = 1e-6;
+    let ticks = sec.max(0.0) * timescale;
+    let rounded = ticks.round();
+    if (ticks - rounded).abs() < EPSILON {
+        rounded as u64
+    } else {
+        ticks as u64
+    }
+}
+
+/// トラックのメディア時刻（メディアのタイムスケール単位）に対応する表示上の時刻（秒）を返す
+///
+/// メディア時刻を含む最初の編集を基準にする。どの編集にも含まれない場合は、
+/// それより前で最も近い編集（なければ最初の編集）を延長して求める
+pub fn media_to_presentation_time(
+    trak_box: &TrakBox,
+    movie_timescale: NonZeroU32,
+    media_time: i64,
+) -> f64 {
+    let media_timescale = trak_box.mdia_box.mdhd_box.timescale.get() as f64;
+    let movie_timescale = movie_timescale.get() as f64;
+    let media_edits: Vec<(f64, f64, f64)> = edits(trak_box)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|edit| {
+            let start_sec = edit.presentation_start as f64 / movie_timescale;
+            let duration_sec = match edit.duration {
+                0 => f64::INFINITY,
+                duration => duration as f64 / movie_timescale,
+            };
+            Some((
+                start_sec,
+                duration_sec,
+                edit.media_time? as f64 / media_timescale,
+            ))
+        })
+        .collect();
+
+    let media_sec = media_time as f64 / media_timescale;
+    let base = media_edits
+        .iter()
+        .find(|(_, duration_sec, edit_media_sec)| {
+            (*edit_media_sec..edit_media_sec + duration_sec).contains(&media_sec)
+        })
+        .or_else(|| {
+            media_edits
+                .iter()
+                .rfind(|(_, _, edit_media_sec)| *edit_media_sec <= media_sec)
+        })
+        .or(media_edits.first());
+    match base {
+        Some((start_sec, _, edit_media_sec)) => start_sec + (media_sec - edit_media_sec),
+        None => media_sec,
+    }
+}
+
+/// shiguredo_mp4 は未知のボックスとして扱うが、このツールが直接解釈するボックス
+const DIRECTLY_PARSED_BOX_TYPES: [[u8; 4]; 4] = [*b"ctts", *b"mvex", *b"url ", *b"urn "];
+
+/// moov ボックス内で shiguredo_mp4 が解釈できなかったボックスとサンプルエントリーの位置を返す
+///
+/// 位置は「moov/trak[1]/mdia/minf/stbl/sdtp」のようなパスで表す（trak の番号は 1 始まり）。
+/// ctts などこのツールが直接解釈するボックスは含めない
+pub fn unknown_box_paths(moov_box: &MoovBox) -> Vec<String> {
+    let mut paths = Vec::new();
+    let mut push = |parent: &str, unknown_boxes: &[UnknownBox]| {
+        for unknown in unknown_boxes {
+            let box_type = unknown.box_type.as_bytes();
+            if DIRECTLY_PARSED_BOX_TYPES.iter().any(|t| t[..] == *box_type) {
+                continue;
+            }
+            paths.push(format!("{}/{}", parent, String::from_utf8_lossy(box_type)));
+        }
+    };
+
+    push("moov", &moov_box.unknown_boxes);
+    for (i, trak_box) in moov_box.trak_boxes.iter().enumerate() {
+        let trak = format!("moov/trak[{}]", i + 1);
+        push(&trak, &trak_box.unknown_boxes);
+        if let Some(edts_box) = &trak_box.edts_box {
+            push(&format!("{trak}/edts"), &edts_box.unknown_boxes);
+        }
+        let mdia_box = &trak_box.mdia_box;
+        push(&format!("{trak}/mdia"), &mdia_box.unknown_boxes);
+        let minf_box = &mdia_box.minf_box;
+        push(&format!("{trak}/mdia/minf"), &minf_box.unknown_boxes);
+        push(
+            &format!("{trak}/mdia/minf/dinf"),
+            &minf_box.dinf_box.unknown_boxes,
+        );
+        push(
+            &format!("{trak}/mdia/minf/dinf/dref"),
+            &minf_box.dinf_box.dref_box.unknown_boxes,
+        );
+        let stbl_box = &minf_box.stbl_box;
+        push(&format!("{trak}/mdia/minf/stbl"), &stbl_box.unknown_boxes);
+        let stsd = format!("{trak}/mdia/minf/stbl/stsd");
+        for sample_entry in &stbl_box.stsd_box.entries {
+            let (box_type, unknown_boxes) = match sample_entry {
+                SampleEntry::Avc1(b) => ("avc1", &b.unknown_boxes),
+                SampleEntry::Hev1(b) => ("hev1", &b.unknown_boxes),
+                SampleEntry::Vp08(b) => ("vp08", &b.unknown_boxes),
+                SampleEntry::Vp09(b) => ("vp09", &b.unknown_boxes),
+                SampleEntry::Av01(b) => ("av01", &b.unknown_boxes),
+                SampleEntry::Opus(b) => ("Opus", &b.unknown_boxes),
+                SampleEntry::Mp4a(b) => ("mp4a", &b.unknown_boxes),
+                SampleEntry::Flac(b) => ("fLaC", &b.unknown_boxes),
+                SampleEntry::Unknown(b) => {
+                    push(&stsd, std::slice::from_ref(b));
+                    continue;
+                }
+            };
+            push(&format!("{stsd}/{box_type}"), unknown_boxes);
+        }
+    }
+    paths
+}
+
+/// トラックのサンプルデータが別ファイルにある場合、その参照先を返す
+///
+/// stsd の各サンプルエントリーの data_reference_index が指す dref のエントリを調べ、
+/// 自己完結（同じファイル内）でないものがあれば、その URL / URN などを説明する文字列を返す。
+/// shiguredo_mp4 は dref 内のエントリの順序を保持しないため、url ボックスが先頭にあるものとみなす
+pub fn external_data_reference(trak_box: &TrakBox) -> Option<String> {
+    let dref_box = &trak_box.mdia_box.minf_box.dinf_box.dref_box;
+    let entries: Vec<Option<String>> = dref_box
+        .url_box
+        .iter()
+        .map(|url_box| {
+            url_box
+                .location
+                .as_ref()
+                .map(|location| format!("url \"{}\"", location.get()))
+        })
+        .chain(dref_box.unknown_boxes.iter().map(data_entry_location))
+        .collect();
+
+    trak_box
+        .mdia_box
+        .minf_box
+        .stbl_box
+        .stsd_box
+        .entries
+        .iter()
+        .find_map(|sample_entry| {
+            let index = data_reference_index(sample_entry)?;
+            match index.checked_sub(1).and_then(|i| entries.get(i as usize)) {
+                Some(location) => location.clone(),
+                None => Some(tr!(
+                    "dref に存在しないエントリ (data_reference_index: {})",
+                    "missing dref entry (data_reference_index: {})",
+                    index
+                )),
+            }
+        })
+}
+
+/// url ボックス以外の dref のエントリ（urn など）の参照先を返す（自己完結の場合は `None`）
+fn data_entry_location(entry: &UnknownBox) -> Option<String> {
+    let box_type = String::from_utf8_lossy(entry.box_type.as_bytes()).into_owned();
+    // フルボックスヘッダーのフラグの最下位ビットが 1 ならメディアデータは同じファイル内にある
+    if entry.payload.get(3).is_some_and(|flags| flags & 1 == 1) {
+        return None;
+    }
+    // urn ボックスは null 終端の name と location を持つ
+    let name = entry
+        .payload
+        .get(4..)
+        .and_then(|fields| fields.split(|&b| b == 0).next())
+        .filter(|name| !name.is_empty());
+    match name {
+        Some(name) => Some(format!(
+            "{} \"{}\"",
+            box_type.trim_end(),
+            String::from_utf8_lossy(name)
+        )),
+        None => Some(box_type.trim_end().to_string()),
+    }
+}
+
+/// サンプルエントリーの data_reference_index を取得する
+fn data_reference_index(sample_entry: &SampleEntry) -> Option<u16> {
+    let index = match sample_entry {
+        SampleEntry::Avc1(b) => b.visual.data_reference_index,
+        SampleEntry::Hev1(b) => b.visual.data_reference_index,
+        SampleEntry::Vp08(b) => b.visual.data_reference_index,
+        SampleEntry::Vp09(b) => b.visual.data_reference_index,
+        SampleEntry::Av01(b) => b.visual.data_reference_index,
+        SampleEntry::Opus(b) => b.audio.data_reference_index,
+        SampleEntry::Mp4a(b) => b.audio.data_reference_index,
+        SampleEntry::Flac(b) => b.audio.data_reference_index,
+        // 未知のサンプルエントリーも、先頭 6 バイトの予約領域の後ろに data_reference_index を持つ
+        SampleEntry::Unknown(b) => {
+            return b
+                .payload
+                .get(6..8)
+                .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]));
+        }
+    };
+    Some(index.get())
+}
+
+/// minf ボックス内のメディアヘッダー（vmhd / smhd）の情報
+#[derive(Debug, Clone, PartialEq)]
+pub enum MediaHeader {
+    Video {
+        graphics_mode: u16,
+        /// グラフィックモードで使用する RGB の色
+        opcolor: [u16; 3],
+    },
+    Sound {
+        /// ステレオバランス（-1.0 が左、1.0 が右）
+        balance: f64,
+    },
+}
+
+/// ctts ボックスのエントリ
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CttsEntry {
+    pub sample_count: u32,
+    /// コンポジションオフセット（タイムスケール単位）
+    pub sample_offset: i64,
+}
+
+/// stbl ボックス内の ctts ボックスを解析する
+///
+/// shiguredo_mp4 は ctts を未知のボックスとして保持するため、ペイロードを直接解釈する。
+/// ctts ボックスが存在しないか、解析できない場合は `None` を返す
+pub fn parse_ctts_entries(stbl_box: &StblBox) -> Option<Vec<CttsEntry>> {
+    let ctts_box = stbl_box
+        .unknown_boxes
+        .iter()
+        .find(|b| b.box_type == BoxType::Normal(*b"ctts"))?;
+    let payload = &ctts_box.payload;
+
+    // バージョン 1 ではオフセットが符号付きになる
+    let version = *payload.first()?;
+    let entry_count = u32::from_be_bytes(payload.get(4..8)?.try_into().ok()?) as usize;
+
+    let mut entries = Vec::new();
+    for entry in payload.get(8..)?.chunks_exact(8).take(entry_count) {
+        let sample_count = u32::from_be_bytes(entry[..4].try_into().ok()?);
+        let offset_bytes = entry[4..].try_into().ok()?;
+        let sample_offset = if version == 0 {
+            u32::from_be_bytes(offset_bytes) as i64
+        } else {
+            i32::from_be_bytes(offset_bytes) as i64
+        };
+        entries.push(CttsEntry {
+            sample_count,
+            sample_offset,
+        });
+    }
+    if entries.len() != entry_count {
+        return None;
+    }
+    Some(entries)
+}
+
+/// サンプルごとのコンポジションオフセットから ctts ボックスを組み立てる
+///
+/// 負のオフセットを含む場合は、符号付きのオフセットを持つバージョン 1 にする。
+/// すべてのオフセットが 0 の場合は ctts ボックスが不要なので `None` を返す
+pub fn build_ctts_box(composition_offsets: &[i64]) -> Result<Option<UnknownBox>, String> {
+    if composition_offsets.iter().all(|&offset| offset == 0) {
+        return Ok(None);
+    }
+    let version = u8::from(composition_offsets.iter().any(|&offset| offset < 0));
+
+    let mut entries: Vec<(u32, i64)> = Vec::new();
+    for &offset in composition_offsets {
+        match entries.last_mut() {
+            Some((count, last_offset)) if *last_offset == offset => *count += 1,
+            _ => entries.push((1, offset)),
+        }
+    }
+    let mut payload = vec![version, 0, 0, 0];
+    payload.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+    for (count, offset) in entries {
+        let offset_bytes = if version == 0 {
+            u32::try_from(offset).map(u32::to_be_bytes)
+        } else {
+            i32::try_from(offset).map(i32::to_be_bytes)
+        }
+        .map_err(|_| {
+            tr!(
+                "コンポジションオフセット {} が大きすぎます",
+                "Composition offset {} is too large",
+                offset
+            )
+        })?;
+        payload.extend_from_slice(&count.to_be_bytes());
+        payload.extend_from_slice(&offset_bytes);
+    }
+    Ok(Some(UnknownBox {
+        box_type: BoxType::Normal(*b"ctts"),
+        box_size: BoxSize::U32(8 + payload.len() as u32),
+        payload,
+    }))
+}
+
+/// ctts のエントリを展開して、先頭サンプルから順にコンポジションオフセットを返すイテレーターを作る
+///
+/// エントリの合計サンプル数がトラックのサンプル数より少ない場合、残りのサンプルのオフセットは 0 とみなすこと
+pub fn composition_offsets(ctts_entries: &[CttsEntry]) -> impl '_ + Iterator<Item = i64> {
+    ctts_entries
+        .iter()
+        .flat_map(|e| std::iter::repeat_n(e.sample_offset, e.sample_count as usize))
+}
+
+/// コンポジションオフセットを考慮した表示時刻の範囲（タイムスケール単位）を計算する
+///
+/// 最初に表示されるサンプルの表示時刻と、最後に表示されるサンプルの表示終了時刻を返す。
+/// サンプルがない場合は `None` を返す
+fn composition_time_range<T: AsRef<StblBox>>(
+    sample_table: &SampleTableAccessor<T>,
+    ctts_entries: &[CttsEntry],
+) -> Option<(i64, i64)> {
+    let mut offsets = composition_offsets(ctts_entries);
+    sample_table.samples().fold(None, |range, sample| {
+        let pts = sample.timestamp() as i64 + offsets.next().unwrap_or(0);
+        let end = pts + sample.duration() as i64;
+        Some(match range {
+            Some((start_min, end_max)) => (pts.min(start_min), end.max(end_max)),
+            None => (pts, end),
+        })
+    })
+}
+
+/// 表示時刻の範囲（メディアのタイムスケール単位）から、編集リストを考慮した表示上の長さを
+/// メディアのタイムスケール単位で計算する
+///
+/// 編集リストがない場合は範囲の長さを返す。ある場合は、各編集が表示するメディアをサンプルの範囲の末尾で
+/// 切り詰めたうえで、最後に表示が終わる時刻を返す（先頭の空の編集の長さも含む）
+fn presentation_duration(
+    trak_box: &TrakBox,
+    movie_timescale: NonZeroU32,
+    (start, end): (i64, i64),
+) -> u64 {
+    let Some(edits) = edits(trak_box).filter(|edits| !edits.is_empty()) else {
+        return end.saturating_sub(start).max(0) as u64;
+    };
+    let media_timescale = trak_box.mdia_box.mdhd_box.timescale.get() as u128;
+    let to_media_ticks =
+        |t: u64| (t as u128 * media_timescale / movie_timescale.get() as u128) as u64;
+    edits
+        .iter()
+        .map(|edit| {
+            let edit_start = to_media_ticks(edit.presentation_start);
+            let edit_duration = to_media_ticks(edit.duration);
+            let Some(media_time) = edit.media_time else {
+                return edit_start.saturating_add(edit_duration);
+            };
+            let shown = end.saturating_sub_unsigned(media_time).max(0) as u64;
+            match edit.duration {
+                // 長さ 0 の編集はメディアの末尾まで表示する
+                0 => edit_start.saturating_add(shown),
+                _ => edit_start.saturating_add(shown.min(edit_duration)),
+            }
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// `--max-tracks` のデフォルト値
+pub const DEFAULT_MAX_TRACKS: &str = "1000";
+
+/// `--max-samples` のデフォルト値（全トラックの合計）
+///
+/// 60 fps の映像と 48 kHz の AAC 音声で 24 時間分のサンプル数（約 950 万）に十分な余裕を持たせている
+pub const DEFAULT_MAX_SAMPLES: &str = "100000000";
+
+/// 信頼できない入力を処理する際の、トラック数とサンプル数の上限
+///
+/// サンプル数が固定サイズの stsz ボックスは数バイトで膨大なサンプル数を宣言できるため、
+/// サンプルテーブルを走査する前に確認して、処理が終わらなくなるのを防ぐ
+#[derive(Debug, Clone, Copy)]
+pub struct ResourceLimits {
+    pub max_tracks: usize,
+    pub max_samples: u64,
+}
+
+const MAX_TRACKS_OPT: noargs::OptSpec = noargs::opt("max-tracks")
+    .doc("処理するトラック数の上限。超える場合は信頼できない入力とみなしてエラーにします")
+    .ty("COUNT")
+    .default(DEFAULT_MAX_TRACKS);
+
+const MAX_SAMPLES_OPT: noargs::OptSpec = noargs::opt("max-samples")
+    .doc("処理するサンプル数（全トラックの合計）の上限。超える場合は信頼できない入力とみなしてエラーにします")
+    .ty("COUNT")
+    .default(DEFAULT_MAX_SAMPLES);
+
+impl ResourceLimits {
+    /// `--max-tracks` と `--max-samples` オプションから上限を取得する
+    pub fn take(args: &mut noargs::RawArgs) -> noargs::Result<Self> {
+        Ok(Self {
+            max_tracks: MAX_TRACKS_OPT.take(args).then(|o| o.value().parse())?,
+            max_samples: MAX_SAMPLES_OPT.take(args).then(|o| o.value().parse())?,
+        })
+    }
+
+    /// moov ボックスに宣言されているトラック数とサンプル数の合計が上限以内かを確認する
+    pub fn check(&self, moov_box: &MoovBox) -> Result<(), String> {
+        let track_count = moov_box.trak_boxes.len();
+        if track_count > self.max_tracks {
+            return Err(tr!(
+                "トラック数 ({}) が上限 ({}) を超えています（--max-tracks で変更できます）",
+                "The number of tracks ({}) exceeds the limit ({}) (can be changed with --max-tracks)",
+                track_count,
+                self.max_tracks
+            ));
+        }
+        let sample_count: u64 = moov_box
+            .trak_boxes
+            .iter()
+            .map(
+                |trak_box| match &trak_box.mdia_box.minf_box.stbl_box.stsz_box {
+                    StszBox::Fixed { sample_count, .. } => *sample_count as u64,
+                    StszBox::Variable { entry_sizes } => entry_sizes.len() as u64,
+                },
+            )
+            .sum();
+        self.check_sample_count(sample_count)
+    }
+
+    /// サンプル数の合計が上限以内かを確認する
+    pub fn check_sample_count(&self, sample_count: u64) -> Result<(), String> {
+        if sample_count > self.max_samples {
+            return Err(tr!(
+                "サンプル数の合計 ({}) が上限 ({}) を超えています（--max-samples で変更できます）",
+                "The total number of samples ({}) exceeds the limit ({}) (can be changed with --max-samples)",
+                sample_count,
+                self.max_samples
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// 通常のサンプル間隔の何倍を超えたらタイムスタンプの欠落とみなすか
+const GAP_THRESHOLD_RATIO: f64 = 1.5;
+
+/// サンプル間隔が通常より長い箇所（フレーム落ちやパケットロスによるタイムスタンプの欠落）
+///
+/// 時刻とサンプル間隔はメディアのタイムスケール単位
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimestampGap {
+    /// 間隔が長い最初のサンプルの 1 始まりの番号
+    pub sample_index: u64,
+    /// 間隔が長いサンプルが連続する数
+    pub sample_count: u32,
+    /// 最初のサンプルのデコード時刻
+    pub timestamp: u64,
+    pub sample_delta: u32,
+    /// トラックで最も多いサンプル間隔
+    pub expected_delta: u32,
+}
+
+impl TimestampGap {
+    /// 欠落している時間の合計
+    pub fn gap_duration(&self) -> u64 {
+        (self.sample_delta - self.expected_delta) as u64 * self.sample_count as u64
+    }
+
+    /// 欠落の位置と長さを、タイムスケール `timescale` で秒に換算して説明する
+    pub fn describe(&self, timescale: u32) -> String {
+        let seconds = |t: u64| t as f64 / timescale as f64;
+        let samples = if self.sample_count == 1 {
+            self.sample_index.to_string()
+        } else {
+            tr!(
+                "{}〜{}",
+                "{}-{}",
+                self.sample_index,
+                self.sample_index + self.sample_count as u64 - 1
+            )
+        };
+        tr!(
+            "サンプル {} ({:.3}秒) の間隔が {:.3}秒（通常 {:.3}秒）で、計 {:.3}秒 欠落しています",
+            "Sample {} ({:.3}s) has a delta of {:.3}s (usually {:.3}s); {:.3}s is missing in total",
+            samples,
+            seconds(self.timestamp),
+            seconds(self.sample_delta as u64),
+            seconds(self.expected_delta as u64),
+            seconds(self.gap_duration())
+        )
+    }
+}
+
+/// stts ボックスを走査して、サンプル間隔が最頻値の `GAP_THRESHOLD_RATIO` 倍を超える箇所を列挙する
+///
+/// デコード時刻はサンプル間隔の累積なので、時刻そのものが飛ぶことはなく、欠落は直前のサンプルの間隔が
+/// 長くなる形で現れる。可変フレームレートの映像では、意図的に間隔が長いフレームも検出される
+pub fn timestamp_gaps(stts_box: &SttsBox) -> Vec<TimestampGap> {
+    let mut frequencies = std::collections::BTreeMap::new();
+    for entry in &stts_box.entries {
+        *frequencies.entry(entry.sample_delta).or_insert(0u64) += entry.sample_count as u64;
+    }
+    let Some((&expected_delta, _)) = frequencies
+        .iter()
+        .max_by_key(|&(&delta, &count)| (count, std::cmp::Reverse(delta)))
+    else {
+        return Vec::new();
+    };
+
+    let mut gaps = Vec::new();
+    let mut sample_index = 1u64;
+    let mut timestamp = 0;
+    for entry in &stts_box.entries {
+        if entry.sample_delta as f64 > expected_delta as f64 * GAP_THRESHOLD_RATIO {
+            gaps.push(TimestampGap {
+                sample_index,
+                sample_count: entry.sample_count,
+                timestamp,
+                sample_delta: entry.sample_delta,
+                expected_delta,
+            });
+        }
+        sample_index += entry.sample_count as u64;
+        timestamp += entry.sample_delta as u64 * entry.sample_count as u64;
+    }
+    gaps
+}
+
+/// 全トラックのサンプルを再生時刻順に先頭から読み込んだ場合の、最大のシーク距離（バイト）を計算する
+///
+/// 直前に読み込んだサンプルの末尾から次のサンプルの先頭へ前方に読み飛ばす距離の最大値を返す
+/// （後方へのシークは含めない）。インターリーブが不十分なファイルほど大きな値になる
+pub fn max_interleave_seek_distance(moov_box: &MoovBox) -> Result<u64, String> {
+    let mut reads = Vec::new();
+    for trak_box in &moov_box.trak_boxes {
+        let timescale = trak_box.mdia_box.mdhd_box.timescale.get() as u128;
+        let sample_table =
+            SampleTableAccessor::new(&trak_box.mdia_box.minf_box.stbl_box).map_err(|e| {
+                tr!(
+                    "サンプルテーブルの解析に失敗しました: {}",
+                    "Failed to parse the sample table: {}",
+                    e
+                )
+            })?;
+        for sample in sample_table.samples() {
+            // トラック間で比較できるようにナノ秒単位に揃える
+            let time = sample.timestamp() as u128 * 1_000_000_000 / timescale;
+            reads.push((time, sample.data_offset(), sample.data_size() as u64));
+        }
+    }
+    Ok(max_seek_distance(reads))
+}
+
+/// `(時刻, オフセット, サイズ)` の読み込みを時刻順に並べたときの前方への最大のシーク距離を計算する
+fn max_seek_distance(mut reads: Vec<(u128, u64, u64)>) -> u64 {
+    // 同時刻のサンプルはトラック順を維持する
+    reads.sort_by_key(|&(time, _, _)| time);
+
+    let mut max_distance = 0;
+    let mut position = None;
+    for (_, offset, size) in reads {
+        if let Some(position) = position {
+            max_distance = max_distance.max(offset.saturating_sub(position));
+        }
+        position = Some(offset + size);
+    }
+    max_distance
+}
+
+/// MP4 ファイル全体のバイト列をデコードする
+///
+/// トップレベルのボックスを一つずつデコードし、失敗した場合はそのボックスの開始オフセットをエラーに含める
+pub fn decode_mp4_file(buffer: &[u8]) -> Result<Mp4File, String> {
+    let mut offset = 0;
+    let ftyp_box = FtypBox::decode_at(buffer, &mut offset).map_err(|e| decode_error(0, e))?;
+
+    let mut boxes = Vec::new();
+    while offset < buffer.len() {
+        let box_offset = offset;
+        // moov ボックスは stz2 ボックスの変換が必要な場合があるため個別にデコードする
+        if let Ok((header, _)) = BoxHeader::decode(&buffer[offset..])
+            && header.box_type == MoovBox::TYPE
+            && let Some((_, _, size)) = split_first_box(&buffer[offset..])
+        {
+            let moov_box = decode_moov_box(&buffer[offset..offset + size])
+                .map_err(|e| decode_error(box_offset as u64, e))?;
+            boxes.push(RootBox::Moov(moov_box));
+            offset += size;
+            continue;
+        }
+        let root_box = RootBox::decode_at(buffer, &mut offset)
+            .map_err(|e| decode_error(box_offset as u64, e))?;
+        boxes.push(root_box);
+    }
+
+    Ok(Mp4File { ftyp_box, boxes })
+}
+
+/// ファイルの末尾を越えるルートボックスがある場合、その開始位置を返す
+///
+/// ボックスのサイズが不正な場合などは `None` を返し、エラーの報告はデコード時に任せる
+fn truncated_box_offset(buffer: &[u8]) -> Option<usize> {
+    let mut offset = 0;
+    while offset < buffer.len() {
+        let bytes = &buffer[offset..];
+        let Some(size) = bytes.get(..4) else {
+            return Some(offset);
+        };
+        let (header_size, size) = match u32::from_be_bytes(size.try_into().ok()?) {
+            0 => return None, // ファイル末尾までのボックス
+            1 => match bytes.get(8..16) {
+                Some(size) => (16, u64::from_be_bytes(size.try_into().ok()?)),
+                None => return Some(offset),
+            },
+            size => (8, size as u64),
+        };
+        if bytes.len() < header_size {
+            return Some(offset);
+        }
+        if size < header_size as u64 {
+            return None;
+        }
+        if size > bytes.len() as u64 {
+            return Some(offset);
+        }
+        offset += size as usize;
+    }
+    None
+}
+
+/// 途中で切れたボックスの種別（ヘッダーも切れている場合は「不明」）
+fn truncated_box_name(bytes: &[u8]) -> String {
+    match bytes.get(4..8) {
+        Some(box_type) => String::from_utf8_lossy(box_type).into_owned(),
+        None => tr!("不明", "unknown"),
+    }
+}
+
+/// デコードエラーを、失敗したボックスのファイル内オフセット付きのメッセージにする
+fn decode_error(offset: u64, e: impl std::fmt::Display) -> String {
+    tr!(
+        "オフセット 0x{:X} 付近で MP4 ファイルの解析に失敗しました: {}",
+        "Failed to parse the MP4 file near offset 0x{:X}: {}",
+        offset,
+        e
+    )
+}
+
+/// シーク可能な入力からトップレベルのボックスを走査して moov ボックスを読み込む
+///
+/// mdat などの moov 以外のボックスは読み飛ばすため、ファイル全体をメモリに載せる必要がない
+pub fn read_moov_box<R: Read + Seek>(reader: &mut R) -> Result<MoovBox, String> {
+    let (_, moov_box) = scan_moov_box(reader, u64::MAX, |reader, size| {
+        let size = i64::try_from(size).map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                tr!(
+                    "ボックスのサイズが大きすぎます",
+                    "The box size is too large"
+                ),
+            )
+        })?;
+        reader.seek(SeekFrom::Current(size)).map(|_| ())
+    })?;
+    Ok(moov_box)
+}
+
+/// トップレベルのボックスを先頭から走査して moov ボックスを読み込む
+///
+/// 先頭の ftyp ボックスは読み込み、moov 以外のボックスのペイロードは `skip` で読み飛ばす。
+/// moov ボックスのサイズが `max_moov_size` を超える場合は読み込まずにエラーを返す
+fn scan_moov_box<R: Read>(
+    reader: &mut R,
+    max_moov_size: u64,
+    mut skip: impl FnMut(&mut R, u64) -> std::io::Result<()>,
+) -> Result<(Option<FtypBox>, MoovBox), String> {
+    let mut box_offset = 0;
+    let mut ftyp_box = None;
+    loop {
+        let Some((header, header_bytes)) = read_box_header(reader).map_err(|e| {
+            tr!(
+                "オフセット 0x{:X} 付近: {}",
+                "Near offset 0x{:X}: {}",
+                box_offset,
+                e
+            )
+        })?
+        else {
+            return Err(tr!("moov ボックスが見つかりません", "moov box not found"));
+        };
+
+        let box_size = header.box_size.get();
+        let is_ftyp = box_offset == 0 && header.box_type == FtypBox::TYPE;
+        if header.box_type == MoovBox::TYPE || is_ftyp {
+            let box_name = if is_ftyp { "ftyp" } else { "moov" };
+            if box_size > max_moov_size {
+                return Err(tr!(
+                    "{} ボックスのサイズ ({} バイト) がメモリ上限 ({} バイト) を超えています",
+                    "The {} box size ({} bytes) exceeds the memory limit ({} bytes)",
+                    box_name,
+                    box_size,
+                    max_moov_size
+                ));
+            }
+            let mut box_bytes = header_bytes;
+            let payload_size = box_size
+                .checked_sub(box_bytes.len() as u64)
+                .ok_or_else(|| {
+                    tr!(
+                        "{} ボックスのサイズが不正です",
+                        "Invalid {} box size",
+                        box_name
+                    )
+                })?;
+            reader
+                .by_ref()
+                .take(payload_size)
+                .read_to_end(&mut box_bytes)
+                .map_err(|e| {
+                    tr!(
+                        "ファイルの読み込みに失敗しました: {}",
+                        "Failed to read the file: {}",
+                        e
+                    )
+                })?;
+            if is_ftyp {
+                let (decoded, _) =
+                    FtypBox::decode(&box_bytes).map_err(|e| decode_error(box_offset, e))?;
+                ftyp_box = Some(decoded);
+                box_offset += box_size;
+                continue;
+            }
+            let moov_box = decode_moov_box(&box_bytes).map_err(|e| decode_error(box_offset, e))?;
+            return Ok((ftyp_box, moov_box));
+        }
+
+        if box_size == 0 {
+            // サイズ 0 はファイル末尾までのボックスを表すので、これ以降にボックスはない
+            return Err(tr!("moov ボックスが見つかりません", "moov box not found"));
+        }
+        let payload_size = box_size
+            .checked_sub(header_bytes.len() as u64)
+            .ok_or_else(|| {
+                tr!(
+                    "オフセット 0x{:X} 付近: ボックスのサイズが不正です",
+                    "Near offset 0x{:X}: invalid box size",
+                    box_offset
+                )
+            })?;
+        skip(reader, payload_size).map_err(|e| {
+            tr!(
+                "ファイルの読み込みに失敗しました: {}",
+                "Failed to read the file: {}",
+                e
+            )
+        })?;
+        box_offset = box_offset.checked_add(box_size).ok_or_else(|| {
+            tr!(
+                "オフセット 0x{:X} 付近: ボックスのサイズが不正です",
+                "Near offset 0x{:X}: invalid box size",
+                box_offset
+            )
+        })?;
+    }
+}
+
+/// トップレベルのボックスの位置情報
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RootBoxLocation {
+    pub box_type: BoxType,
+    /// ファイル先頭からのボックスの開始位置
+    pub offset: u64,
+    /// ヘッダー部分のサイズ
+    pub header_size: u64,
+    /// ヘッダーを含むボックス全体のサイズ
+    pub box_size: u64,
+}
+
+impl RootBoxLocation {
+    /// ペイロードの開始位置
+    pub fn payload_offset(&self) -> u64 {
+        self.offset + self.header_size
+    }
+
+    /// ボックスの終了位置
+    pub fn end_offset(&self) -> u64 {
+        self.offset + self.box_size
+    }
+}
+
+/// シーク可能な入力のトップレベルのボックスを走査して、各ボックスの位置を返す
+///
+/// サイズ 0（ファイル末尾まで）のボックスは実際のサイズに解決する
+pub fn scan_root_boxes<R: Read + Seek>(reader: &mut R) -> Result<Vec<RootBoxLocation>, String> {
+    let seek_error = |e: std::io::Error| {
+        tr!(
+            "ファイルのシークに失敗しました: {}",
+            "Failed to seek the file: {}",
+            e
+        )
+    };
+    let file_size = reader.seek(SeekFrom::End(0)).map_err(seek_error)?;
+    reader.seek(SeekFrom::Start(0)).map_err(seek_error)?;
+
+    let mut locations = Vec::new();
+    let mut offset = 0;
+    while let Some((header, header_bytes)) = read_box_header(reader).map_err(|e| {
+        tr!(
+            "オフセット 0x{:X} 付近: {}",
+            "Near offset 0x{:X}: {}",
+            offset,
+            e
+        )
+    })? {
+        let header_size = header_bytes.len() as u64;
+        let box_size = match header.box_size.get() {
+            0 => file_size - offset,
+            box_size => box_size,
+        };
+        if box_size < header_size
+            || offset
+                .checked_add(box_size)
+                .is_none_or(|end| end > file_size)
+        {
+            return Err(tr!(
+                "オフセット 0x{:X} 付近: ボックスのサイズが不正です",
+                "Near offset 0x{:X}: invalid box size",
+                offset
+            ));
+        }
+        locations.push(RootBoxLocation {
+            box_type: header.box_type,
+            offset,
+            header_size,
+            box_size,
+        });
+        offset += box_size;
+        reader.seek(SeekFrom::Start(offset)).map_err(seek_error)?;
+    }
+    Ok(locations)
+}
+
+/// トップレベルに置かれるボックスのうち、このツールが把握しているもの
+const KNOWN_ROOT_BOX_TYPES: [[u8; 4]; 16] = [
+    *b"ftyp", *b"styp", *b"moov", *b"mdat", *b"free", *b"skip", *b"wide", *b"moof", *b"mfra",
+    *b"sidx", *b"ssix", *b"prft", *b"emsg", *b"meta", *b"pdin", *b"uuid",
+];
+
+/// トップレベルの未知のボックスの種別を返す
+pub fn unknown_root_box_types(root_boxes: &[RootBoxLocation]) -> Vec<String> {
+    root_boxes
+        .iter()
+        .map(|location| location.box_type.as_bytes())
+        .filter(|box_type| !KNOWN_ROOT_BOX_TYPES.iter().any(|t| t[..] == **box_type))
+        .map(|box_type| String::from_utf8_lossy(box_type).into_owned())
+        .collect()
+}
+
+/// 入力の先頭にある ftyp ボックスを読み込む
+///
+/// 読み込み前に入力の先頭へシークする
+pub fn read_ftyp_box<R: Read + Seek>(reader: &mut R) -> Result<FtypBox, String> {
+    reader.seek(SeekFrom::Start(0)).map_err(|e| {
+        tr!(
+            "ファイルのシークに失敗しました: {}",
+            "Failed to seek the file: {}",
+            e
+        )
+    })?;
+    let Some((header, mut box_bytes)) = read_box_header(reader)? else {
+        return Err(tr!("ftyp ボックスが見つかりません", "ftyp box not found"));
+    };
+    if header.box_type != FtypBox::TYPE {
+        return Err(tr!("ftyp ボックスが見つかりません", "ftyp box not found"));
+    }
+
+    let payload_size = header
+        .box_size
+        .get()
+        .checked_sub(box_bytes.len() as u64)
+        .ok_or_else(|| tr!("ftyp ボックスのサイズが不正です", "Invalid ftyp box size"))?;
+    reader
+        .by_ref()
+        .take(payload_size)
+        .read_to_end(&mut box_bytes)
+        .map_err(|e| {
+            tr!(
+                "ファイルの読み込みに失敗しました: {}",
+                "Failed to read the file: {}",
+                e
+            )
+        })?;
+    let (ftyp_box, _) = FtypBox::decode(&box_bytes).map_err(|e| decode_error(0, e))?;
+    Ok(ftyp_box)
+}
+
+/// サンプルテーブルのチャンクオフセットを `delta` バイトずらす
+///
+/// mdat より前のボックスのサイズが変わった場合に、サンプルデータの参照位置を合わせるために使う
+pub fn shift_chunk_offsets(stbl_box: &mut StblBox, delta: i64) -> Result<(), String> {
+    let overflow = || {
+        tr!(
+            "チャンクオフセットが表現可能な範囲を超えました",
+            "A chunk offset is out of the representable range"
+        )
+    };
+    match &mut stbl_box.stco_or_co64_box {
+        Either::A(stco_box) => {
+            let delta = i32::try_from(delta).map_err(|_| overflow())?;
+            for offset in &mut stco_box.chunk_offsets {
+                *offset = offset.checked_add_signed(delta).ok_or_else(overflow)?;
+            }
+        }
+        Either::B(co64_box) => {
+            for offset in &mut co64_box.chunk_offsets {
+                *offset = offset.checked_add_signed(delta).ok_or_else(overflow)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// prft ボックス（Producer Reference Time）の内容
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProducerReferenceTime {
+    pub reference_track_id: u32,
+    /// 64 ビット NTP 形式の時刻（上位 32 ビットが 1900 年からの秒数、下位 32 ビットが小数部）
+    pub ntp_timestamp: u64,
+    /// `ntp_timestamp` に対応する参照トラックのメディア時刻（タイムスケール単位）
+    pub media_time: u64,
+}
+
+impl ProducerReferenceTime {
+    /// ボックス種別
+    pub const TYPE: BoxType = BoxType::Normal(*b"prft");
+
+    /// NTP エポック（1900 年）から UNIX エポック（1970 年）までの秒数
+    const NTP_UNIX_EPOCH_OFFSET: f64 = 2_208_988_800.0;
+
+    /// prft ボックスのペイロードを解析する（解析できない場合は `None` を返す）
+    pub fn parse(payload: &[u8]) -> Option<Self> {
+        // バージョン 1 では media_time が 64 ビットになる
+        let version = *payload.first()?;
+        let reference_track_id = u32::from_be_bytes(payload.get(4..8)?.try_into().ok()?);
+        let ntp_timestamp = u64::from_be_bytes(payload.get(8..16)?.try_into().ok()?);
+        let media_time = if version == 0 {
+            u32::from_be_bytes(payload.get(16..20)?.try_into().ok()?) as u64
+        } else {
+            u64::from_be_bytes(payload.get(16..24)?.try_into().ok()?)
+        };
+        Some(Self {
+            reference_track_id,
+            ntp_timestamp,
+            media_time,
+        })
+    }
+
+    /// `ntp_timestamp` を UNIX 時刻（秒）に変換する
+    pub fn unix_time(&self) -> f64 {
+        let seconds = (self.ntp_timestamp >> 32) as f64;
+        let fraction = (self.ntp_timestamp & 0xFFFF_FFFF) as f64 / (1u64 << 32) as f64;
+        seconds + fraction - Self::NTP_UNIX_EPOCH_OFFSET
+    }
+}
+
+/// トップレベルの prft ボックスをすべて読み込む
+///
+/// 解析できない prft ボックスは無視する
+pub fn read_prft_boxes<R: Read + Seek>(
+    reader: &mut R,
+    root_boxes: &[RootBoxLocation],
+) -> Result<Vec<ProducerReferenceTime>, String> {
+    let mut prfts = Vec::new();
+    for location in root_boxes
+        .iter()
+        .filter(|b| b.box_type == ProducerReferenceTime::TYPE)
+    {
+        let mut payload = vec![0; (location.box_size - location.header_size) as usize];
+        reader
+            .seek(SeekFrom::Start(location.payload_offset()))
+            .map_err(|e| {
+                tr!(
+                    "ファイルのシークに失敗しました: {}",
+                    "Failed to seek the file: {}",
+                    e
+                )
+            })?;
+        reader.read_exact(&mut payload).map_err(|e| {
+            tr!(
+                "ファイルの読み込みに失敗しました: {}",
+                "Failed to read the file: {}",
+                e
+            )
+        })?;
+        prfts.extend(ProducerReferenceTime::parse(&payload));
+    }
+    Ok(prfts)
+}
+
+/// ボックスヘッダーを読み込む
+///
+/// 入力の末尾に達している場合は `None` を返す
+fn read_box_header<R: Read>(reader: &mut R) -> Result<Option<(BoxHeader, Vec<u8>)>, String> {
+    let read_error = |e: std::io::Error| {
+        tr!(
+            "ファイルの読み込みに失敗しました: {}",
+            "Failed to read the file: {}",
+            e
+        )
+    };
+
+    let mut bytes = vec![0; BoxHeader::MIN_SIZE];
+    let mut filled = 0;
+    while filled < bytes.len() {
+        match reader.read(&mut bytes[filled..]).map_err(read_error)? {
+            0 if filled == 0 => return Ok(None),
+            0 => {
+                return Err(tr!(
+                    "ボックスヘッダーの途中でファイルが終了しています",
+                    "The file ends in the middle of a box header"
+                ));
+            }
+            n => filled += n,
+        }
+    }
+
+    // 64 ビットサイズおよび uuid の拡張部分を必要に応じて読み込む
+    let mut extra_size = 0;
+    if bytes[..4] == [0, 0, 0, 1] {
+        extra_size += 8;
+    }
+    if bytes[4..8] == *b"uuid" {
+        extra_size += 16;
+    }
+    if extra_size > 0 {
+        let mut extra = vec![0; extra_size];
+        reader.read_exact(&mut extra).map_err(read_error)?;
+        bytes.extend_from_slice(&extra);
+    }
+
+    let (header, _) = BoxHeader::decode(&bytes).map_err(|e| {
+        tr!(
+            "ボックスヘッダーの解析に失敗しました: {}",
+            "Failed to parse a box header: {}",
+            e
+        )
+    })?;
+    Ok(Some((header, bytes)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::Mp4Builder;
+    use shiguredo_mp4::{
+        BoxSize, Encode, TrackKind,
+        boxes::{StcoBox, StscBox, StscEntry, StsdBox, SttsBox, UnknownBox},
+    };
+
+    fn stbl_box_with_ctts(sample_deltas: &[u32], ctts_payload: Vec<u8>) -> StblBox {
+        let sample_count = sample_deltas.len() as u32;
+        StblBox {
+            stsd_box: StsdBox {
+                entries: vec![SampleEntry::Unknown(UnknownBox {
+                    box_type: BoxType::Normal(*b"test"),
+                    box_size: BoxSize::U32(8),
+                    payload: Vec::new(),
+                })],
+            },
+            stts_box: SttsBox::from_sample_deltas(sample_deltas.iter().copied()),
+            stsc_box: StscBox {
+                entries: vec![StscEntry {
+                    first_chunk: NonZeroU32::MIN,
+                    sample_per_chunk: sample_count,
+                    sample_description_index: NonZeroU32::MIN,
+                }],
+            },
+            stsz_box: StszBox::Variable {
+                entry_sizes: vec![1; sample_count as usize],
+            },
+            stco_or_co64_box: Either::A(StcoBox {
+                chunk_offsets: vec![0],
+            }),
+            stss_box: None,
+            unknown_boxes: vec![UnknownBox {
+                box_type: BoxType::Normal(*b"ctts"),
+                box_size: BoxSize::U32(8 + ctts_payload.len() as u32),
+                payload: ctts_payload,
+            }],
+        }
+    }
+
+    fn ctts_payload(version: u8, entries: &[(u32, i32)]) -> Vec<u8> {
+        let mut payload = vec![version, 0, 0, 0];
+        payload.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+        for (count, offset) in entries {
+            payload.extend_from_slice(&count.to_be_bytes());
+            payload.extend_from_slice(&offset.to_be_bytes());
+        }
+        payload
+    }
+
+    #[test]
+    fn test_child_boxes() {
+        let mut payload = encode_raw_box(b"free", &[1, 2]);
+        payload.extend_from_slice(&encode_raw_box(b"skip", &[]));
+        // サイズがペイロードの残りより大きいボックス
+        payload.extend_from_slice(&[0, 0, 0, 100]);
+        payload.extend_from_slice(b"trak");
+
+        let mut children = child_boxes(&payload);
+        assert_eq!(children.next(), Some(Ok((*b"free", &[1, 2][..]))));
+        assert_eq!(children.next(), Some(Ok((*b"skip", &[][..]))));
+        assert!(children.next().unwrap().unwrap_err().contains("trak"));
+        assert_eq!(children.next(), None);
+    }
+
+    #[test]
+    fn test_decode_moov_box_with_stz2() {
+        let sizes = [3u32, 300, 7, 65535];
+        let mut builder = Mp4Builder::new();
+        for &size in &sizes {
+            builder = builder.sample(TrackKind::Audio, 1000, 20, true, &vec![0; size as usize]);
+        }
+        let moov_bytes = builder.moov_box().encode_to_vec().unwrap();
+
+        // stsz を 16 ビットの stz2 に置き換える（親ボックスのサイズを変えないよう末尾を 0 で埋める）
+        let stsz_start = moov_bytes.windows(4).position(|w| w == b"stsz").unwrap() - 4;
+        let stsz_size = 20 + 4 * sizes.len();
+        let mut stz2_box = vec![0, 0, 0, stsz_size as u8];
+        stz2_box.extend_from_slice(b"stz2");
+        stz2_box.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 16]);
+        stz2_box.extend_from_slice(&(sizes.len() as u32).to_be_bytes());
+        for &size in &sizes {
+            stz2_box.extend_from_slice(&(size as u16).to_be_bytes());
+        }
+        stz2_box.resize(stsz_size, 0);
+        let mut stz2_moov_bytes = moov_bytes.clone();
+        stz2_moov_bytes[stsz_start..stsz_start + stsz_size].copy_from_slice(&stz2_box);
+        assert!(MoovBox::decode(&stz2_moov_bytes).is_err());
+
+        let moov_box = decode_moov_box(&stz2_moov_bytes).unwrap();
+        let stbl_box = &moov_box.trak_boxes[0].mdia_box.minf_box.stbl_box;
+        let sample_table = SampleTableAccessor::new(stbl_box).unwrap();
+        assert_eq!(sample_table.sample_count(), sizes.len() as u32);
+        let data_sizes: Vec<u32> = sample_table.samples().map(|s| s.data_size()).collect();
+        assert_eq!(data_sizes, sizes);
+
+        // 4 ビットのフィールドは 1 バイトに 2 サンプル分を上位ビットから格納する
+        let payload = [0, 0, 0, 0, 0, 0, 0, 4, 0, 0, 0, 3, 0x12, 0x30];
+        let stsz_payload = stz2_to_stsz_payload(&payload).unwrap();
+        assert_eq!(&stsz_payload[8..12], &3u32.to_be_bytes());
+        assert_eq!(
+            &stsz_payload[12..],
+            [1u32, 2, 3].map(u32::to_be_bytes).concat()
+        );
+        assert!(stz2_to_stsz_payload(&[0, 0, 0, 0, 0, 0, 0, 8, 0, 0, 0, 3, 1]).is_err());
+    }
+
+    #[test]
+    fn test_decode_mp4_file_reports_failed_box_offset() {
+        let mut buffer = FtypBox {
+            major_brand: shiguredo_mp4::boxes::Brand::ISOM,
+            minor_version: 0,
+            compatible_brands: Vec::new(),
+        }
+        .encode_to_vec()
+        .unwrap();
+        let ftyp_size = buffer.len();
+
+        // ペイロードが途中で切れている free ボックス
+        buffer.extend_from_slice(&[0, 0, 0, 100, b'f', b'r', b'e', b'e', 0, 0]);
+
+        let error = decode_mp4_file(&buffer).err().unwrap();
+        assert!(
+            error.starts_with(&format!("オフセット 0x{:X} 付近", ftyp_size)),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_read_ftyp_box_and_shift_chunk_offsets() {
+        let ftyp_box = FtypBox {
+            major_brand: shiguredo_mp4::boxes::Brand::new(*b"mp42"),
+            minor_version: 1,
+            compatible_brands: vec![shiguredo_mp4::boxes::Brand::new(*b"isom")],
+        };
+        let mut reader = std::io::Cursor::new(ftyp_box.encode_to_vec().unwrap());
+        reader.set_position(4);
+        assert_eq!(read_ftyp_box(&mut reader).unwrap(), ftyp_box);
+
+        let mut stbl_box = stbl_box_with_ctts(&[10], ctts_payload(0, &[]));
+        stbl_box.stco_or_co64_box = Either::A(StcoBox {
+            chunk_offsets: vec![100, 200],
+        });
+        shift_chunk_offsets(&mut stbl_box, -36).unwrap();
+        assert!(matches!(
+            &stbl_box.stco_or_co64_box,
+            Either::A(stco_box) if stco_box.chunk_offsets == [64, 164]
+        ));
+
+        // 先頭より前を指すことになる場合はエラー
+        assert!(shift_chunk_offsets(&mut stbl_box, -100).is_err());
+    }
+
+    #[test]
+    fn test_input_mp4_samples() {
+        let buffer = Mp4Builder::new()
+            .sample(TrackKind::Video, 30, 2, true, b"key")
+            .sample(TrackKind::Video, 30, 2, false, b"delta")
+            .build();
+
+        let input_mp4 = InputMp4::parse(&buffer[..]).unwrap();
+        let samples: Vec<_> = input_mp4.samples(0).unwrap().collect();
+        assert_eq!(samples.len(), 2);
+        assert_eq!(samples[1].dts, 2);
+        assert_eq!(samples[1].pts, 2);
+        assert!(samples[0].is_sync && !samples[1].is_sync);
+        assert_eq!(samples[1].data_size, 5);
+        assert_eq!(&buffer[samples[1].data_offset as usize..][..5], b"delta");
+
+        assert!(input_mp4.samples(1).is_err());
+
+        // ストリーミング解析でも ftyp / moov ボックスをそのまま参照できる
+        let streamed = InputMp4::parse_streaming(&buffer[..], u64::MAX).unwrap();
+        assert_eq!(streamed.ftyp(), input_mp4.ftyp());
+        assert!(streamed.ftyp().is_some());
+        assert_eq!(streamed.moov(), input_mp4.moov());
+        assert_eq!(streamed.mvhd().timescale, input_mp4.mvhd().timescale);
+        assert_eq!(streamed.tracks().len(), 1);
+    }
+
+    #[test]
+    fn test_parse_truncated_file() {
+        let mut muxer = shiguredo_mp4::mux::Mp4FileMuxer::new().unwrap();
+        let (_, ftyp_size) = FtypBox::decode(muxer.initial_boxes_bytes()).unwrap();
+        let ftyp_bytes = muxer.initial_boxes_bytes()[..ftyp_size].to_vec();
+        let moov_bytes = muxer
+            .finalize()
+            .unwrap()
+            .moov_box()
+            .encode_to_vec()
+            .unwrap();
+        // 1000 バイトと宣言しているが、途中までしかない mdat ボックス
+        let truncated_mdat = [&[0, 0, 0x03, 0xE8][..], b"mdat", &[0; 10]].concat();
+
+        // moov ボックスの後ろで切れている場合は moov ボックスの情報を使う
+        let buffer = [&ftyp_bytes[..], &moov_bytes, &truncated_mdat].concat();
+        let input_mp4 = InputMp4::parse(&buffer[..]).unwrap();
+        assert_eq!(input_mp4.moov().encode_to_vec().unwrap(), moov_bytes);
+
+        // moov ボックスより前で切れている場合はその旨のエラー
+        let buffer = [&ftyp_bytes[..], &truncated_mdat, &moov_bytes].concat();
+        let error = InputMp4::parse(&buffer[..]).err().unwrap();
+        assert_eq!(
+            error,
+            format!(
+                "moov ボックスを読み込む前にファイルが途中で切れています（オフセット 0x{:X} の mdat ボックス）",
+                ftyp_size
+            )
+        );
+        let error = InputMp4::parse(&moov_bytes[..4]).err().unwrap();
+        assert!(error.contains("オフセット 0x0 の 不明 ボックス"), "{error}");
+    }
+
+    #[test]
+    fn test_timestamp_gaps() {
+        // 4 番目のサンプルの後に 2 フレーム分、8〜9 番目のサンプルの後に 1 フレーム分ずつ欠落している
+        let stts_box = SttsBox::from_sample_deltas([10, 10, 10, 30, 10, 10, 10, 20, 20, 10, 12]);
+        let gaps = timestamp_gaps(&stts_box);
+        assert_eq!(
+            gaps,
+            [
+                TimestampGap {
+                    sample_index: 4,
+                    sample_count: 1,
+                    timestamp: 30,
+                    sample_delta: 30,
+                    expected_delta: 10,
+                },
+                TimestampGap {
+                    sample_index: 8,
+                    sample_count: 2,
+                    timestamp: 90,
+                    sample_delta: 20,
+                    expected_delta: 10,
+                },
+            ]
+        );
+        assert_eq!(gaps[0].gap_duration(), 20);
+        assert_eq!(gaps[1].gap_duration(), 20);
+        assert_eq!(
+            gaps[1].describe(100),
+            "サンプル 8〜9 (0.900秒) の間隔が 0.200秒（通常 0.100秒）で、計 0.200秒 欠落しています"
+        );
+
+        assert!(timestamp_gaps(&SttsBox::from_sample_deltas([1001; 5])).is_empty());
+        assert!(
+            timestamp_gaps(&SttsBox {
+                entries: Vec::new()
+            })
+            .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_get_track_infos_without_tracks() {
+        // トラックを一つも含まない moov ボックスを持つファイル
+        let mut muxer = shiguredo_mp4::mux::Mp4FileMuxer::new().unwrap();
+        let (_, ftyp_size) = FtypBox::decode(muxer.initial_boxes_bytes()).unwrap();
+        let mut buffer = muxer.initial_boxes_bytes()[..ftyp_size].to_vec();
+        let moov_box = muxer.finalize().unwrap().moov_box().clone();
+        assert!(moov_box.trak_boxes.is_empty());
+        buffer.extend_from_slice(&moov_box.encode_to_vec().unwrap());
+
+        let input_mp4 = InputMp4::parse(&buffer[..]).unwrap();
+        assert!(
+            input_mp4
+                .get_track_infos()
+                .is_some_and(|tracks| tracks.is_empty())
+        );
+        assert!(input_mp4.samples(0).is_err());
+    }
+
+    #[test]
+    fn test_codec_details_av1() {
+        use shiguredo_mp4::{
+            Uint,
+            boxes::{Av01Box, Av1cBox, VisualSampleEntryFields},
+        };
+
+        let sample_entry = SampleEntry::Av01(Av01Box {
+            visual: VisualSampleEntryFields {
+                data_reference_index: VisualSampleEntryFields::DEFAULT_DATA_REFERENCE_INDEX,
+                width: 1920,
+                height: 1080,
+                horizresolution: VisualSampleEntryFields::DEFAULT_HORIZRESOLUTION,
+                vertresolution: VisualSampleEntryFields::DEFAULT_VERTRESOLUTION,
+                frame_count: VisualSampleEntryFields::DEFAULT_FRAME_COUNT,
+                compressorname: [0; 32],
+                depth: 0x18,
+            },
+            av1c_box: Av1cBox {
+                seq_profile: Uint::new(0),
+                seq_level_idx_0: Uint::new(8),
+                seq_tier_0: Uint::new(0),
+                high_bitdepth: Uint::new(1),
+                twelve_bit: Uint::new(0),
+                monochrome: Uint::new(0),
+                chroma_subsampling_x: Uint::new(1),
+                chroma_subsampling_y: Uint::new(1),
+                chroma_sample_position: Uint::new(0),
+                initial_presentation_delay_minus_one: None,
+                config_obus: Vec::new(),
+            },
+            unknown_boxes: Vec::new(),
+        });
+
+        let details = codec_details(&sample_entry).unwrap();
+        assert_eq!(details.to_string(), "Main Profile, Level 4.0, 10-bit");
+    }
+
+    #[test]
+    fn test_parameter_sets_avc() {
+        use shiguredo_mp4::{
+            Uint,
+            boxes::{Avc1Box, AvccBox, VisualSampleEntryFields},
+        };
+
+        let sample_entry = SampleEntry::Avc1(Avc1Box {
+            visual: VisualSampleEntryFields {
+                data_reference_index: VisualSampleEntryFields::DEFAULT_DATA_REFERENCE_INDEX,
+                width: 1280,
+                height: 720,
+                horizresolution: VisualSampleEntryFields::DEFAULT_HORIZRESOLUTION,
+                vertresolution: VisualSampleEntryFields::DEFAULT_VERTRESOLUTION,
+                frame_count: VisualSampleEntryFields::DEFAULT_FRAME_COUNT,
+                compressorname: [0; 32],
+                depth: 0x18,
+            },
+            avcc_box: AvccBox {
+                avc_profile_indication: 66,
+                profile_compatibility: 0xc0,
+                avc_level_indication: 31,
+                length_size_minus_one: Uint::new(3),
+                sps_list: vec![vec![0x67, 0x42, 0xc0, 0x1f], vec![0x67, 0x42, 0xc0, 0x20]],
+                pps_list: vec![vec![0x68, 0xce, 0x3c, 0x80]],
+                chroma_format: None,
+                bit_depth_luma_minus8: None,
+                bit_depth_chroma_minus8: None,
+                sps_ext_list: Vec::new(),
+            },
+            unknown_boxes: Vec::new(),
+        });
+
+        let sets = parameter_sets(&sample_entry);
+        let names: Vec<_> = sets.iter().map(|s| (s.name, s.nal_unit_type)).collect();
+        assert_eq!(names, [("SPS", 7), ("SPS", 7), ("PPS", 8)]);
+        assert_eq!(sets[1].data, [0x67, 0x42, 0xc0, 0x20]);
+    }
+
+    #[test]
+    fn test_parameter_sets_hvc1() {
+        // shiguredo_mp4 は hvc1 を未知のサンプルエントリーとして扱うため、hvcC はペイロードから取り出す
+        // Main Profile, Level 3.1, lengthSizeMinusOne 3 の hvcC に VPS 1 つと SPS 2 つを格納する
+        let mut hvcc = vec![
+            0x01, 0x01, 0x60, 0x00, 0x00, 0x00, 0x90, 0x00, 0x00, 0x00, 0x00, 0x00, 0x5d, 0xf0,
+            0x00, 0xfc, 0xfd, 0xf8, 0xf8, 0x00, 0x00, 0x0f, 0x02,
+        ];
+        hvcc.extend_from_slice(&[0x20, 0x00, 0x01, 0x00, 0x02, 0x40, 0x01]);
+        hvcc.extend_from_slice(&[
+            0x21, 0x00, 0x02, 0x00, 0x02, 0x42, 0x01, 0x00, 0x02, 0x42, 0x02,
+        ]);
+        let mut payload = vec![0; VISUAL_SAMPLE_ENTRY_FIELDS_SIZE];
+        payload.extend_from_slice(&encode_raw_box(b"hvcC", &hvcc));
+        let sample_entry = SampleEntry::Unknown(UnknownBox {
+            box_type: BoxType::Normal(*b"hvc1"),
+            box_size: BoxSize::U32(8 + payload.len() as u32),
+            payload,
+        });
+
+        let sets = parameter_sets(&sample_entry);
+        let names: Vec<_> = sets.iter().map(|s| (s.name, s.nal_unit_type)).collect();
+        assert_eq!(names, [("VPS", 32), ("SPS", 33), ("SPS", 33)]);
+        assert_eq!(sets[2].data, [0x42, 0x02]);
+    }
+
+    #[test]
+    fn test_build_ctts_box_with_negative_offsets() {
+        // 負のオフセットを含む場合は符号付きのバージョン 1 になり、解析すると元の値に戻る
+        let offsets = [2, -1, -1, 2, -1];
+        let ctts_box = build_ctts_box(&offsets).unwrap().unwrap();
+        assert_eq!(ctts_box.payload[0], 1);
+        let stbl_box = stbl_box_with_ctts(&[1; 5], ctts_box.payload);
+        let entries = parse_ctts_entries(&stbl_box).unwrap();
+        assert_eq!(entries.len(), 4);
+        assert_eq!(composition_offsets(&entries).collect::<Vec<_>>(), offsets);
+
+        // 0 以上のみの場合はバージョン 0
+        let ctts_box = build_ctts_box(&[1, 0, 0]).unwrap().unwrap();
+        assert_eq!(ctts_box.payload[0], 0);
+        assert_eq!(build_ctts_box(&[0, 0]), Ok(None));
+        assert!(build_ctts_box(&[-1, i32::MAX as i64 + 1]).is_err());
+    }
+
+    #[test]
+    fn test_handler_name() {
+        assert_eq!(
+            handler_name(b"SoundHandler\0").as_deref(),
+            Some("SoundHandler")
+        );
+        assert_eq!(
+            handler_name(b"\x0cVideoHandler").as_deref(),
+            Some("VideoHandler")
+        );
+        assert_eq!(handler_name(b"Caf\xe9\0").as_deref(), Some("Caf\u{fffd}"));
+        assert_eq!(handler_name(b"\0"), None);
+        assert_eq!(handler_name(b""), None);
+    }
+
+    #[test]
+    fn test_itunes_gapless_info() {
+        fn boxed(box_type: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+            let mut bytes = (8 + payload.len() as u32).to_be_bytes().to_vec();
+            bytes.extend_from_slice(box_type);
+            bytes.extend_from_slice(payload);
+            bytes
+        }
+
+        let smpb = b" 00000000 00000840 00000240 00000000003F31F6 00000000";
+        let item = [
+            boxed(b"mean", b"\0\0\0\0com.apple.iTunes"),
+            boxed(b"name", b"\0\0\0\0iTunSMPB"),
+            boxed(b"data", &[&[0, 0, 0, 1, 0, 0, 0, 0][..], smpb].concat()),
+        ]
+        .concat();
+        let ilst = boxed(b"ilst", &boxed(b"----", &item));
+        let meta = boxed(b"meta", &[&[0, 0, 0, 0][..], &ilst].concat());
+        let udta = UnknownBox {
+            box_type: BoxType::Normal(*b"udta"),
+            box_size: BoxSize::U32(8 + meta.len() as u32),
+            payload: meta,
+        };
+        assert_eq!(
+            itunes_gapless_info(std::slice::from_ref(&udta)),
+            Some(EncoderDelay {
+                delay: 2112,
+                padding: 576
+            })
+        );
+        assert_eq!(itunes_gapless_info(&[]), None);
+    }
+
+    #[test]
+    fn test_edit_list_with_empty_and_media_edits() {
+        let mut trak_box = Mp4Builder::new()
+            .track(TrackKind::Video, 30, &[1; 90], 90)
+            .moov_box()
+            .trak_boxes[0]
+            .clone();
+        let movie_timescale = NonZeroU32::new(1000).unwrap();
+
+        // 1 秒の空の編集の後に、メディアの 0.5 秒目から 2 秒間を表示する編集
+        trak_box.edts_box = Some(shiguredo_mp4::boxes::EdtsBox {
+            elst_box: Some(shiguredo_mp4::boxes::ElstBox {
+                entries: vec![
+                    shiguredo_mp4::boxes::ElstEntry {
+                        edit_duration: 1000,
+                        media_time: -1,
+                        media_rate: shiguredo_mp4::FixedPointNumber::new(1, 0),
+                    },
+                    shiguredo_mp4::boxes::ElstEntry {
+                        edit_duration: 2000,
+                        media_time: 15,
+                        media_rate: shiguredo_mp4::FixedPointNumber::new(1, 0),
+                    },
+                ],
+            }),
+            unknown_boxes: Vec::new(),
+        });
+        assert_eq!(initial_empty_edit_duration(&trak_box), Some(1000));
+
+        // 表示上の 2 秒目は、空の編集の 1 秒を除いたメディア編集の 1 秒目（メディアの 1.5 秒目）
+        assert_eq!(
+            presentation_to_media_time(&trak_box, movie_timescale, 2.0),
+            45
+        );
+        // 空の編集の範囲はメディア編集の先頭に対応する
+        assert_eq!(
+            presentation_to_media_time(&trak_box, movie_timescale, 0.5),
+            15
+        );
+        assert_eq!(
+            media_to_presentation_time(&trak_box, movie_timescale, 45),
+            2.0
+        );
+        assert_eq!(
+            media_to_presentation_time(&trak_box, movie_timescale, 15),
+            1.0
+        );
+        // メディアの 0.5 秒目から 2 秒間を表示するので、先頭の 15 と末尾の 15 が捨てられる
+        assert_eq!(
+            edit_list_encoder_delay(&trak_box, movie_timescale),
+            Some(EncoderDelay {
+                delay: 15,
+                padding: 15
+            })
+        );
+
+        // 編集リストがない場合は表示上の時刻とメディア時刻が一致する
+        trak_box.edts_box = None;
+        assert_eq!(initial_empty_edit_duration(&trak_box), None);
+        assert_eq!(edit_list_encoder_delay(&trak_box, movie_timescale), None);
+        assert_eq!(
+            presentation_to_media_time(&trak_box, movie_timescale, 2.0),
+            60
+        );
+        assert_eq!(
+            media_to_presentation_time(&trak_box, movie_timescale, 60),
+            2.0
+        );
+        // 4.1 * 30 は浮動小数点数では 123 をわずかに下回るが、123 として扱う
+        assert_eq!(
+            presentation_to_media_time(&trak_box, movie_timescale, 4.1),
+            123
+        );
+        // フレームの途中の時刻は切り捨てる
+        assert_eq!(
+            presentation_to_media_time(&trak_box, movie_timescale, 4.12),
+            123
+        );
+    }
+
+    #[test]
+    fn test_unknown_box_paths() {
+        let mut moov_box = Mp4Builder::new()
+            .track(TrackKind::Video, 30, &[1], 1)
+            .moov_box();
+        let unknown_box = |box_type: &[u8; 4]| UnknownBox {
+            box_type: BoxType::Normal(*box_type),
+            box_size: BoxSize::U32(8),
+            payload: Vec::new(),
+        };
+        moov_box.unknown_boxes.push(unknown_box(b"udta"));
+        // このツールが直接解釈する ctts は含めない
+        let stbl_box = &mut moov_box.trak_boxes[0].mdia_box.minf_box.stbl_box;
+        stbl_box.unknown_boxes.push(unknown_box(b"ctts"));
+        stbl_box.unknown_boxes.push(unknown_box(b"sdtp"));
+
+        assert_eq!(
+            unknown_box_paths(&moov_box),
+            [
+                "moov/udta",
+                "moov/trak[1]/mdia/minf/stbl/sdtp",
+                "moov/trak[1]/mdia/minf/stbl/stsd/test",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_external_data_reference() {
+        let mut trak_box = Mp4Builder::new()
+            // 予約領域の後ろに data_reference_index = 1
+            .sample_entry(SampleEntry::Unknown(UnknownBox {
+                box_type: BoxType::Normal(*b"test"),
+                box_size: BoxSize::U32(16),
+                payload: vec![0, 0, 0, 0, 0, 0, 0, 1],
+            }))
+            .track(TrackKind::Video, 30, &[1], 1)
+            .moov_box()
+            .trak_boxes[0]
+            .clone();
+        assert_eq!(external_data_reference(&trak_box), None);
+
+        let dref_box = &mut trak_box.mdia_box.minf_box.dinf_box.dref_box;
+        dref_box.url_box = Some(shiguredo_mp4::boxes::UrlBox {
+            location: shiguredo_mp4::Utf8String::new("media.mp4"),
+        });
+        assert_eq!(
+            external_data_reference(&trak_box).as_deref(),
+            Some("url \"media.mp4\"")
+        );
+
+        // url ボックスがなく、urn ボックスのみの場合
+        let dref_box = &mut trak_box.mdia_box.minf_box.dinf_box.dref_box;
+        dref_box.url_box = None;
+        dref_box.unknown_boxes.push(UnknownBox {
+            box_type: BoxType::Normal(*b"urn "),
+            box_size: BoxSize::U32(20),
+            payload: b"\0\0\0\0urn:x\0\0".to_vec(),
+        });
+        assert_eq!(
+            external_data_reference(&trak_box).as_deref(),
+            Some("urn \"urn:x\"")
+        );
+
+        // dref に存在しないエントリを参照している場合
+        trak_box
+            .mdia_box
+            .minf_box
+            .dinf_box
+            .dref_box
+            .unknown_boxes
+            .clear();
+        assert!(external_data_reference(&trak_box).is_some());
+    }
+
+    #[test]
+    fn test_producer_reference_time() {
+        // バージョン 0、トラック 1、1970-01-01T00:00:01.5Z、メディア時刻 90000
+        let mut payload = vec![0, 0, 0, 0, 0, 0, 0, 1];
+        payload.extend_from_slice(&((2_208_988_801u64 << 32) | (1 << 31)).to_be_bytes());
+        payload.extend_from_slice(&90000u32.to_be_bytes());
+        let prft = ProducerReferenceTime::parse(&payload).unwrap();
+        assert_eq!(prft.reference_track_id, 1);
+        assert_eq!(prft.media_time, 90000);
+        assert_eq!(prft.unix_time(), 1.5);
+
+        // バージョン 1 では media_time が 64 ビット
+        payload[0] = 1;
+        assert_eq!(ProducerReferenceTime::parse(&payload), None);
+        payload.splice(16.., (1u64 << 40).to_be_bytes());
+        assert_eq!(
+            ProducerReferenceTime::parse(&payload).unwrap().media_time,
+            1 << 40
+        );
+    }
+
+    #[test]
+    fn test_max_seek_distance() {
+        // 音声と映像が交互に並んでいる場合はシークが発生しない
+        let reads = vec![(0, 0, 10), (0, 10, 5), (1, 15, 10), (1, 25, 5)];
+        assert_eq!(max_seek_distance(reads), 0);
+
+        // 映像の後ろに音声がまとめて配置されている場合
+        // 時刻順の読み込み位置は 0, 100, 10, 105 で、前方へのシークの最大は 10 → 100
+        let reads = vec![(0, 0, 10), (1, 10, 10), (0, 100, 5), (1, 105, 5)];
+        assert_eq!(max_seek_distance(reads), 90);
+
+        // 後方へのシークは含めない
+        let reads = vec![(0, 100, 10), (1, 0, 10), (2, 10, 10)];
+        assert_eq!(max_seek_distance(reads), 0);
+    }
+
+    #[test]
+    fn test_presentation_duration_with_composition_offsets() {
+        // I P B B 相当: 表示順は 0, 3, 1, 2 番目のサンプル
+        let stbl_box = stbl_box_with_ctts(
+            &[10, 10, 10, 10],
+            ctts_payload(0, &[(1, 10), (1, 30), (2, 0)]),
+        );
+        let entries = parse_ctts_entries(&stbl_box).unwrap();
+        assert_eq!(entries.len(), 3);
+
+        let sample_table = SampleTableAccessor::new(&stbl_box).unwrap();
+        // 表示時刻は 10, 40, 20, 30 なので 10..50 の範囲になる
+        assert_eq!(
+            composition_time_range(&sample_table, &entries),
+            Some((10, 50))
+        );
+    }
+
+    #[test]
+    fn test_presentation_duration_with_edit_list() {
+        let mut trak_box = Mp4Builder::new()
+            .track(TrackKind::Video, 30, &[1; 90], 90)
+            .moov_box()
+            .trak_boxes[0]
+            .clone();
+        let movie_timescale = NonZeroU32::new(1000).unwrap();
+        assert_eq!(
+            presentation_duration(&trak_box, movie_timescale, (0, 90)),
+            90
+        );
+
+        // 1 秒の空の編集の後に、メディアの 0.5 秒目から 2 秒間を表示する
+        let edit = |edit_duration, media_time| shiguredo_mp4::boxes::ElstEntry {
+            edit_duration,
+            media_time,
+            media_rate: shiguredo_mp4::FixedPointNumber::new(1, 0),
+        };
+        trak_box.edts_box = Some(shiguredo_mp4::boxes::EdtsBox {
+            elst_box: Some(shiguredo_mp4::boxes::ElstBox {
+                entries: vec![edit(1000, -1), edit(2000, 15)],
+            }),
+            unknown_boxes: Vec::new(),
+        });
+        assert_eq!(
+            presentation_duration(&trak_box, movie_timescale, (0, 90)),
+            90
+        );
+
+        // 長さ 0 の編集はメディアの末尾（2.5 秒分）まで表示する
+        trak_box.edts_box.as_mut().unwrap().elst_box = Some(shiguredo_mp4::boxes::ElstBox {
+            entries: vec![edit(1000, -1), edit(0, 15)],
+        });
+        assert_eq!(
+            presentation_duration(&trak_box, movie_timescale, (0, 90)),
+            105
+        );
+    }
+
+    #[test]
+    fn test_resource_limits() {
+        let mut moov_box = Mp4Builder::new()
+            .track(TrackKind::Audio, 1000, &[20; 3], 1)
+            .moov_box();
+
+        let limits = |max_tracks, max_samples| ResourceLimits {
+            max_tracks,
+            max_samples,
+        };
+        assert!(limits(1, 3).check(&moov_box).is_ok());
+        assert!(limits(0, 3).check(&moov_box).is_err());
+        assert!(limits(1, 2).check(&moov_box).is_err());
+
+        // 固定サイズの stsz ボックスで膨大なサンプル数を宣言した場合
+        moov_box.trak_boxes[0].mdia_box.minf_box.stbl_box.stsz_box = StszBox::Fixed {
+            sample_size: NonZeroU32::MIN,
+            sample_count: u32::MAX,
+        };
+        let error = limits(1, 100_000_000).check(&moov_box).unwrap_err();
+        assert!(error.contains("--max-samples"), "{error}");
+    }
+
+    #[test]
+    fn test_read_moov_box_with_oversized_box() {
+        // 64 ビットのサイズに i64 で表せない値を持つ free ボックス
+        let mut data = 1u32.to_be_bytes().to_vec();
+        data.extend_from_slice(b"free");
+        data.extend_from_slice(&u64::MAX.to_be_bytes());
+        let error = read_moov_box(&mut std::io::Cursor::new(&data)).unwrap_err();
+        assert!(error.contains("大きすぎます"), "{error}");
+    }
 }
@@ -1,16 +1,35 @@
 use std::{
-    io::{Cursor, Read, Seek, SeekFrom, Write},
+    io::{BufReader, Cursor, Read, Seek, SeekFrom, Write},
     num::NonZeroU32,
+    path::PathBuf,
 };
 
+use sha2::{Digest, Sha256};
+
 use shiguredo_mp4::{
-    Decode, Encode, Mp4File, TrackKind,
-    aux::SampleTableAccessor,
-    boxes::{RootBox, SampleEntry, TrakBox},
-    mux::{Mp4FileMuxer, Mp4FileMuxerOptions, Sample, estimate_maximum_moov_box_size},
+    Decode, Encode, FixedPointNumber, TrackKind,
+    aux::{SampleAccessor, SampleTableAccessor},
+    boxes::{
+        Brand, EdtsBox, ElstBox, ElstEntry, FtypBox, MoovBox, MvhdBox, SampleEntry, StblBox,
+        TrakBox,
+    },
+    mux::{
+        FinalizedBoxes, Mp4FileMuxer, Mp4FileMuxerOptions, Sample, estimate_maximum_moov_box_size,
+    },
 };
 
-use crate::io::{InputSource, OutputSink};
+use crate::fragment::{
+    build_init_segment, build_media_segment, defragment_moov_box, is_fragmented,
+};
+use crate::io::{InputSource, OutputSink, OutputWriter, ReadSeek, WriteSeek, parse_byte_size};
+use crate::json;
+use crate::mp4::{
+    DEFAULT_MAX_SAMPLES, DEFAULT_MAX_TRACKS, InputMp4, ResourceLimits, build_ctts_box,
+    composition_offsets, external_data_reference, media_to_presentation_time, parse_ctts_entries,
+    presentation_to_media_time, read_ftyp_box, read_moov_box, scan_root_boxes, shift_chunk_offsets,
+};
+use crate::subcommand_info::print_mp4_info;
+use crate::tr;
 
 const START_OPT: noargs::OptSpec = noargs::opt("start")
     .short('s')
@@ -20,16 +39,144 @@ const START_OPT: noargs::OptSpec = noargs::opt("start")
 
 const END_OPT: noargs::OptSpec = noargs::opt("end")
     .short('e')
-    .doc("終了秒数")
+    .doc("終了秒数（省略時はファイル末尾まで）")
     .ty("SECONDS")
     .example("30.0");
 
+const START_PCT_OPT: noargs::OptSpec = noargs::opt("start-pct")
+    .doc("開始位置をファイルの長さに対する割合（0〜100）で指定")
+    .ty("PERCENT")
+    .example("33");
+
+const END_PCT_OPT: noargs::OptSpec = noargs::opt("end-pct")
+    .doc("終了位置をファイルの長さに対する割合（0〜100）で指定（省略時はファイル末尾まで）")
+    .ty("PERCENT")
+    .example("66");
+
+const START_FRAME_OPT: noargs::OptSpec = noargs::opt("start-frame")
+    .doc("開始位置をビデオのフレーム番号（0 始まり）で指定（フレームレートが一定のビデオのみ。開始位置は --start と同様にキーフレームに調整され、オーディオは同じ時刻の範囲を抽出）")
+    .ty("FRAME")
+    .example("300");
+
+const END_FRAME_OPT: noargs::OptSpec = noargs::opt("end-frame")
+    .doc("終了位置をビデオのフレーム番号（0 始まり。このフレームを含む）で指定（省略時はファイル末尾まで）")
+    .ty("FRAME")
+    .example("599");
+
+const START_TOLERANCE_OPT: noargs::OptSpec = noargs::opt("start-tolerance")
+    .doc("キーフレームへの調整で開始位置が指定より前にずれる量の上限（秒）。超える場合はエラーにします（省略時は無制限）")
+    .ty("SECONDS")
+    .example("2.0");
+
 const OUTPUT_OPT: noargs::OptSpec = noargs::opt("output")
     .short('o')
     .doc("出力ファイルパス（省略時は stdout）")
     .ty("PATH")
     .example("output.mp4");
 
+const START_SAMPLE_OPT: noargs::OptSpec = noargs::opt("start-sample")
+    .doc("開始サンプル番号（1 始まり。--track と組み合わせて使用し、キーフレーム調整は行わない）")
+    .ty("INDEX")
+    .example("100");
+
+const END_SAMPLE_OPT: noargs::OptSpec = noargs::opt("end-sample")
+    .doc("終了サンプル番号（1 始まり。このサンプルを含む）")
+    .ty("INDEX")
+    .example("400");
+
+const TRACK_OPT: noargs::OptSpec = noargs::opt("track")
+    .short('t')
+    .doc("抽出するトラックの番号（info で表示される 1 始まりの番号）")
+    .ty("TRACK")
+    .example("1");
+
+const MAP_OPT: noargs::OptSpec = noargs::opt("map")
+    .doc("抽出するトラックを「種別:種別内の番号」で指定（v: ビデオ、a: オーディオ。省略時は全トラック）")
+    .ty("SPEC")
+    .example("v:0,a:1");
+
+const AUDIO_LANG_OPT: noargs::OptSpec = noargs::opt("audio-lang")
+    .doc("mdhd ボックスの言語コード（ISO 639-2）が一致するオーディオトラックと、全てのビデオトラックを抽出します")
+    .ty("LANG")
+    .example("jpn");
+
+const JSON_FLAG: noargs::FlagSpec = noargs::flag("json")
+    .doc("抽出結果を JSON で出力します（出力先が stdout の場合は stderr に出力）");
+
+const NO_CLAMP_FLAG: noargs::FlagSpec = noargs::flag("no-clamp")
+    .doc("終了秒数がファイルの長さを超える場合に、最後のサンプルまでに丸めずエラーにします");
+
+const END_AT_KEYFRAME_FLAG: noargs::FlagSpec = noargs::flag("end-at-keyframe").doc(
+    "ビデオトラックの終了位置を、終了秒数以前の最後のキーフレームの直前に合わせます（末尾の不完全な GOP を含めない）",
+);
+
+const INFO_AFTER_FLAG: noargs::FlagSpec = noargs::flag("info-after")
+    .doc("抽出の完了後に出力ファイルを解析し、info と同じ形式で情報を表示します");
+
+const COPY_BRAND_FLAG: noargs::FlagSpec = noargs::flag("copy-brand").doc(
+    "入力ファイルの ftyp ボックス（メジャーブランド・互換ブランド）を出力にそのまま使用します",
+);
+
+const NO_COPY_BRAND_FLAG: noargs::FlagSpec = noargs::flag("no-copy-brand")
+    .doc("入力ファイルの ftyp ボックスを使用せず、--container に従ったブランドを出力します（デフォルト）");
+
+const CONTAINER_OPT: noargs::OptSpec = noargs::opt("container")
+    .doc("出力の ftyp のブランド（auto: オーディオトラックのみの場合は m4a、mp4: Muxer 標準の互換ブランド、m4a: メジャーブランドを「M4A 」にして音楽プレイヤーに音声ファイルとして認識させる）")
+    .ty("CONTAINER")
+    .default("auto");
+
+const FASTSTART_FLAG: noargs::FlagSpec =
+    noargs::flag("faststart").doc("moov ボックスをファイル先頭に配置します（デフォルト）");
+
+const NO_FASTSTART_FLAG: noargs::FlagSpec =
+    noargs::flag("no-faststart").doc("moov ボックスをファイル末尾に配置します");
+
+const PRESET_OPT: noargs::OptSpec = noargs::opt("preset")
+    .doc("出力設定のプリセット（web: faststart を有効にして標準の互換ブランドを使用、archive: faststart を無効にして入力の ftyp をそのまま使用）。個別のオプションを指定した場合はそちらが優先されます")
+    .ty("PRESET")
+    .example("web");
+
+const SIDECAR_FLAG: noargs::FlagSpec = noargs::flag("sidecar").doc(
+    "抽出元・抽出範囲・トラックの対応・チェックサム（SHA-256）を記録した「<出力ファイル>.json」を書き出します",
+);
+
+const VERIFY_OUTPUT_FLAG: noargs::FlagSpec = noargs::flag("verify-output").doc(
+    "書き込み後に出力を読み直し、すべてのサンプルのデータが抽出元と一致することを SHA-256 で確認します（不一致があればエラー）",
+);
+
+const COUNT_ONLY_FLAG: noargs::FlagSpec = noargs::flag("count-only").doc(
+    "抽出は行わず、範囲に含まれるサンプル数をトラックごとに「入力のトラック番号 サンプル数」の形式で 1 行ずつ出力します",
+);
+
+const SPLIT_INIT_FLAG: noargs::FlagSpec = noargs::flag("split-init").doc(
+    "出力ファイルの代わりに、fMP4 の初期化セグメント（<出力ファイル名>.init.mp4）とメディアセグメント（<出力ファイル名>.m4s）を書き出します",
+);
+
+const OUTPUT_TIMESCALE_VIDEO_OPT: noargs::OptSpec = noargs::opt("output-timescale-video")
+    .doc("出力のビデオトラックのタイムスケール（省略時は入力と同じ。例: 90000）")
+    .ty("TIMESCALE");
+
+const OUTPUT_TIMESCALE_AUDIO_OPT: noargs::OptSpec = noargs::opt("output-timescale-audio")
+    .doc("出力のオーディオトラックのタイムスケール（省略時は入力と同じ。通常はサンプリングレート）")
+    .ty("TIMESCALE");
+
+const PTS_OFFSET_OPT: noargs::OptSpec = noargs::opt("pts-offset")
+    .doc("出力のタイムスタンプの扱い（zero: 先頭を 0 にそろえる、preserve: 編集リストで入力ファイル上の時刻を維持する）")
+    .ty("MODE")
+    .default("zero");
+
+/// 入出力のバッファサイズのデフォルト値
+///
+/// 72 MB のファイル全体の抽出では、ローカルのディスク（ページキャッシュ上）で 8 KiB〜8 MiB の間に
+/// 有意な差はなかった（いずれも約 0.1 秒）。システムコールの回数が効くネットワーク越しのストレージを考慮して、
+/// 大きめの 1 MiB にしている
+const DEFAULT_BUFFER_SIZE: &str = "1M";
+
+const BUFFER_SIZE_OPT: noargs::OptSpec = noargs::opt("buffer-size")
+    .doc("サンプルデータの読み書きに使うバッファのサイズ（K/M/G の接尾辞に対応）。低速なストレージやネットワーク越しのファイルでは大きくすると速くなります")
+    .ty("BYTES")
+    .default(DEFAULT_BUFFER_SIZE);
+
 pub fn run(mut args: noargs::RawArgs) -> noargs::Result<()> {
     let input_file_arg: Option<String> = noargs::arg("[INPUT_FILE]")
         .example("/path/to/input.mp4")
@@ -38,14 +185,95 @@ pub fn run(mut args: noargs::RawArgs) -> noargs::Result<()> {
         .then(|a| a.value().parse())
         .ok();
 
-    let start_sec: f64 = START_OPT.take(&mut args).then(|o| o.value().parse())?;
+    let start_sec: Option<f64> = START_OPT
+        .take(&mut args)
+        .present_and_then(|o| o.value().parse())?;
 
-    let end_sec: f64 = END_OPT.take(&mut args).then(|o| o.value().parse())?;
+    let end_sec: Option<f64> = END_OPT
+        .take(&mut args)
+        .present_and_then(|o| o.value().parse())?;
 
-    let output_file_arg: Option<String> = OUTPUT_OPT
+    let start_pct: Option<f64> = START_PCT_OPT
         .take(&mut args)
-        .then(|o| o.value().parse())
-        .ok();
+        .present_and_then(|o| o.value().parse())?;
+
+    let end_pct: Option<f64> = END_PCT_OPT
+        .take(&mut args)
+        .present_and_then(|o| o.value().parse())?;
+
+    let start_frame: Option<u32> = START_FRAME_OPT
+        .take(&mut args)
+        .present_and_then(|o| o.value().parse())?;
+
+    let end_frame: Option<u32> = END_FRAME_OPT
+        .take(&mut args)
+        .present_and_then(|o| o.value().parse())?;
+
+    let start_tolerance: Option<f64> = START_TOLERANCE_OPT
+        .take(&mut args)
+        .present_and_then(|o| o.value().parse())?;
+
+    let start_sample: Option<NonZeroU32> = START_SAMPLE_OPT
+        .take(&mut args)
+        .present_and_then(|o| o.value().parse())?;
+
+    let end_sample: Option<NonZeroU32> = END_SAMPLE_OPT
+        .take(&mut args)
+        .present_and_then(|o| o.value().parse())?;
+
+    let track_number: Option<NonZeroU32> = TRACK_OPT
+        .take(&mut args)
+        .present_and_then(|o| o.value().parse())?;
+
+    let output_file_arg: Option<String> =
+        OUTPUT_OPT.take(&mut args).then(|o| o.value().parse()).ok();
+
+    let stream_map: Option<Vec<StreamSpec>> = MAP_OPT
+        .take(&mut args)
+        .present_and_then(|o| parse_stream_map(o.value()))?;
+    let audio_lang: Option<[u8; 3]> = AUDIO_LANG_OPT
+        .take(&mut args)
+        .present_and_then(|o| parse_language_code(o.value()))?;
+
+    let preset: Option<Preset> = PRESET_OPT
+        .take(&mut args)
+        .present_and_then(|o| parse_preset(o.value()))?;
+
+    let pts_offset: PtsOffset = PTS_OFFSET_OPT
+        .take(&mut args)
+        .then(|o| parse_pts_offset(o.value()))?;
+
+    let container: Container = CONTAINER_OPT
+        .take(&mut args)
+        .then(|o| parse_container(o.value()))?;
+
+    let buffer_size: u64 = BUFFER_SIZE_OPT
+        .take(&mut args)
+        .then(|o| parse_byte_size(o.value()))?;
+
+    let output_timescales = OutputTimescales {
+        video: OUTPUT_TIMESCALE_VIDEO_OPT
+            .take(&mut args)
+            .present_and_then(|o| o.value().parse())?,
+        audio: OUTPUT_TIMESCALE_AUDIO_OPT
+            .take(&mut args)
+            .present_and_then(|o| o.value().parse())?,
+    };
+
+    let limits = ResourceLimits::take(&mut args)?;
+
+    let copy_brand_flag = COPY_BRAND_FLAG.take(&mut args).is_present();
+    let no_copy_brand_flag = NO_COPY_BRAND_FLAG.take(&mut args).is_present();
+    let faststart_flag = FASTSTART_FLAG.take(&mut args).is_present();
+    let no_faststart_flag = NO_FASTSTART_FLAG.take(&mut args).is_present();
+    let json = JSON_FLAG.take(&mut args).is_present();
+    let no_clamp = NO_CLAMP_FLAG.take(&mut args).is_present();
+    let end_at_keyframe = END_AT_KEYFRAME_FLAG.take(&mut args).is_present();
+    let info_after = INFO_AFTER_FLAG.take(&mut args).is_present();
+    let sidecar = SIDECAR_FLAG.take(&mut args).is_present();
+    let split_init = SPLIT_INIT_FLAG.take(&mut args).is_present();
+    let verify_output = VERIFY_OUTPUT_FLAG.take(&mut args).is_present();
+    let count_only = COUNT_ONLY_FLAG.take(&mut args).is_present();
 
     if let Some(help) = args.finish()? {
         print!("{help}");
@@ -53,175 +281,608 @@ pub fn run(mut args: noargs::RawArgs) -> noargs::Result<()> {
     }
 
     // 入力の検証
-    if start_sec < 0.0 {
-        return Err("開始秒数は0以上である必要があります".into());
+    let range = match (start_sec, end_sec, start_sample, end_sample) {
+        _ if start_frame.is_some() || end_frame.is_some() => {
+            if start_sec.is_some()
+                || end_sec.is_some()
+                || start_pct.is_some()
+                || end_pct.is_some()
+                || start_sample.is_some()
+                || end_sample.is_some()
+            {
+                return Err(tr!(
+                    "--start-frame/--end-frame は他の開始・終了位置の指定と同時に指定できません",
+                    "--start-frame/--end-frame cannot be combined with other start/end options"
+                )
+                .into());
+            }
+            let start = start_frame.ok_or_else(|| {
+                tr!(
+                    "--end-frame には --start-frame の指定が必要です",
+                    "--end-frame requires --start-frame"
+                )
+            })?;
+            if end_frame.is_some_and(|end| end < start) {
+                return Err(tr!(
+                    "終了フレーム番号は開始フレーム番号以上である必要があります",
+                    "The end frame number must be greater than or equal to the start frame number"
+                )
+                .into());
+            }
+            ExtractRange::Frames {
+                start,
+                end: end_frame,
+            }
+        }
+        _ if start_pct.is_some() || end_pct.is_some() => {
+            if start_sec.is_some()
+                || end_sec.is_some()
+                || start_sample.is_some()
+                || end_sample.is_some()
+            {
+                return Err(tr!(
+                    "--start-pct/--end-pct は --start/--end や --start-sample/--end-sample と同時に指定できません",
+                    "--start-pct/--end-pct cannot be combined with --start/--end or --start-sample/--end-sample"
+                )
+                .into());
+            }
+            let start_pct = start_pct.ok_or_else(|| {
+                tr!(
+                    "--end-pct には --start-pct の指定が必要です",
+                    "--end-pct requires --start-pct"
+                )
+            })?;
+            let is_percentage = |pct: f64| (0.0..=100.0).contains(&pct);
+            if !is_percentage(start_pct) || end_pct.is_some_and(|end_pct| !is_percentage(end_pct)) {
+                return Err(tr!(
+                    "--start-pct/--end-pct には 0 から 100 の値を指定してください",
+                    "--start-pct/--end-pct must be between 0 and 100"
+                )
+                .into());
+            }
+            if end_pct.is_some_and(|end_pct| end_pct <= start_pct) {
+                return Err(tr!(
+                    "終了割合は開始割合より大きい必要があります",
+                    "The end percentage must be greater than the start percentage"
+                )
+                .into());
+            }
+            ExtractRange::Percent { start_pct, end_pct }
+        }
+        (Some(start_sec), end_sec, None, None) => {
+            if start_sec < 0.0 {
+                return Err(tr!(
+                    "開始秒数は0以上である必要があります",
+                    "The start time must be 0 or greater"
+                )
+                .into());
+            }
+            if end_sec.is_some_and(|end_sec| end_sec <= start_sec) {
+                return Err(tr!(
+                    "終了秒数は開始秒数より大きい必要があります",
+                    "The end time must be greater than the start time"
+                )
+                .into());
+            }
+            ExtractRange::Time { start_sec, end_sec }
+        }
+        (None, None, Some(start), Some(end)) => {
+            if track_number.is_none() {
+                return Err(tr!(
+                    "--start-sample/--end-sample には --track の指定が必要です",
+                    "--start-sample/--end-sample requires --track"
+                )
+                .into());
+            }
+            if end < start {
+                return Err(tr!(
+                    "終了サンプル番号は開始サンプル番号以上である必要があります",
+                    "The end sample number must be greater than or equal to the start sample number"
+                )
+                .into());
+            }
+            ExtractRange::Samples { start, end }
+        }
+        (None, None, _, _) if start_sample.is_some() || end_sample.is_some() => {
+            return Err(tr!(
+                "--start-sample と --end-sample は両方指定する必要があります",
+                "Both --start-sample and --end-sample must be specified"
+            )
+            .into());
+        }
+        (_, _, None, None) => {
+            return Err(tr!(
+                "--start、--start-pct、--start-frame、または --start-sample と --end-sample の両方を指定してください",
+                "Specify --start, --start-pct, --start-frame, or both --start-sample and --end-sample"
+            )
+            .into());
+        }
+        _ => {
+            return Err(tr!(
+                "--start/--end と --start-sample/--end-sample は同時に指定できません",
+                "--start/--end cannot be combined with --start-sample/--end-sample"
+            )
+            .into());
+        }
+    };
+    if let Some(start_tolerance) = start_tolerance {
+        if matches!(range, ExtractRange::Samples { .. }) {
+            return Err(tr!(
+                "--start-tolerance は --start または --start-pct と組み合わせて使用してください",
+                "--start-tolerance must be used with --start or --start-pct"
+            )
+            .into());
+        }
+        if start_tolerance.is_nan() || start_tolerance < 0.0 {
+            return Err(tr!(
+                "--start-tolerance は0以上である必要があります",
+                "--start-tolerance must be 0 or greater"
+            )
+            .into());
+        }
+    }
+    let has_end_time = matches!(
+        range,
+        ExtractRange::Time {
+            end_sec: Some(_),
+            ..
+        } | ExtractRange::Percent {
+            end_pct: Some(_),
+            ..
+        } | ExtractRange::Frames { end: Some(_), .. }
+    );
+    if end_at_keyframe && !has_end_time {
+        return Err(tr!(
+            "--end-at-keyframe は --end または --end-pct と組み合わせて使用してください",
+            "--end-at-keyframe must be used with --end or --end-pct"
+        )
+        .into());
+    }
+    if faststart_flag && no_faststart_flag {
+        return Err(tr!(
+            "--faststart と --no-faststart は同時に指定できません",
+            "--faststart and --no-faststart cannot be specified together"
+        )
+        .into());
     }
-    if end_sec <= start_sec {
-        return Err("終了秒数は開始秒数より大きい必要があります".into());
+    // 個別のオプションの指定がない項目はプリセットの設定に従う
+    let preset = preset.unwrap_or_default();
+    let faststart = if faststart_flag || no_faststart_flag {
+        faststart_flag
+    } else {
+        preset.faststart()
+    };
+    if copy_brand_flag && no_copy_brand_flag {
+        return Err(tr!(
+            "--copy-brand と --no-copy-brand は同時に指定できません",
+            "--copy-brand and --no-copy-brand cannot be specified together"
+        )
+        .into());
+    }
+    if copy_brand_flag && container != Container::Auto {
+        return Err(tr!(
+            "--copy-brand と --container は同時に指定できません",
+            "--copy-brand and --container cannot be specified together"
+        )
+        .into());
+    }
+    // --container を指定した場合も、プリセットの --copy-brand より優先する
+    let copy_brand = if copy_brand_flag || no_copy_brand_flag {
+        copy_brand_flag
+    } else {
+        container == Container::Auto && preset.copy_brand()
+    };
+    let buffer_size = usize::try_from(buffer_size)
+        .ok()
+        .filter(|&size| size > 0)
+        .ok_or_else(|| {
+            tr!(
+                "--buffer-size には 1 以上の値を指定してください",
+                "--buffer-size must be 1 or greater"
+            )
+        })?;
+    if track_number.is_some() && stream_map.is_some() {
+        return Err(tr!(
+            "--track と --map は同時に指定できません",
+            "--track and --map cannot be specified together"
+        )
+        .into());
+    }
+    if audio_lang.is_some() && (track_number.is_some() || stream_map.is_some()) {
+        return Err(tr!(
+            "--audio-lang は --track や --map と同時に指定できません",
+            "--audio-lang cannot be combined with --track or --map"
+        )
+        .into());
+    }
+
+    extract(ExtractOptions {
+        input_file_arg,
+        output_file_arg,
+        range,
+        start_tolerance,
+        track_number,
+        stream_map,
+        audio_lang,
+        pts_offset,
+        output_timescales,
+        buffer_size,
+        limits,
+        copy_brand,
+        container,
+        faststart,
+        json,
+        no_clamp,
+        end_at_keyframe,
+        info_after,
+        sidecar,
+        split_init,
+        verify_output,
+        count_only,
+    })
+}
+
+/// 検証済みの抽出の設定
+struct ExtractOptions {
+    input_file_arg: Option<String>,
+    output_file_arg: Option<String>,
+    range: ExtractRange,
+    start_tolerance: Option<f64>,
+    track_number: Option<NonZeroU32>,
+    stream_map: Option<Vec<StreamSpec>>,
+    audio_lang: Option<[u8; 3]>,
+    pts_offset: PtsOffset,
+    output_timescales: OutputTimescales,
+    buffer_size: usize,
+    limits: ResourceLimits,
+    copy_brand: bool,
+    container: Container,
+    faststart: bool,
+    json: bool,
+    no_clamp: bool,
+    end_at_keyframe: bool,
+    info_after: bool,
+    sidecar: bool,
+    split_init: bool,
+    verify_output: bool,
+    count_only: bool,
+}
+
+impl ExtractOptions {
+    /// `range` の範囲を、それ以外はデフォルトの設定で抽出する設定を作る
+    fn with_defaults(
+        input_file_arg: Option<String>,
+        output_file_arg: Option<String>,
+        range: ExtractRange,
+    ) -> noargs::Result<Self> {
+        let preset = Preset::default();
+        Ok(Self {
+            input_file_arg,
+            output_file_arg,
+            range,
+            start_tolerance: None,
+            track_number: None,
+            stream_map: None,
+            audio_lang: None,
+            pts_offset: PtsOffset::Zero,
+            output_timescales: OutputTimescales::default(),
+            buffer_size: parse_byte_size(DEFAULT_BUFFER_SIZE)? as usize,
+            limits: ResourceLimits {
+                max_tracks: DEFAULT_MAX_TRACKS.parse()?,
+                max_samples: DEFAULT_MAX_SAMPLES.parse()?,
+            },
+            copy_brand: preset.copy_brand(),
+            container: Container::Auto,
+            faststart: preset.faststart(),
+            json: false,
+            no_clamp: false,
+            end_at_keyframe: false,
+            info_after: false,
+            sidecar: false,
+            split_init: false,
+            verify_output: false,
+            count_only: false,
+        })
     }
+}
+
+/// `start_sec` 秒から `end_sec` 秒（`None` の場合は末尾）までを、デフォルトの設定で抽出する
+///
+/// trim サブコマンドから使用する
+pub fn trim(
+    input_file_arg: Option<String>,
+    output_file_arg: Option<String>,
+    start_sec: f64,
+    end_sec: Option<f64>,
+) -> noargs::Result<()> {
+    extract(ExtractOptions::with_defaults(
+        input_file_arg,
+        output_file_arg,
+        ExtractRange::Time { start_sec, end_sec },
+    )?)
+}
+
+/// 抽出を実行する
+fn extract(options: ExtractOptions) -> noargs::Result<()> {
+    let ExtractOptions {
+        range,
+        pts_offset,
+        buffer_size,
+        limits,
+        copy_brand,
+        container,
+        faststart,
+        json,
+        end_at_keyframe,
+        info_after,
+        sidecar,
+        split_init,
+        verify_output,
+        count_only,
+        ..
+    } = options;
 
     // 入力ソースを決定
-    let input_source = match InputSource::from_arg(input_file_arg) {
+    let input_source = match InputSource::from_arg(options.input_file_arg.clone()) {
         Some(source) => source,
         None => {
-            eprintln!("エラー: 入力ファイルを指定するか、パイプで入力してください");
-            eprintln!("使用例: mp4-util extract input.mp4 -s 10 -e 30 -o output.mp4");
-            eprintln!("使用例: cat input.mp4 | mp4-util extract -s 10 -e 30 > output.mp4");
+            eprintln!(
+                "{}",
+                tr!(
+                    "エラー: 入力ファイルを指定するか、パイプで入力してください",
+                    "Error: specify an input file or pipe the input"
+                )
+            );
+            eprintln!(
+                "{}",
+                tr!(
+                    "使用例: {}",
+                    "Example: {}",
+                    "mp4-util extract input.mp4 -s 10 -e 30 -o output.mp4"
+                )
+            );
+            eprintln!(
+                "{}",
+                tr!(
+                    "使用例: {}",
+                    "Example: {}",
+                    "cat input.mp4 | mp4-util extract -s 10 -e 30 > output.mp4"
+                )
+            );
             std::process::exit(1);
         }
     };
 
-    // 出力先を決定（バイナリ出力なので TTY は不可）
-    let output_sink = OutputSink::from_arg(output_file_arg, false)?;
+    if count_only
+        && (options.output_file_arg.is_some()
+            || json
+            || sidecar
+            || split_init
+            || verify_output
+            || info_after)
+    {
+        return Err(tr!(
+            "--count-only は出力に関するオプション（-o、--json、--sidecar、--split-init、--verify-output、--info-after）と同時に指定できません",
+            "--count-only cannot be combined with output options (-o, --json, --sidecar, --split-init, --verify-output, --info-after)"
+        )
+        .into());
+    }
+
+    // 出力先を決定（バイナリ出力なので TTY は不可。--count-only ではサンプル数のみを stdout に出力する）
+    let output_sink = if count_only {
+        OutputSink::Stdout
+    } else {
+        OutputSink::from_arg(options.output_file_arg.clone(), false)?
+    };
+    let sidecar_path = match &output_sink {
+        OutputSink::File(path) if sidecar => {
+            let mut sidecar_path = path.clone().into_os_string();
+            sidecar_path.push(".json");
+            Some(PathBuf::from(sidecar_path))
+        }
+        OutputSink::Stdout if sidecar => {
+            return Err(tr!(
+                "--sidecar には -o で出力ファイルを指定する必要があります",
+                "--sidecar requires an output file given with -o"
+            )
+            .into());
+        }
+        _ => None,
+    };
+    let split_init_paths = match &output_sink {
+        _ if !split_init => None,
+        OutputSink::File(path) => {
+            Some((path.with_extension("init.mp4"), path.with_extension("m4s")))
+        }
+        OutputSink::Stdout => {
+            return Err(tr!(
+                "--split-init には -o で出力ファイルを指定する必要があります",
+                "--split-init requires an output file given with -o"
+            )
+            .into());
+        }
+    };
+    if split_init && (sidecar || json || verify_output) {
+        return Err(tr!(
+            "--split-init は --sidecar、--json、--verify-output と同時に指定できません",
+            "--split-init cannot be combined with --sidecar, --json, or --verify-output"
+        )
+        .into());
+    }
+    if info_after {
+        if !output_sink.is_file() {
+            return Err(tr!(
+                "--info-after には -o で出力ファイルを指定する必要があります",
+                "--info-after requires an output file given with -o"
+            )
+            .into());
+        }
+        if split_init || json {
+            return Err(tr!(
+                "--info-after は --split-init や --json と同時に指定できません",
+                "--info-after cannot be combined with --split-init or --json"
+            )
+            .into());
+        }
+    }
 
     // メッセージを stderr に出力するかどうか（stdout が出力先の場合）
     let use_stderr = !output_sink.is_file();
 
-    // MP4 ファイルを読み込み
-    let mut reader = input_source
-        .reader()
-        .map_err(|e| format!("入力を開けません ({}): {}", input_source.description(), e))?;
-    let mut file_data = Vec::new();
-    reader.read_to_end(&mut file_data)?;
-
-    let (mp4_file, _) = Mp4File::decode(&file_data)
-        .map_err(|e| format!("MP4 ファイルの解析に失敗しました: {}", e))?;
+    // MP4 ファイルを開く（stdin の場合は一時ファイルに退避してシーク可能にする）
+    let mut reader = BufReader::with_capacity(
+        buffer_size,
+        input_source.seekable_reader().map_err(|e| {
+            tr!(
+                "入力を開けません ({}): {}",
+                "Cannot open the input ({}): {}",
+                input_source.description(),
+                e
+            )
+        })?,
+    );
 
     // moov ボックスを取得
-    let moov_box = mp4_file
-        .boxes
-        .iter()
-        .find_map(|box_item| {
-            if let RootBox::Moov(moov_box) = box_item {
-                Some(moov_box)
-            } else {
-                None
-            }
-        })
-        .ok_or("moov ボックスが見つかりません")?;
-
-    // トラック情報を収集
-    let mut track_infos: Vec<TrackExtractInfo> = Vec::new();
-    for trak in &moov_box.trak_boxes {
-        let handler_type = &trak.mdia_box.hdlr_box.handler_type;
-        let track_kind = match handler_type {
-            b"vide" => TrackKind::Video,
-            b"soun" => TrackKind::Audio,
-            _ => continue, // ビデオ・オーディオ以外はスキップ
-        };
-
-        let timescale = trak.mdia_box.mdhd_box.timescale.get();
-        let sample_table = SampleTableAccessor::new(&trak.mdia_box.minf_box.stbl_box)
-            .map_err(|e| format!("サンプルテーブルの解析に失敗しました: {}", e))?;
-
-        // 開始・終了タイムスタンプを計算
-        let start_timestamp = (start_sec * timescale as f64) as u64;
-        let end_timestamp = (end_sec * timescale as f64) as u64;
-
-        // 開始サンプルを見つける（キーフレーム境界に調整）
-        let start_sample = sample_table
-            .get_sample_by_timestamp(start_timestamp)
-            .ok_or("指定された開始時間にサンプルが見つかりません")?;
-
-        // ビデオトラックの場合はキーフレームに調整
-        let actual_start_sample = if track_kind == TrackKind::Video {
-            start_sample
-                .sync_sample()
-                .ok_or("開始位置より前にキーフレームが見つかりません")?
-        } else {
-            start_sample
-        };
-
-        // 終了サンプルを見つける
-        let end_sample = sample_table
-            .get_sample_by_timestamp(end_timestamp)
-            .or_else(|| {
-                // 終了時間がファイル末尾を超えている場合は最後のサンプルを使用
-                let sample_count = sample_table.sample_count();
-                sample_table.get_sample(NonZeroU32::new(sample_count)?)
-            })
-            .ok_or("指定された終了時間にサンプルが見つかりません")?;
-
-        // サンプルエントリーを取得
-        let sample_entry = actual_start_sample.chunk().sample_entry().clone();
-
-        track_infos.push(TrackExtractInfo {
-            track_kind,
-            timescale: NonZeroU32::new(timescale).unwrap(),
-            sample_entry,
-            start_sample_index: actual_start_sample.index(),
-            end_sample_index: end_sample.index(),
-            start_timestamp: actual_start_sample.timestamp(),
-            trak_box: trak.clone(),
-        });
+    let mut moov_box = read_moov_box(&mut reader)?;
+    // フラグメント MP4 では moof のサンプルを読み込む前に、moov に宣言されている分を確認しておく
+    limits.check(&moov_box)?;
+    if is_fragmented(&moov_box) {
+        // フラグメント MP4 の場合は moof ボックスのサンプルを moov のサンプルテーブルに移す。
+        // ボックスヘッダーの走査ごとにバッファを読み直さないよう、バッファを介さずに読み込む
+        // （以降のサンプルの読み込みは絶対位置へのシークから始まるので、バッファの内容は使われない）
+        let root_boxes = scan_root_boxes(reader.get_mut())?;
+        defragment_moov_box(reader.get_mut(), &mut moov_box, &root_boxes, &limits)?;
+    }
+    if moov_box.trak_boxes.is_empty() {
+        return Err(tr!("トラックが含まれていません", "The file contains no tracks").into());
     }
+    limits.check(&moov_box)?;
 
-    if track_infos.is_empty() {
-        return Err("ビデオまたはオーディオトラックが見つかりません".into());
+    let track_infos = select_tracks(&moov_box, &options)?;
+
+    if count_only {
+        for info in &track_infos {
+            println!("{} {}", info.source_track_number, info.sample_count());
+        }
+        return Ok(());
     }
 
     // サンプル数を見積もって moov ボックスサイズを予約
     let sample_counts: Vec<usize> = track_infos
         .iter()
-        .map(|t| (t.end_sample_index.get() - t.start_sample_index.get() + 1) as usize)
+        .map(|t| t.sample_count() as usize)
         .collect();
-    let reserved_moov_size = estimate_maximum_moov_box_size(&sample_counts);
+    let reserved_moov_size = if faststart {
+        // 入力のコンポジションオフセットを引き継ぐ場合は ctts の分も確保する（全サンプルで値が変わる場合の最大サイズ）
+        let has_ctts = |info: &TrackExtractInfo<'_>| {
+            parse_ctts_entries(&info.trak_box.mdia_box.minf_box.stbl_box).is_some()
+        };
+        let ctts_size: usize = track_infos
+            .iter()
+            .zip(&sample_counts)
+            .filter(|(info, _)| has_ctts(info))
+            .map(|(_, sample_count)| 16 + 8 * sample_count)
+            .sum();
+        // 編集リストを追加する場合はその分も確保する（zero でも ctts を引き継ぐトラックには追加することがある）
+        let edts_size = match pts_offset {
+            PtsOffset::Zero => {
+                MAX_EDTS_BOX_SIZE * track_infos.iter().filter(|info| has_ctts(info)).count()
+            }
+            PtsOffset::Preserve => MAX_EDTS_BOX_SIZE * track_infos.len(),
+        };
+        estimate_maximum_moov_box_size(&sample_counts) + edts_size + ctts_size
+    } else {
+        0
+    };
 
     // Muxer を初期化
     let options = Mp4FileMuxerOptions {
         reserved_moov_box_size: reserved_moov_size,
         ..Default::default()
     };
-    let mut muxer = Mp4FileMuxer::with_options(options)
-        .map_err(|e| format!("Muxer の初期化に失敗しました: {}", e))?;
+    let mut muxer = Mp4FileMuxer::with_options(options).map_err(|e| {
+        tr!(
+            "Muxer の初期化に失敗しました: {}",
+            "Failed to initialize the muxer: {}",
+            e
+        )
+    })?;
 
-    // 出力バッファを作成（stdout 出力のため Seek 可能な Cursor を使用）
+    // 出力先を開く
+    // moov と mdat ヘッダーを最後に書き戻すため、シークできない出力先（stdout、パイプなど）の場合は
+    // メモリ上の Cursor に書き込み、完了後にまとめて出力する
+    // --split-init 指定時は、セグメントに組み立て直すためにメモリ上に書き込む
+    let mut opened_writer = if split_init {
+        None
+    } else {
+        Some(output_sink.open(buffer_size).map_err(|e| {
+            tr!(
+                "出力先を開けません ({}): {}",
+                "Cannot open the output ({}): {}",
+                output_sink.description(),
+                e
+            )
+        })?)
+    };
     let mut output_buffer: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+    let output: &mut dyn WriteSeek = match &mut opened_writer {
+        Some(OutputWriter::Seekable(writer)) => writer.as_mut(),
+        _ => &mut output_buffer,
+    };
 
     // 初期ボックスを書き込み
+    // 入力の ftyp で置き換える場合は、サイズの差分だけ出力内の位置が Muxer の想定からずれる
     let initial_bytes = muxer.initial_boxes_bytes();
-    output_buffer.write_all(initial_bytes)?;
+    let (_, muxer_ftyp_size) = FtypBox::decode(initial_bytes).map_err(|e| {
+        tr!(
+            "ftyp ボックスの解析に失敗しました: {}",
+            "Failed to parse the ftyp box: {}",
+            e
+        )
+    })?;
+    let ftyp_bytes = if copy_brand {
+        read_ftyp_box(&mut reader)?.encode_to_vec().map_err(|e| {
+            tr!(
+                "ftyp ボックスのエンコードに失敗しました: {}",
+                "Failed to encode the ftyp box: {}",
+                e
+            )
+        })?
+    } else if container.is_m4a(&track_infos) {
+        m4a_ftyp_box().encode_to_vec().map_err(|e| {
+            tr!(
+                "ftyp ボックスのエンコードに失敗しました: {}",
+                "Failed to encode the ftyp box: {}",
+                e
+            )
+        })?
+    } else {
+        initial_bytes[..muxer_ftyp_size].to_vec()
+    };
+    let offset_delta = ftyp_bytes.len() as i64 - muxer_ftyp_size as i64;
+    output.write_all(&ftyp_bytes)?;
+    output.write_all(&initial_bytes[muxer_ftyp_size..])?;
     let mut current_offset = initial_bytes.len() as u64;
+    let mut sample_data = Vec::new();
+    // 読み込みバッファを活かすため、サンプルの読み込み位置を追跡して相対シークする
+    let mut reader_position = None;
 
     // 各トラックからサンプルを抽出して書き込み
     // トラックごとにサンプルを時系列順で処理
     let mut sample_iterators: Vec<SampleIterator> = track_infos
         .iter()
-        .map(|info| {
-            let sample_table = SampleTableAccessor::new(&info.trak_box.mdia_box.minf_box.stbl_box)
-                .expect("already validated");
-            SampleIterator {
-                track_info: info,
-                sample_table,
-                current_index: info.start_sample_index,
-                base_timestamp: info.start_timestamp,
-                is_first_sample: true,
-            }
+        .map(|info| SampleIterator {
+            track_info: info,
+            current_index: info.start_sample_index,
+            base_timestamp: info.start_timestamp,
+            is_first_sample: true,
         })
         .collect();
 
     // 全てのトラックのサンプルを時系列順にインターリーブ
     loop {
         // 次のサンプルを持つトラックを見つける（タイムスタンプが最小のもの）
-        let mut next_track_idx = None;
-        let mut min_timestamp = u64::MAX;
-
-        for (idx, iter) in sample_iterators.iter().enumerate() {
-            if iter.current_index <= iter.track_info.end_sample_index {
-                let sample = iter
-                    .sample_table
-                    .get_sample(iter.current_index)
-                    .expect("valid index");
-                let normalized_timestamp = normalize_timestamp(
-                    sample.timestamp() - iter.base_timestamp,
-                    iter.track_info.timescale.get(),
-                );
-                if normalized_timestamp < min_timestamp {
-                    min_timestamp = normalized_timestamp;
-                    next_track_idx = Some(idx);
-                }
-            }
-        }
+        let next_track_idx = next_track_index(&sample_iterators);
 
         let Some(track_idx) = next_track_idx else {
             break; // 全てのサンプルを処理完了
@@ -229,17 +890,28 @@ pub fn run(mut args: noargs::RawArgs) -> noargs::Result<()> {
 
         let iter = &mut sample_iterators[track_idx];
         let sample_accessor = iter
+            .track_info
             .sample_table
             .get_sample(iter.current_index)
             .expect("valid index");
 
         // サンプルデータを読み取り
-        let data_offset = sample_accessor.data_offset() as usize;
         let data_size = sample_accessor.data_size() as usize;
-        let sample_data = &file_data[data_offset..data_offset + data_size];
+        sample_data.resize(data_size, 0);
+        // 直前の読み込み位置からの相対シークなら、移動先がバッファ内にある場合はバッファが再利用される
+        let data_offset = sample_accessor.data_offset();
+        match reader_position {
+            Some(position) if position == data_offset => {}
+            Some(position) => reader.seek_relative(data_offset as i64 - position as i64)?,
+            None => {
+                reader.seek(SeekFrom::Start(data_offset))?;
+            }
+        }
+        reader.read_exact(&mut sample_data)?;
+        reader_position = Some(data_offset + data_size as u64);
 
         // 出力バッファに書き込み
-        output_buffer.write_all(sample_data)?;
+        write_sample_data(output, &sample_data, current_offset, offset_delta)?;
 
         // Muxer にサンプルを追加
         let sample = Sample {
@@ -250,14 +922,18 @@ pub fn run(mut args: noargs::RawArgs) -> noargs::Result<()> {
                 None
             },
             keyframe: sample_accessor.is_sync_sample(),
-            timescale: iter.track_info.timescale,
-            duration: sample_accessor.duration(),
+            timescale: iter.track_info.output_timescale,
+            duration: iter.track_info.output_duration(&sample_accessor)?,
             data_offset: current_offset,
             data_size,
         };
-        muxer
-            .append_sample(&sample)
-            .map_err(|e| format!("サンプルの追加に失敗しました: {}", e))?;
+        muxer.append_sample(&sample).map_err(|e| {
+            tr!(
+                "サンプルの追加に失敗しました: {}",
+                "Failed to add a sample: {}",
+                e
+            )
+        })?;
 
         current_offset += data_size as u64;
         iter.current_index = iter.current_index.saturating_add(1);
@@ -265,9 +941,13 @@ pub fn run(mut args: noargs::RawArgs) -> noargs::Result<()> {
     }
 
     // ファイナライズ
-    let finalized = muxer
-        .finalize()
-        .map_err(|e| format!("ファイナライズに失敗しました: {}", e))?;
+    let finalized = muxer.finalize().map_err(|e| {
+        tr!(
+            "ファイナライズに失敗しました: {}",
+            "Failed to finalize: {}",
+            e
+        )
+    })?;
 
     // moov_box を複製して matrix を修正（回転情報を維持するため）
     let mut modified_moov_box = finalized.moov_box().clone();
@@ -287,29 +967,207 @@ pub fn run(mut args: noargs::RawArgs) -> noargs::Result<()> {
         }
     }
 
+    // 入力のコンポジションオフセット（ctts）を引き継いで、B フレームなどの表示順を維持する
+    for trak_box in &mut modified_moov_box.trak_boxes {
+        let track_kind = if trak_box.mdia_box.hdlr_box.handler_type == *b"vide" {
+            TrackKind::Video
+        } else {
+            TrackKind::Audio
+        };
+        let Some(info) = track_infos.iter().find(|t| t.track_kind == track_kind) else {
+            continue;
+        };
+        let Some(ctts_entries) = parse_ctts_entries(&info.trak_box.mdia_box.minf_box.stbl_box)
+        else {
+            continue;
+        };
+        let offsets: Vec<i64> = composition_offsets(&ctts_entries)
+            .chain(std::iter::repeat(0))
+            .skip(info.start_sample_index.get() as usize - 1)
+            .take(info.sample_count() as usize)
+            .zip(info.start_sample_index.get()..)
+            .map(|(offset, index)| {
+                let sample = info
+                    .sample_table
+                    .get_sample(NonZeroU32::new(index).expect("non-zero"))
+                    .expect("valid index");
+                info.output_composition_offset(&sample, offset)
+            })
+            .collect();
+        trak_box
+            .mdia_box
+            .minf_box
+            .stbl_box
+            .unknown_boxes
+            .extend(build_ctts_box(&offsets)?);
+    }
+
+    // 入力ファイル上の時刻を維持する場合は、先頭に空の編集を入れて表示開始を遅らせる。
+    // zero の場合も、引き継いだコンポジションオフセットで先頭のサンプルの表示が遅れるトラックは
+    // 編集リストでその分を詰めて、トラック間の同期を保つ
+    {
+        let movie_timescale = modified_moov_box.mvhd_box.timescale.get() as u64;
+        let mut movie_duration = 0;
+        for trak_box in &mut modified_moov_box.trak_boxes {
+            let track_kind = if trak_box.mdia_box.hdlr_box.handler_type == *b"vide" {
+                TrackKind::Video
+            } else {
+                TrackKind::Audio
+            };
+            let Some(info) = track_infos.iter().find(|t| t.track_kind == track_kind) else {
+                continue;
+            };
+            // ctts を引き継いだ場合、先頭のサンプルは出力上もコンポジションオフセットの分だけ遅れて表示される
+            let media_start = parse_ctts_entries(&trak_box.mdia_box.minf_box.stbl_box)
+                .and_then(|entries| composition_offsets(&entries).next())
+                .unwrap_or(0)
+                .max(0) as u64;
+            let start_timestamp = match pts_offset {
+                PtsOffset::Zero if media_start == 0 => {
+                    // 編集リストを追加しないトラックは、メディアの長さをムービーの長さに含める
+                    let mdhd_box = &trak_box.mdia_box.mdhd_box;
+                    let track_duration = (mdhd_box.duration as u128 * movie_timescale as u128
+                        / mdhd_box.timescale.get() as u128)
+                        as u64;
+                    movie_duration = movie_duration.max(track_duration);
+                    continue;
+                }
+                PtsOffset::Zero => 0,
+                PtsOffset::Preserve => {
+                    (info.start_time().max(0.0) * info.output_timescale.get() as f64) as u64
+                }
+            };
+            let elst_box = preserve_timeline_edit_list(
+                start_timestamp,
+                media_start,
+                info.output_timescale.get() as u64,
+                trak_box.mdia_box.mdhd_box.duration,
+                movie_timescale,
+            );
+            let track_duration = elst_box.entries.iter().map(|e| e.edit_duration).sum();
+            trak_box.tkhd_box.duration = track_duration;
+            movie_duration = movie_duration.max(track_duration);
+            trak_box.edts_box = Some(EdtsBox {
+                elst_box: Some(elst_box),
+                unknown_boxes: Vec::new(),
+            });
+        }
+        modified_moov_box.mvhd_box.duration = movie_duration;
+    }
+
+    // ftyp のサイズが変わった分だけサンプルデータの位置を補正
+    if offset_delta != 0 {
+        for trak_box in &mut modified_moov_box.trak_boxes {
+            shift_chunk_offsets(&mut trak_box.mdia_box.minf_box.stbl_box, offset_delta)?;
+        }
+    }
+
     // 修正した moov_box を再エンコード
-    let modified_moov_bytes = modified_moov_box
-        .encode_to_vec()
-        .map_err(|e| format!("moov ボックスの再エンコードに失敗しました: {}", e))?;
+    let modified_moov_bytes = modified_moov_box.encode_to_vec().map_err(|e| {
+        tr!(
+            "moov ボックスの再エンコードに失敗しました: {}",
+            "Failed to re-encode the moov box: {}",
+            e
+        )
+    })?;
 
-    // offset_and_bytes_pairs() から offset を取得
-    let pairs: Vec<_> = finalized.offset_and_bytes_pairs().collect();
-    let (moov_offset, _) = pairs[0];
-    let (mdat_offset, mdat_header_bytes) = pairs[1];
+    // 修正した moov と mdat ヘッダーを書き込み
+    write_finalized_boxes(output, finalized, &modified_moov_bytes, offset_delta)?;
+
+    // 初期化セグメントとメディアセグメントに分けて書き出す
+    if let Some((init_path, media_path)) = &split_init_paths {
+        let init_segment = build_init_segment(&ftyp_bytes, &modified_moov_box)?;
+        let media_segment = build_media_segment(&modified_moov_box, output_buffer.get_ref())?;
+        for (path, segment) in [(init_path, init_segment), (media_path, media_segment)] {
+            std::fs::write(path, segment).map_err(|e| {
+                tr!(
+                    "セグメントの書き込みに失敗しました ({}): {}",
+                    "Failed to write the segment ({}): {}",
+                    path.display(),
+                    e
+                )
+            })?;
+        }
+        println!(
+            "{}",
+            tr!(
+                "初期化セグメント: {}",
+                "Initialization segment: {}",
+                init_path.display()
+            )
+        );
+        println!(
+            "{}",
+            tr!(
+                "メディアセグメント: {}",
+                "Media segment: {}",
+                media_path.display()
+            )
+        );
+        return Ok(());
+    }
 
-    // 修正した moov を書き込み
-    output_buffer.seek(SeekFrom::Start(moov_offset))?;
-    output_buffer.write_all(&modified_moov_bytes)?;
+    // シークできない出力先の場合はバッファの内容をまとめて書き込み
+    output.flush()?;
+    if let Some(OutputWriter::Sequential(writer)) = &mut opened_writer {
+        writer.write_all(output_buffer.get_ref())?;
+        writer.flush()?;
+    }
 
-    // mdat ヘッダーを書き込み
-    output_buffer.seek(SeekFrom::Start(mdat_offset))?;
-    output_buffer.write_all(mdat_header_bytes)?;
+    // 完了を表示する前に、出力のサンプルが抽出元と一致することを確認する
+    if verify_output {
+        let verified = match (&opened_writer, &output_sink) {
+            (Some(OutputWriter::Seekable(_)), OutputSink::File(path)) => {
+                let file = std::fs::File::open(path).map_err(|e| {
+                    tr!(
+                        "出力ファイルを開けません ({}): {}",
+                        "Cannot open the output file ({}): {}",
+                        path.display(),
+                        e
+                    )
+                })?;
+                verify_output_samples(&mut reader, &mut BufReader::new(file), &track_infos)?
+            }
+            _ => {
+                output_buffer.seek(SeekFrom::Start(0))?;
+                verify_output_samples(&mut reader, &mut output_buffer, &track_infos)?
+            }
+        };
+        log::info!(
+            "{}",
+            tr!(
+                "出力の検証に成功しました（{} サンプル）",
+                "Output verified ({} samples)",
+                verified
+            )
+        );
+    }
 
-    // バッファの内容を出力先に書き込み
-    let mut writer = output_sink
-        .writer()
-        .map_err(|e| format!("出力先を開けません ({}): {}", output_sink.description(), e))?;
-    writer.write_all(output_buffer.get_ref())?;
+    // 抽出の記録をサイドカーファイルに書き出す
+    if let (Some(sidecar_path), OutputSink::File(output_path)) = (&sidecar_path, &output_sink) {
+        reader.seek(SeekFrom::Start(0))?;
+        let source_sha256 = sha256_hex(&mut reader)?;
+        let output_sha256 = match &opened_writer {
+            Some(OutputWriter::Seekable(_)) => sha256_hex(std::fs::File::open(output_path)?)?,
+            _ => sha256_hex(output_buffer.get_ref().as_slice())?,
+        };
+        let sidecar_json = extract_sidecar_json(
+            &input_source.description(),
+            &output_sink.description(),
+            &range,
+            &track_infos,
+            &source_sha256,
+            &output_sha256,
+        );
+        std::fs::write(sidecar_path, sidecar_json + "\n").map_err(|e| {
+            tr!(
+                "サイドカーファイルの書き込みに失敗しました ({}): {}",
+                "Failed to write the sidecar file ({}): {}",
+                sidecar_path.display(),
+                e
+            )
+        })?;
+    }
 
     // 結果を表示（stdout が出力先の場合は stderr に出力）
     let video_info = track_infos
@@ -327,47 +1185,2141 @@ pub fn run(mut args: noargs::RawArgs) -> noargs::Result<()> {
         }
     };
 
-    print_message(&format!("抽出が完了しました: {}", output_sink.description()));
-    if let Some(info) = video_info {
-        let start_time = info.start_timestamp as f64 / info.timescale.get() as f64;
-        let sample_count = info.end_sample_index.get() - info.start_sample_index.get() + 1;
-        print_message(&format!(
-            "  ビデオ: {} サンプル (実際の開始時間: {:.3}秒)",
-            sample_count, start_time
+    if json {
+        let (moov_offset, _) = finalized
+            .offset_and_bytes_pairs()
+            .next()
+            .expect("infallible");
+        let output_size = current_offset.max(moov_offset + modified_moov_bytes.len() as u64) as i64
+            + offset_delta;
+        print_message(&extract_summary_json(
+            &output_sink.description(),
+            &track_infos,
+            finalized.is_faststart_enabled(),
+            output_size as u64,
         ));
+        return Ok(());
+    }
+
+    print_message(&tr!(
+        "抽出が完了しました: {}",
+        "Extraction complete: {}",
+        output_sink.description()
+    ));
+    if let Some(info) = video_info {
+        let start_time = info.start_time();
+        let sample_count = info.sample_count();
+        if end_at_keyframe {
+            let end_time = info.end_time();
+            print_message(&tr!(
+                "  ビデオ: {} サンプル (実際の開始時間: {:.3}秒、実際の終了時間: {:.3}秒、タイムスケール: {})",
+                "  Video: {} samples (actual start: {:.3}s, actual end: {:.3}s, timescale: {})",
+                sample_count,
+                start_time,
+                end_time,
+                info.output_timescale
+            ));
+        } else {
+            print_message(&tr!(
+                "  ビデオ: {} サンプル (実際の開始時間: {:.3}秒、タイムスケール: {})",
+                "  Video: {} samples (actual start: {:.3}s, timescale: {})",
+                sample_count,
+                start_time,
+                info.output_timescale
+            ));
+        }
     }
     if let Some(info) = audio_info {
-        let sample_count = info.end_sample_index.get() - info.start_sample_index.get() + 1;
-        print_message(&format!("  オーディオ: {} サンプル", sample_count));
+        let sample_count = info.sample_count();
+        print_message(&tr!(
+            "  オーディオ: {} サンプル (タイムスケール: {})",
+            "  Audio: {} samples (timescale: {})",
+            sample_count,
+            info.output_timescale
+        ));
     }
     if finalized.is_faststart_enabled() {
-        print_message("  faststart: 有効");
+        print_message(&tr!("  faststart: 有効", "  faststart: enabled"));
+    }
+
+    // 出力ファイルを読み直して、正しく解析できることを確認する
+    if info_after && let OutputSink::File(path) = &output_sink {
+        let file = std::fs::File::open(path).map_err(|e| {
+            tr!(
+                "出力ファイルを開けません ({}): {}",
+                "Cannot open the output file ({}): {}",
+                path.display(),
+                e
+            )
+        })?;
+        // 出力ファイル全体をメモリに載せないよう、moov ボックスのみを読み込む
+        let output_mp4 =
+            InputMp4::parse_streaming(BufReader::new(file), u64::MAX).map_err(|e| {
+                tr!(
+                    "出力ファイルの解析に失敗しました: {}",
+                    "Failed to parse the output file: {}",
+                    e
+                )
+            })?;
+        println!();
+        print_mp4_info(&output_mp4, false, false);
     }
 
     Ok(())
 }
 
-/// トラック抽出情報
-struct TrackExtractInfo {
-    track_kind: TrackKind,
-    timescale: NonZeroU32,
-    sample_entry: SampleEntry,
-    start_sample_index: NonZeroU32,
-    end_sample_index: NonZeroU32,
-    start_timestamp: u64,
-    trak_box: TrakBox,
-}
+/// 抽出するトラックと、トラックごとの抽出するサンプルの範囲を選ぶ
+fn select_tracks<'a>(
+    moov_box: &'a MoovBox,
+    options: &ExtractOptions,
+) -> noargs::Result<Vec<TrackExtractInfo<'a>>> {
+    let ExtractOptions {
+        start_tolerance,
+        track_number,
+        audio_lang,
+        output_timescales,
+        no_clamp,
+        end_at_keyframe,
+        ..
+    } = *options;
+    let stream_map = &options.stream_map;
 
-/// サンプルイテレーター
-struct SampleIterator<'a> {
-    track_info: &'a TrackExtractInfo,
-    sample_table: SampleTableAccessor<&'a shiguredo_mp4::boxes::StblBox>,
-    current_index: NonZeroU32,
-    base_timestamp: u64,
-    is_first_sample: bool,
-}
+    // 割合やフレーム番号での指定は、秒数に変換してから通常の時刻指定と同じように扱う
+    let range = resolve_percent_range(options.range, &moov_box.mvhd_box)?;
+    let (range, frame_timestamps) =
+        resolve_frame_range(range, moov_box, track_number, stream_map.as_deref())?;
 
-/// タイムスタンプを正規化（ナノ秒単位に変換）
-fn normalize_timestamp(timestamp: u64, timescale: u32) -> u64 {
-    timestamp * 1_000_000_000 / timescale as u64
+    // トラック情報を収集
+    let mut track_infos: Vec<TrackExtractInfo> = Vec::new();
+    let mut video_track_count = 0;
+    let mut audio_track_count = 0;
+    let mut audio_languages: Vec<[u8; 3]> = Vec::new();
+    for (i, trak) in moov_box.trak_boxes.iter().enumerate() {
+        let handler_type = &trak.mdia_box.hdlr_box.handler_type;
+        let track_kind = match handler_type {
+            b"vide" => TrackKind::Video,
+            b"soun" => TrackKind::Audio,
+            _ if track_number.is_some_and(|n| n.get() as usize == i + 1) => {
+                return Err(tr!(
+                    "トラック {} はビデオ・オーディオトラックではありません",
+                    "Track {} is not a video or audio track",
+                    i + 1
+                )
+                .into());
+            }
+            _ => continue, // ビデオ・オーディオ以外はスキップ
+        };
+
+        // 種別ごとの番号を採番して、--map で選択されていないトラックはスキップ
+        let kind_track_count = match track_kind {
+            TrackKind::Video => &mut video_track_count,
+            TrackKind::Audio => &mut audio_track_count,
+        };
+        let stream_spec = StreamSpec {
+            track_kind,
+            index: *kind_track_count,
+        };
+        *kind_track_count += 1;
+        if let Some(stream_map) = &stream_map
+            && !stream_map.contains(&stream_spec)
+        {
+            continue;
+        }
+        if track_number.is_some_and(|n| n.get() as usize != i + 1) {
+            continue;
+        }
+        // --audio-lang では言語が一致する最初のオーディオトラックのみを選ぶ
+        // （Muxer は種別ごとに 1 トラックしか扱えないため）
+        if let Some(audio_lang) = audio_lang
+            && track_kind == TrackKind::Audio
+        {
+            let language = trak.mdia_box.mdhd_box.language;
+            if !audio_languages.contains(&language) {
+                audio_languages.push(language);
+            }
+            if language != audio_lang {
+                continue;
+            }
+            if track_infos.iter().any(|t| t.track_kind == TrackKind::Audio) {
+                log::warn!(
+                    "{}",
+                    tr!(
+                        "トラック {} も言語 {} のオーディオトラックですが、最初に一致したトラックのみを抽出します",
+                        "Track {} is also an audio track in language {}, but only the first matching track is extracted",
+                        i + 1,
+                        String::from_utf8_lossy(&audio_lang)
+                    )
+                );
+                continue;
+            }
+        }
+        if let Some(reference) = external_data_reference(trak) {
+            return Err(tr!(
+                "トラック {} のサンプルデータは外部のファイル ({}) にあるため抽出できません",
+                "Cannot extract track {} because its sample data is in an external file ({})",
+                i + 1,
+                reference
+            )
+            .into());
+        }
+
+        let timescale = trak.mdia_box.mdhd_box.timescale.get();
+        let sample_table =
+            SampleTableAccessor::new(&trak.mdia_box.minf_box.stbl_box).map_err(|e| {
+                tr!(
+                    "サンプルテーブルの解析に失敗しました: {}",
+                    "Failed to parse the sample table: {}",
+                    e
+                )
+            })?;
+
+        let (actual_start_sample, end_sample) = match range {
+            ExtractRange::Time { start_sec, end_sec } => {
+                // 指定された表示上の時刻を、編集リストに従ってメディアの開始・終了タイムスタンプに変換
+                // （フレーム番号での指定の基準のトラックは、フレームのサンプルのタイムスタンプをそのまま使う）
+                let movie_timescale = moov_box.mvhd_box.timescale;
+                let (start_timestamp, end_timestamp) = match frame_timestamps {
+                    Some(frame) if frame.track_index == i => {
+                        (frame.start_timestamp, frame.end_timestamp)
+                    }
+                    _ => (
+                        presentation_to_media_time(trak, movie_timescale, start_sec),
+                        end_sec.map(|end_sec| {
+                            presentation_to_media_time(trak, movie_timescale, end_sec)
+                        }),
+                    ),
+                };
+
+                // 開始サンプルを見つける（キーフレーム境界に調整）
+                // 開始時刻ちょうどに始まるサンプルがあればそのサンプル、なければ開始時刻を含むサンプル
+                let Some(start_sample) = sample_table.get_sample_by_timestamp(start_timestamp)
+                else {
+                    // 開始時間がトラックの末尾より後ろの場合、空のトラックを出力しないよう除外する
+                    log::warn!(
+                        "{}",
+                        tr!(
+                            "トラック {} ({}) には指定範囲のサンプルがないため除外します",
+                            "Skipping track {} ({}) because it has no samples in the range",
+                            i + 1,
+                            stream_spec
+                        )
+                    );
+                    continue;
+                };
+
+                // ビデオトラックの場合はキーフレームに調整
+                let actual_start_sample = if track_kind == TrackKind::Video {
+                    match start_sample.sync_sample() {
+                        Some(sync_sample) => sync_sample,
+                        None => {
+                            // 開始位置より前にキーフレームがない場合は、開始位置以降の最初のキーフレーム
+                            // （それもなければ先頭サンプル）から開始する
+                            let fallback_sample = (start_sample.index().get()
+                                ..=sample_table.sample_count())
+                                .filter_map(|i| sample_table.get_sample(NonZeroU32::new(i)?))
+                                .find(|s| s.is_sync_sample())
+                                .or_else(|| sample_table.get_sample(NonZeroU32::MIN))
+                                .ok_or_else(|| {
+                                    tr!(
+                                        "開始位置のサンプルが見つかりません",
+                                        "The sample at the start position was not found"
+                                    )
+                                })?;
+                            log::warn!(
+                                "{}",
+                                tr!(
+                                    "開始位置より前にキーフレームが見つからないため、{:.3}秒 (サンプル {}) から開始します",
+                                    "No keyframe found before the start position; starting at {:.3}s (sample {})",
+                                    fallback_sample.timestamp() as f64 / timescale as f64,
+                                    fallback_sample.index()
+                                )
+                            );
+                            fallback_sample
+                        }
+                    }
+                } else {
+                    start_sample
+                };
+
+                // キーフレームへの調整で開始位置が許容範囲を超えて前にずれた場合はエラーにする
+                if let Some(start_tolerance) = start_tolerance {
+                    let pre_roll = start_timestamp.saturating_sub(actual_start_sample.timestamp())
+                        as f64
+                        / timescale as f64;
+                    if pre_roll > start_tolerance {
+                        return Err(tr!(
+                            "トラック {} の開始位置がキーフレーム ({:.3}秒) に合わせて {:.3}秒前にずれ、--start-tolerance ({}秒) を超えました。キーフレーム間隔が粗すぎるため、この位置では切り出せません",
+                            "The start of track {} was moved to the keyframe at {:.3}s, {:.3}s earlier, exceeding --start-tolerance ({}s); the keyframe interval is too coarse to cut at this position",
+                            i + 1,
+                            actual_start_sample.timestamp() as f64 / timescale as f64,
+                            pre_roll,
+                            start_tolerance
+                        )
+                        .into());
+                    }
+                }
+
+                let last_sample = NonZeroU32::new(sample_table.sample_count())
+                    .and_then(|n| sample_table.get_sample(n));
+                // --no-clamp 指定時は、終了時間がトラックの末尾を超えていればエラーにする
+                if no_clamp
+                    && let (Some(end_sec), Some(end_timestamp), Some(last_sample)) =
+                        (end_sec, end_timestamp, &last_sample)
+                {
+                    let track_end = last_sample.timestamp() + last_sample.duration() as u64;
+                    if end_timestamp > track_end {
+                        return Err(tr!(
+                            "終了秒数 {} がトラック {} の長さ ({:.3}秒) を超えています",
+                            "The end time {} exceeds the duration of track {} ({:.3}s)",
+                            end_sec,
+                            i + 1,
+                            track_end as f64 / timescale as f64
+                        )
+                        .into());
+                    }
+                }
+
+                // 終了サンプルを見つける
+                let end_sample = end_timestamp
+                    .and_then(|end_timestamp| end_sample_by_timestamp(&sample_table, end_timestamp))
+                    // 終了時間の指定がない、またはファイル末尾を超えている場合は最後のサンプルを使用
+                    .or(last_sample)
+                    .ok_or_else(|| {
+                        tr!(
+                            "指定された終了時間にサンプルが見つかりません",
+                            "No sample found at the specified end time"
+                        )
+                    })?;
+
+                // --end-at-keyframe 指定時は、終了位置以前の最後のキーフレームの直前までにする
+                // （終了時間がトラックの末尾を超えている場合は末尾の GOP も完全なので調整しない）
+                let end_sample = match end_sample.sync_sample() {
+                    Some(sync_sample)
+                        if end_at_keyframe
+                            && track_kind == TrackKind::Video
+                            && end_timestamp.is_some_and(|end_timestamp| {
+                                sample_table
+                                    .get_sample_by_timestamp(end_timestamp)
+                                    .is_some()
+                            }) =>
+                    {
+                        // 開始位置と終了位置が同じ GOP 内にある場合は、調整すると範囲が空になる
+                        let Some(snapped_end_sample) =
+                            NonZeroU32::new(sync_sample.index().get() - 1)
+                                .filter(|&index| index >= actual_start_sample.index())
+                                .and_then(|index| sample_table.get_sample(index))
+                        else {
+                            return Err(tr!(
+                                "トラック {} ({}) の指定範囲には完全な GOP が含まれないため、--end-at-keyframe で終了位置を調整できません",
+                                "The range of track {} ({}) contains no complete GOP, so --end-at-keyframe cannot adjust the end position",
+                                i + 1,
+                                stream_spec
+                            )
+                            .into());
+                        };
+                        snapped_end_sample
+                    }
+                    _ => end_sample,
+                };
+
+                (actual_start_sample, end_sample)
+            }
+            ExtractRange::Percent { .. } | ExtractRange::Frames { .. } => {
+                // 割合・フレーム番号での指定は上で秒数に変換している
+                return Err(tr!(
+                    "割合・フレーム番号での範囲指定を秒数に変換できませんでした",
+                    "Failed to convert the percentage or frame number range to seconds"
+                )
+                .into());
+            }
+            ExtractRange::Samples { start, end } => {
+                // サンプル番号を直接使用する（キーフレームへの調整は行わない）
+                let sample_count = sample_table.sample_count();
+                if end.get() > sample_count {
+                    return Err(tr!(
+                        "終了サンプル番号 {} がトラックのサンプル数 {} を超えています",
+                        "The end sample number {} exceeds the number of samples in the track ({})",
+                        end,
+                        sample_count
+                    )
+                    .into());
+                }
+                (
+                    sample_table.get_sample(start).expect("validated"),
+                    sample_table.get_sample(end).expect("validated"),
+                )
+            }
+        };
+
+        if end_sample.index() < actual_start_sample.index() {
+            log::warn!(
+                "{}",
+                tr!(
+                    "トラック {} ({}) には指定範囲のサンプルがないため除外します",
+                    "Skipping track {} ({}) because it has no samples in the range",
+                    i + 1,
+                    stream_spec
+                )
+            );
+            continue;
+        }
+
+        // サンプルエントリーを取得
+        let sample_entry = actual_start_sample.chunk().sample_entry().clone();
+
+        // 表示時刻はデコード時刻にコンポジションオフセットを加えたもの
+        let ctts_entries = parse_ctts_entries(&trak.mdia_box.minf_box.stbl_box).unwrap_or_default();
+        let composition_offset = |index: NonZeroU32| {
+            composition_offsets(&ctts_entries)
+                .nth(index.get() as usize - 1)
+                .unwrap_or(0)
+        };
+
+        let start_sample_index = actual_start_sample.index();
+        let end_sample_index = end_sample.index();
+        let start_timestamp = actual_start_sample.timestamp();
+        let end_timestamp = end_sample.timestamp() + end_sample.duration() as u64;
+        let timescale = NonZeroU32::new(timescale).unwrap();
+        let output_timescale = match track_kind {
+            TrackKind::Video => output_timescales.video,
+            TrackKind::Audio => output_timescales.audio,
+        }
+        .unwrap_or(timescale);
+        track_infos.push(TrackExtractInfo {
+            track_kind,
+            timescale,
+            output_timescale,
+            sample_entry,
+            start_sample_index,
+            end_sample_index,
+            start_timestamp,
+            start_presentation_timestamp: start_timestamp as i64
+                + composition_offset(start_sample_index),
+            end_presentation_timestamp: end_timestamp as i64 + composition_offset(end_sample_index),
+            source_track_number: i + 1,
+            movie_timescale: moov_box.mvhd_box.timescale,
+            trak_box: trak,
+            sample_table,
+        });
+    }
+
+    // --map で存在しないトラックが指定されていないかを確認
+    if let Some(stream_map) = &stream_map
+        && let Some(missing) = stream_map.iter().find(|s| {
+            let count = match s.track_kind {
+                TrackKind::Video => video_track_count,
+                TrackKind::Audio => audio_track_count,
+            };
+            s.index >= count
+        })
+    {
+        let available: Vec<String> = (0..video_track_count)
+            .map(|index| StreamSpec {
+                track_kind: TrackKind::Video,
+                index,
+            })
+            .chain((0..audio_track_count).map(|index| StreamSpec {
+                track_kind: TrackKind::Audio,
+                index,
+            }))
+            .map(|s| s.to_string())
+            .collect();
+        return Err(tr!(
+            "トラック {} が見つかりません（利用可能: {}）",
+            "Track {} not found (available: {})",
+            missing,
+            available.join(", ")
+        )
+        .into());
+    }
+
+    if let Some(audio_lang) = audio_lang
+        && !track_infos.iter().any(|t| t.track_kind == TrackKind::Audio)
+    {
+        let available: Vec<_> = audio_languages
+            .iter()
+            .map(|language| String::from_utf8_lossy(language).into_owned())
+            .collect();
+        return Err(tr!(
+            "言語 {} のオーディオトラックが見つかりません（利用可能: {}）",
+            "No audio track in language {} found (available: {})",
+            String::from_utf8_lossy(&audio_lang),
+            if available.is_empty() {
+                tr!("なし", "none")
+            } else {
+                available.join(", ")
+            }
+        )
+        .into());
+    }
+
+    if let Some(track_number) = track_number
+        && track_number.get() as usize > moov_box.trak_boxes.len()
+    {
+        return Err(tr!(
+            "トラック {} が見つかりません（トラック数: {}）",
+            "Track {} not found (number of tracks: {})",
+            track_number,
+            moov_box.trak_boxes.len()
+        )
+        .into());
+    }
+
+    if track_infos.is_empty() {
+        return Err(tr!(
+            "ビデオまたはオーディオトラックが見つかりません",
+            "No video or audio track found"
+        )
+        .into());
+    }
+    for info in &track_infos {
+        check_output_timescale(info)?;
+    }
+    Ok(track_infos)
+}
+
+/// サンプルデータを出力に書き込む
+///
+/// デバッグビルドでは、書き込み位置が Muxer に渡す `data_offset` と一致していることを確認する。
+/// `data_offset` は Muxer の初期ボックスを基準にした位置なので、入力の ftyp に置き換えた場合は
+/// `offset_delta` だけ実際の位置とずれる（BufWriter の位置の取得はバッファの書き出しを伴うため、
+/// リリースビルドでは確認しない）
+fn write_sample_data(
+    output: &mut dyn WriteSeek,
+    data: &[u8],
+    data_offset: u64,
+    offset_delta: i64,
+) -> std::io::Result<()> {
+    debug_assert_eq!(
+        output.stream_position()? as i64,
+        data_offset as i64 + offset_delta,
+        "サンプルの書き込み位置が Muxer に渡すオフセットと一致しません"
+    );
+    output.write_all(data)
+}
+
+/// extract の出力設定のプリセット
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Preset {
+    /// Web 再生向け（プリセット未指定時と同じ）
+    ///
+    /// faststart を有効にし、ftyp には Muxer 標準の互換ブランド（isom / iso2 / mp41 / avc1 / av01）を使用する
+    #[default]
+    Web,
+    /// 保存向け
+    ///
+    /// faststart を無効にし、入力ファイルの ftyp をそのまま使用する
+    Archive,
+}
+
+impl Preset {
+    fn faststart(self) -> bool {
+        self == Self::Web
+    }
+
+    fn copy_brand(self) -> bool {
+        self == Self::Archive
+    }
+}
+
+fn parse_preset(value: &str) -> Result<Preset, String> {
+    match value {
+        "web" => Ok(Preset::Web),
+        "archive" => Ok(Preset::Archive),
+        _ => Err(tr!(
+            "不明なプリセットです: {}（web / archive のいずれかを指定してください）",
+            "Unknown preset: {} (specify web or archive)",
+            value
+        )),
+    }
+}
+
+/// 出力の ftyp のブランドの種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Container {
+    /// 出力するトラックがすべてオーディオの場合は `M4a`、それ以外は `Mp4` として扱う
+    Auto,
+    /// Muxer 標準の互換ブランドを使用する
+    Mp4,
+    /// メジャーブランドを「M4A 」にする
+    M4a,
+}
+
+impl Container {
+    fn is_m4a(self, track_infos: &[TrackExtractInfo]) -> bool {
+        match self {
+            Self::Auto => {
+                !track_infos.is_empty()
+                    && track_infos
+                        .iter()
+                        .all(|info| &info.trak_box.mdia_box.hdlr_box.handler_type == b"soun")
+            }
+            Self::Mp4 => false,
+            Self::M4a => true,
+        }
+    }
+}
+
+fn parse_container(value: &str) -> Result<Container, String> {
+    match value {
+        "auto" => Ok(Container::Auto),
+        "mp4" => Ok(Container::Mp4),
+        "m4a" => Ok(Container::M4a),
+        _ => Err(tr!(
+            "不明な指定です: {}（auto / mp4 / m4a のいずれかを指定してください）",
+            "Unknown value: {} (specify auto, mp4, or m4a)",
+            value
+        )),
+    }
+}
+
+/// 音声ファイル（.m4a）用の ftyp ボックス
+fn m4a_ftyp_box() -> FtypBox {
+    let m4a = Brand::new(*b"M4A ");
+    FtypBox {
+        major_brand: m4a,
+        minor_version: 0,
+        compatible_brands: vec![m4a, Brand::ISOM, Brand::ISO2, Brand::MP41],
+    }
+}
+
+/// 出力のタイムスタンプの扱い
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PtsOffset {
+    /// 先頭のサンプルが 0 から始まるようにする
+    Zero,
+    /// 編集リストで先頭のサンプルの表示を遅らせて、入力ファイル上の時刻を維持する
+    ///
+    /// 同じ入力から抽出した複数のファイルを共通のタイムライン上で並べる場合に使う
+    Preserve,
+}
+
+/// `--audio-lang` の ISO 639-2 の言語コード（英小文字 3 文字）を解釈する
+fn parse_language_code(value: &str) -> Result<[u8; 3], String> {
+    value
+        .as_bytes()
+        .try_into()
+        .ok()
+        .filter(|code: &[u8; 3]| code.iter().all(u8::is_ascii_lowercase))
+        .ok_or_else(|| {
+            tr!(
+                "不正な言語コードです: {}（「jpn」のような ISO 639-2 の英小文字 3 文字で指定してください）",
+                "Invalid language code: {} (specify three lowercase ISO 639-2 letters, e.g. jpn)",
+                value
+            )
+        })
+}
+
+fn parse_pts_offset(value: &str) -> Result<PtsOffset, String> {
+    match value {
+        "zero" => Ok(PtsOffset::Zero),
+        "preserve" => Ok(PtsOffset::Preserve),
+        _ => Err(tr!(
+            "不明な指定です: {}（zero / preserve のいずれかを指定してください）",
+            "Unknown value: {} (specify zero or preserve)",
+            value
+        )),
+    }
+}
+
+/// トラックの種別ごとの出力のタイムスケール（`None` の場合は入力のトラックと同じ）
+#[derive(Debug, Default, Clone, Copy)]
+struct OutputTimescales {
+    video: Option<NonZeroU32>,
+    audio: Option<NonZeroU32>,
+}
+
+/// タイムスケール `from` の時刻を、タイムスケール `to` の最も近い時刻に変換する
+///
+/// サンプルの長さを個別に丸めると誤差が積み重なるため、抽出開始位置からの経過時刻を変換して、
+/// その差分を出力のサンプルの長さにする
+fn rescale_timestamp(timestamp: i64, from: NonZeroU32, to: NonZeroU32) -> i64 {
+    let (from, to) = (from.get() as i128, to.get() as i128);
+    (timestamp as i128 * to * 2 + from).div_euclid(from * 2) as i64
+}
+
+/// 出力のタイムスケールでの丸め誤差が、サンプルの長さを超えないかを確認する
+///
+/// 誤差がサンプルの長さを超えたり、出力のサンプルの長さが 0 になったりする場合は、
+/// サンプルの時刻が隣のサンプルにずれてしまうのでエラーにする
+fn check_output_timescale(info: &TrackExtractInfo) -> Result<(), String> {
+    if info.output_timescale == info.timescale {
+        return Ok(());
+    }
+    let (timescale, output_timescale) = (info.timescale.get() as f64, info.output_timescale.get());
+    let mut max_error: f64 = 0.0;
+    let mut min_duration = u32::MAX;
+    let mut collapsed = false;
+    let mut previous_end = 0;
+    for index in info.start_sample_index.get()..=info.end_sample_index.get() {
+        let sample = info
+            .sample_table
+            .get_sample(NonZeroU32::new(index).expect("non-zero"))
+            .expect("valid index");
+        let elapsed = (sample.timestamp() + sample.duration() as u64 - info.start_timestamp) as i64;
+        let rescaled = rescale_timestamp(elapsed, info.timescale, info.output_timescale);
+        if sample.duration() > 0 {
+            min_duration = min_duration.min(sample.duration());
+            collapsed |= rescaled == previous_end;
+        }
+        previous_end = rescaled;
+        max_error = max_error
+            .max((rescaled as f64 / output_timescale as f64 - elapsed as f64 / timescale).abs());
+    }
+    if min_duration != u32::MAX && (collapsed || max_error > min_duration as f64 / timescale) {
+        return Err(tr!(
+            "トラック {} の時刻を出力のタイムスケール {} では正しく表現できません（丸め誤差: 最大 {:.6}秒、最短のサンプルの長さ: {:.6}秒）。より大きなタイムスケールを指定してください",
+            "The timestamps of track {} cannot be represented correctly in the output timescale {} (rounding error: up to {:.6}s, shortest sample duration: {:.6}s); specify a larger timescale",
+            info.source_track_number,
+            output_timescale,
+            max_error,
+            min_duration as f64 / timescale
+        ));
+    }
+    Ok(())
+}
+
+/// 編集リストを追加する場合の edts ボックスの最大サイズ（バージョン 1 の elst に 2 エントリ）
+const MAX_EDTS_BOX_SIZE: usize = 8 + 16 + 2 * 20;
+
+/// 先頭のサンプルを `start_timestamp` の時刻に表示する編集リストを作る
+///
+/// `media_start` は先頭のサンプルの出力上の表示時刻（コンポジションオフセット）。
+/// `start_timestamp`・`media_start`・`media_duration` はメディアのタイムスケール、編集の長さはムービーのタイムスケールの単位
+fn preserve_timeline_edit_list(
+    start_timestamp: u64,
+    media_start: u64,
+    media_timescale: u64,
+    media_duration: u64,
+    movie_timescale: u64,
+) -> ElstBox {
+    let to_movie_time =
+        |t: u64| (t as u128 * movie_timescale as u128 / media_timescale as u128) as u64;
+    let normal_rate = FixedPointNumber::new(1, 0);
+
+    let mut entries = Vec::new();
+    if start_timestamp > 0 {
+        // メディア時刻 -1 は空の編集（何も表示しない区間）
+        entries.push(ElstEntry {
+            edit_duration: to_movie_time(start_timestamp),
+            media_time: -1,
+            media_rate: normal_rate,
+        });
+    }
+    entries.push(ElstEntry {
+        edit_duration: to_movie_time(media_duration.saturating_sub(media_start)),
+        media_time: media_start as i64,
+        media_rate: normal_rate,
+    });
+    ElstBox { entries }
+}
+
+/// ファイナライズ結果に従って moov ボックスと mdat ヘッダーを出力に書き込む
+///
+/// faststart の場合は事前に確保した領域に moov を書き込み、残りを free ボックスで埋める。
+/// `offset_delta` は ftyp の置き換えによる、Muxer の想定からの出力内の位置のずれ
+fn write_finalized_boxes<W: Write + Seek + ?Sized>(
+    output: &mut W,
+    finalized: &FinalizedBoxes,
+    moov_bytes: &[u8],
+    offset_delta: i64,
+) -> Result<(), String> {
+    let write_error = |e: std::io::Error| {
+        tr!(
+            "出力への書き込みに失敗しました: {}",
+            "Failed to write the output: {}",
+            e
+        )
+    };
+
+    let mut pairs = finalized.offset_and_bytes_pairs();
+    let (moov_offset, _) = pairs.next().expect("infallible");
+    let (mdat_offset, mdat_header_bytes) = pairs.next().expect("infallible");
+
+    let mut moov_bytes = moov_bytes.to_vec();
+    if finalized.is_faststart_enabled() {
+        let reserved_size = (mdat_offset - moov_offset) as usize;
+        let free_box_size = reserved_size.checked_sub(moov_bytes.len()).ok_or_else(|| {
+            tr!(
+                "moov ボックスが予約した領域に収まりません",
+                "The moov box does not fit in the reserved space"
+            )
+        })?;
+        if free_box_size > 0 {
+            if free_box_size < 8 {
+                return Err(tr!(
+                    "予約した領域の残りが free ボックスのヘッダーより小さくなりました",
+                    "The remaining reserved space is smaller than a free box header"
+                ));
+            }
+            // 残りの領域のペイロードは初期ボックスの書き込み時にゼロで埋められている
+            moov_bytes.extend_from_slice(&(free_box_size as u32).to_be_bytes());
+            moov_bytes.extend_from_slice(b"free");
+        }
+    }
+
+    output
+        .seek(SeekFrom::Start(
+            moov_offset.saturating_add_signed(offset_delta),
+        ))
+        .map_err(write_error)?;
+    output.write_all(&moov_bytes).map_err(write_error)?;
+
+    output
+        .seek(SeekFrom::Start(
+            mdat_offset.saturating_add_signed(offset_delta),
+        ))
+        .map_err(write_error)?;
+    output.write_all(mdat_header_bytes).map_err(write_error)?;
+    output.flush().map_err(write_error)?;
+    Ok(())
+}
+
+/// 抽出結果を JSON オブジェクトの文字列にする
+fn extract_summary_json(
+    output: &str,
+    track_infos: &[TrackExtractInfo],
+    faststart: bool,
+    output_size: u64,
+) -> String {
+    let tracks: Vec<String> = track_infos
+        .iter()
+        .map(|info| {
+            let kind = match info.track_kind {
+                TrackKind::Video => "video",
+                TrackKind::Audio => "audio",
+            };
+            format!(
+                r#"{{"kind":{},"sample_count":{},"start_time":{},"end_time":{},"timescale":{}}}"#,
+                json::string(kind),
+                info.sample_count(),
+                json::number(info.start_time()),
+                json::number(info.end_time()),
+                info.output_timescale
+            )
+        })
+        .collect();
+    format!(
+        r#"{{"output":{},"tracks":[{}],"faststart":{},"size":{}}}"#,
+        json::string(output),
+        tracks.join(","),
+        faststart,
+        output_size
+    )
+}
+
+/// 割合で指定された範囲を、mvhd ボックスのファイルの長さを基準に秒数の範囲に変換する
+///
+/// 割合以外の指定はそのまま返す
+fn resolve_percent_range(range: ExtractRange, mvhd_box: &MvhdBox) -> Result<ExtractRange, String> {
+    let ExtractRange::Percent { start_pct, end_pct } = range else {
+        return Ok(range);
+    };
+    if mvhd_box.duration == 0 {
+        return Err(tr!(
+            "ファイルの長さが不明なため --start-pct/--end-pct は使用できません",
+            "--start-pct/--end-pct cannot be used because the file duration is unknown"
+        ));
+    }
+    let duration_sec = mvhd_box.duration as f64 / mvhd_box.timescale.get() as f64;
+    Ok(ExtractRange::Time {
+        start_sec: duration_sec * start_pct / 100.0,
+        end_sec: end_pct.map(|end_pct| duration_sec * end_pct / 100.0),
+    })
+}
+
+/// `--start-frame`/`--end-frame` の基準のビデオトラックで、指定されたフレームが占めるメディア時刻の範囲
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FrameTimestamps {
+    /// 基準のビデオトラックのインデックス（0 始まり）
+    track_index: usize,
+    /// 開始フレームのサンプルのタイムスタンプ
+    start_timestamp: u64,
+    /// 終了フレームのサンプルの末尾のタイムスタンプ（`None` の場合は末尾まで）
+    end_timestamp: Option<u64>,
+}
+
+/// フレーム番号で指定された範囲を、基準のビデオトラックのサンプルの範囲と、それ以外のトラック向けの
+/// 秒数の範囲に変換する
+///
+/// 基準のビデオトラックは `--track` の指定、`--map` の最初のビデオ、最初のビデオトラックの順に決める。
+/// フレーム N はそのトラックのサンプル N にそのまま対応させ、秒数の範囲はそれらのサンプルの
+/// タイムスタンプから求める。フレーム番号はフレームレートが一定（stts のサンプル間隔が一つ）の場合のみ
+/// 意味を持つので、それ以外の場合はエラーにする。フレーム番号以外の指定はそのまま返す
+fn resolve_frame_range(
+    range: ExtractRange,
+    moov_box: &MoovBox,
+    track_number: Option<NonZeroU32>,
+    stream_map: Option<&[StreamSpec]>,
+) -> Result<(ExtractRange, Option<FrameTimestamps>), String> {
+    let ExtractRange::Frames { start, end } = range else {
+        return Ok((range, None));
+    };
+    let video_index = stream_map
+        .and_then(|specs| specs.iter().find(|s| s.track_kind == TrackKind::Video))
+        .map_or(0, |spec| spec.index);
+    let (track_number, trak_box) = match track_number {
+        Some(n) => (
+            n.get() as usize,
+            moov_box
+                .trak_boxes
+                .get(n.get() as usize - 1)
+                .ok_or_else(|| tr!("トラック {} が見つかりません", "Track {} not found", n))?,
+        ),
+        None => moov_box
+            .trak_boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, trak)| &trak.mdia_box.hdlr_box.handler_type == b"vide")
+            .nth(video_index)
+            .map(|(i, trak)| (i + 1, trak))
+            .ok_or_else(|| {
+                tr!(
+                    "--start-frame/--end-frame にはビデオトラックが必要です",
+                    "--start-frame/--end-frame requires a video track"
+                )
+            })?,
+    };
+    if &trak_box.mdia_box.hdlr_box.handler_type != b"vide" {
+        return Err(tr!(
+            "--start-frame/--end-frame にはビデオトラックが必要です（トラック {} はビデオトラックではありません）",
+            "--start-frame/--end-frame requires a video track (track {} is not a video track)",
+            track_number
+        ));
+    }
+
+    let stbl_box = &trak_box.mdia_box.minf_box.stbl_box;
+    let mut deltas = stbl_box.stts_box.entries.iter().map(|e| e.sample_delta);
+    let frame_duration = deltas.next().unwrap_or(0);
+    if frame_duration == 0 || deltas.any(|delta| delta != frame_duration) {
+        return Err(tr!(
+            "トラック {} はフレームレートが一定でない（stts のサンプル間隔が一つではない）ため、フレーム番号では指定できません",
+            "Track {} does not have a constant frame rate (its stts has more than one sample delta), so frame numbers cannot be used",
+            track_number
+        ));
+    }
+    let frame_count: u64 = stbl_box
+        .stts_box
+        .entries
+        .iter()
+        .map(|e| e.sample_count as u64)
+        .sum();
+    if let Some(frame) = [Some(start), end]
+        .into_iter()
+        .flatten()
+        .find(|&f| f as u64 >= frame_count)
+    {
+        return Err(tr!(
+            "フレーム番号 {} がトラック {} のフレーム数 {} を超えています",
+            "Frame number {} exceeds the number of frames in track {} ({})",
+            frame,
+            track_number,
+            frame_count
+        ));
+    }
+
+    // フレーム N（0 始まり）はサンプル N + 1（1 始まり）
+    let sample_table = SampleTableAccessor::new(stbl_box).map_err(|e| {
+        tr!(
+            "サンプルテーブルの解析に失敗しました: {}",
+            "Failed to parse the sample table: {}",
+            e
+        )
+    })?;
+    let frame_sample = |frame: u32| {
+        frame
+            .checked_add(1)
+            .and_then(NonZeroU32::new)
+            .and_then(|index| sample_table.get_sample(index))
+            .ok_or_else(|| {
+                tr!(
+                    "フレーム番号 {} に対応するサンプルがトラック {} にありません",
+                    "No sample for frame number {} in track {}",
+                    frame,
+                    track_number
+                )
+            })
+    };
+    let start_timestamp = frame_sample(start)?.timestamp();
+    // 終了フレームを含むよう、その末尾（次のフレームの先頭）までにする
+    let end_timestamp = end
+        .map(|end| frame_sample(end).map(|sample| sample.timestamp() + sample.duration() as u64))
+        .transpose()?;
+
+    let movie_timescale = moov_box.mvhd_box.timescale;
+    let to_sec = |timestamp: u64| {
+        media_to_presentation_time(trak_box, movie_timescale, timestamp as i64).max(0.0)
+    };
+    Ok((
+        ExtractRange::Time {
+            start_sec: to_sec(start_timestamp),
+            end_sec: end_timestamp.map(to_sec),
+        },
+        Some(FrameTimestamps {
+            track_index: track_number - 1,
+            start_timestamp,
+            end_timestamp,
+        }),
+    ))
+}
+
+/// サイドカーファイルに書き出す、抽出の記録の JSON を生成する
+fn extract_sidecar_json(
+    source: &str,
+    output: &str,
+    range: &ExtractRange,
+    track_infos: &[TrackExtractInfo],
+    source_sha256: &str,
+    output_sha256: &str,
+) -> String {
+    let requested = match range {
+        ExtractRange::Time { start_sec, end_sec } => format!(
+            r#"{{"start_time":{},"end_time":{}}}"#,
+            json::number(*start_sec),
+            end_sec.map_or_else(|| "null".to_string(), json::number)
+        ),
+        ExtractRange::Percent { start_pct, end_pct } => format!(
+            r#"{{"start_pct":{},"end_pct":{}}}"#,
+            json::number(*start_pct),
+            end_pct.map_or_else(|| "null".to_string(), json::number)
+        ),
+        ExtractRange::Samples { start, end } => {
+            format!(r#"{{"start_sample":{},"end_sample":{}}}"#, start, end)
+        }
+        ExtractRange::Frames { start, end } => format!(
+            r#"{{"start_frame":{},"end_frame":{}}}"#,
+            start,
+            end.map_or_else(|| "null".to_string(), |end| end.to_string())
+        ),
+    };
+    let tracks: Vec<String> = track_infos
+        .iter()
+        .map(|info| {
+            let kind = match info.track_kind {
+                TrackKind::Video => "video",
+                TrackKind::Audio => "audio",
+            };
+            format!(
+                r#"{{"source_track":{},"kind":{},"start_sample":{},"end_sample":{},"start_time":{},"end_time":{}}}"#,
+                info.source_track_number,
+                json::string(kind),
+                info.start_sample_index,
+                info.end_sample_index,
+                json::number(info.start_time()),
+                json::number(info.end_time())
+            )
+        })
+        .collect();
+    format!(
+        r#"{{"source":{{"path":{},"sha256":{}}},"output":{{"path":{},"sha256":{}}},"requested":{},"tracks":[{}]}}"#,
+        json::string(source),
+        json::string(source_sha256),
+        json::string(output),
+        json::string(output_sha256),
+        requested,
+        tracks.join(",")
+    )
+}
+
+/// 出力の各トラックのサンプルのデータが、抽出元の対応するサンプルと一致することを SHA-256 で確認する
+///
+/// 出力のトラックは `track_infos` と同じ順に並んでいる前提。確認したサンプル数を返す
+fn verify_output_samples<S: Read + Seek, O: Read + Seek>(
+    source: &mut S,
+    output: &mut O,
+    track_infos: &[TrackExtractInfo],
+) -> Result<u64, String> {
+    let output_moov_box = read_moov_box(output).map_err(|e| {
+        tr!(
+            "出力の検証に失敗しました: moov ボックスを読み込めません: {}",
+            "Output verification failed: cannot read the moov box: {}",
+            e
+        )
+    })?;
+    if output_moov_box.trak_boxes.len() != track_infos.len() {
+        return Err(tr!(
+            "出力の検証に失敗しました: トラック数が一致しません（期待値: {}、出力: {}）",
+            "Output verification failed: the number of tracks does not match (expected: {}, output: {})",
+            track_infos.len(),
+            output_moov_box.trak_boxes.len()
+        ));
+    }
+
+    let mut data = Vec::new();
+    let mut sample_sha256 = |reader: &mut dyn ReadSeek, offset: u64, size: u32| {
+        data.resize(size as usize, 0);
+        reader
+            .seek(SeekFrom::Start(offset))
+            .and_then(|_| reader.read_exact(&mut data))
+            .map_err(|e| {
+                tr!(
+                    "出力の検証に失敗しました: サンプルを読み込めません: {}",
+                    "Output verification failed: cannot read a sample: {}",
+                    e
+                )
+            })?;
+        Ok::<_, String>(Sha256::digest(&data))
+    };
+    let mut verified = 0;
+    for (info, output_trak_box) in track_infos.iter().zip(&output_moov_box.trak_boxes) {
+        let track_number = info.source_track_number;
+        let output_sample_table =
+            SampleTableAccessor::new(&output_trak_box.mdia_box.minf_box.stbl_box).map_err(|e| {
+                tr!(
+                    "出力の検証に失敗しました: トラック {} のサンプルテーブルを解析できません: {}",
+                    "Output verification failed: cannot parse the sample table of track {}: {}",
+                    track_number,
+                    e
+                )
+            })?;
+        let expected_count = info.sample_count();
+        if output_sample_table.sample_count() != expected_count {
+            return Err(tr!(
+                "出力の検証に失敗しました: トラック {} のサンプル数が一致しません（期待値: {}、出力: {}）",
+                "Output verification failed: the number of samples in track {} does not match (expected: {}, output: {})",
+                track_number,
+                expected_count,
+                output_sample_table.sample_count()
+            ));
+        }
+
+        for (source_index, output_sample) in
+            (info.start_sample_index.get()..).zip(output_sample_table.samples())
+        {
+            let source_sample = info
+                .sample_table
+                .get_sample(NonZeroU32::new(source_index).expect("non-zero"))
+                .expect("validated");
+            let mismatch = source_sample.data_size() != output_sample.data_size()
+                || sample_sha256(
+                    source,
+                    source_sample.data_offset(),
+                    source_sample.data_size(),
+                )? != sample_sha256(
+                    output,
+                    output_sample.data_offset(),
+                    output_sample.data_size(),
+                )?;
+            if mismatch {
+                return Err(tr!(
+                    "出力の検証に失敗しました: トラック {} のサンプル {}（出力のサンプル {}）のデータが抽出元と一致しません",
+                    "Output verification failed: track {} sample {} (output sample {}) does not match the source",
+                    track_number,
+                    source_index,
+                    output_sample.index()
+                ));
+            }
+            verified += 1;
+        }
+    }
+    Ok(verified)
+}
+
+/// 入力の末尾までの SHA-256 を 16 進数の文字列で返す
+fn sha256_hex<R: Read>(mut reader: R) -> std::io::Result<String> {
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let n = reader.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+/// 抽出範囲の指定方法
+#[derive(Debug, Clone, Copy)]
+enum ExtractRange {
+    /// 秒数で指定（ビデオはキーフレームに調整される。終了が `None` の場合は末尾まで）
+    Time {
+        start_sec: f64,
+        end_sec: Option<f64>,
+    },
+    /// ファイルの長さに対する割合（0〜100）で指定（moov ボックスの読み込み後に `Time` に変換される）
+    Percent {
+        start_pct: f64,
+        end_pct: Option<f64>,
+    },
+    /// 1 始まりのサンプル番号で指定（終了サンプルを含む）
+    Samples { start: NonZeroU32, end: NonZeroU32 },
+    /// ビデオの 0 始まりのフレーム番号で指定（終了フレームを含む。moov ボックスの読み込み後に `Time` に変換される）
+    Frames { start: u32, end: Option<u32> },
+}
+
+/// `--map` で指定されるトラック（種別と種別内の 0 始まりの番号）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct StreamSpec {
+    track_kind: TrackKind,
+    index: usize,
+}
+
+impl std::fmt::Display for StreamSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let prefix = match self.track_kind {
+            TrackKind::Video => "v",
+            TrackKind::Audio => "a",
+        };
+        write!(f, "{}:{}", prefix, self.index)
+    }
+}
+
+/// `v:0,a:1` 形式のトラック指定を解析する
+///
+/// 出力 MP4 にはビデオ・オーディオそれぞれ 1 トラックまでしか含められないため、
+/// 同じ種別を複数指定した場合はエラーにする
+fn parse_stream_map(value: &str) -> Result<Vec<StreamSpec>, String> {
+    let mut stream_map: Vec<StreamSpec> = Vec::new();
+    for item in value.split(',').map(str::trim) {
+        let (kind, index) = item.split_once(':').ok_or_else(|| {
+            tr!(
+                "トラック指定の形式が不正です: {}",
+                "Invalid track specifier: {}",
+                item
+            )
+        })?;
+        let track_kind = match kind {
+            "v" => TrackKind::Video,
+            "a" => TrackKind::Audio,
+            _ => {
+                return Err(tr!(
+                    "不明なトラック種別です: {}",
+                    "Unknown track kind: {}",
+                    kind
+                ));
+            }
+        };
+        let index = index.parse().map_err(|_| {
+            tr!(
+                "トラック番号が不正です: {}",
+                "Invalid track number: {}",
+                item
+            )
+        })?;
+
+        if stream_map.iter().any(|s| s.track_kind == track_kind) {
+            return Err(tr!(
+                "ビデオ・オーディオはそれぞれ 1 トラックまでしか指定できません: {}",
+                "Only one video and one audio track can be specified: {}",
+                item
+            ));
+        }
+        stream_map.push(StreamSpec { track_kind, index });
+    }
+    Ok(stream_map)
+}
+
+/// トラック抽出情報
+///
+/// サンプルテーブルは範囲の決定とサンプルの書き込みで共有し、トラックごとに一度だけ解析する
+struct TrackExtractInfo<'a> {
+    track_kind: TrackKind,
+    timescale: NonZeroU32,
+    /// 出力のトラックのタイムスケール（`--output-timescale-video` / `--output-timescale-audio`）
+    output_timescale: NonZeroU32,
+    sample_entry: SampleEntry,
+    start_sample_index: NonZeroU32,
+    end_sample_index: NonZeroU32,
+    start_timestamp: u64,
+    /// 開始サンプルの表示時刻（コンポジションオフセットを考慮したもの）
+    start_presentation_timestamp: i64,
+    /// 終了サンプルの表示終了時刻（コンポジションオフセットを考慮したもの）
+    end_presentation_timestamp: i64,
+    /// 入力ファイルでの 1 始まりのトラック番号
+    source_track_number: usize,
+    movie_timescale: NonZeroU32,
+    trak_box: &'a TrakBox,
+    sample_table: SampleTableAccessor<&'a StblBox>,
+}
+
+impl TrackExtractInfo<'_> {
+    /// 抽出するサンプル数
+    fn sample_count(&self) -> u32 {
+        self.end_sample_index.get() - self.start_sample_index.get() + 1
+    }
+
+    /// サンプルの長さを出力のタイムスケールに変換する
+    fn output_duration<T: AsRef<StblBox>>(
+        &self,
+        sample: &SampleAccessor<'_, T>,
+    ) -> Result<u32, String> {
+        let start = (sample.timestamp() - self.start_timestamp) as i64;
+        let end = start + sample.duration() as i64;
+        u32::try_from(self.rescale(end) - self.rescale(start)).map_err(|_| {
+            tr!(
+                "トラック {} のサンプルの長さを出力のタイムスケール {} で表現できません",
+                "The sample duration of track {} cannot be represented in the output timescale {}",
+                self.source_track_number,
+                self.output_timescale
+            )
+        })
+    }
+
+    /// コンポジションオフセットを出力のタイムスケールに変換する
+    ///
+    /// 表示時刻とデコード時刻をそれぞれ変換した差分にして、サンプルの長さと同じく誤差が積み重ならないようにする
+    fn output_composition_offset<T: AsRef<StblBox>>(
+        &self,
+        sample: &SampleAccessor<'_, T>,
+        offset: i64,
+    ) -> i64 {
+        let start = (sample.timestamp() - self.start_timestamp) as i64;
+        self.rescale(start + offset) - self.rescale(start)
+    }
+
+    fn rescale(&self, timestamp: i64) -> i64 {
+        rescale_timestamp(timestamp, self.timescale, self.output_timescale)
+    }
+
+    /// 開始サンプルの入力ファイル上の表示時刻（秒。編集リストを考慮したもの）
+    fn start_time(&self) -> f64 {
+        media_to_presentation_time(
+            self.trak_box,
+            self.movie_timescale,
+            self.start_presentation_timestamp,
+        )
+    }
+
+    /// 終了サンプルの入力ファイル上の表示終了時刻（秒。編集リストを考慮したもの）
+    fn end_time(&self) -> f64 {
+        media_to_presentation_time(
+            self.trak_box,
+            self.movie_timescale,
+            self.end_presentation_timestamp,
+        )
+    }
+}
+
+/// サンプルイテレーター
+struct SampleIterator<'a> {
+    track_info: &'a TrackExtractInfo<'a>,
+    current_index: NonZeroU32,
+    base_timestamp: u64,
+    is_first_sample: bool,
+}
+
+impl SampleIterator<'_> {
+    /// 次のサンプルの抽出開始位置からの経過時間（ナノ秒）を返す（残りのサンプルがない場合は `None`）
+    ///
+    /// トラックごとにタイムスケールが異なるため、必ずこのトラック自身のタイムスケールで正規化する
+    fn next_normalized_timestamp(&self) -> Option<u64> {
+        if self.current_index > self.track_info.end_sample_index {
+            return None;
+        }
+        let sample = self
+            .track_info
+            .sample_table
+            .get_sample(self.current_index)
+            .expect("valid index");
+        Some(normalize_timestamp(
+            sample.timestamp() - self.base_timestamp,
+            self.track_info.timescale.get(),
+        ))
+    }
+}
+
+/// 終了タイムスタンプ `end_timestamp` より前に始まる最後のサンプルを返す
+///
+/// 時刻での抽出範囲は開始を含み終了を含まない [開始, 終了) として扱う。終了時刻ちょうどに始まるサンプルは
+/// 含めないので、`-s 10 -e 10+d`（d はサンプルの長さ）は 10 秒に始まるサンプルのみになり、
+/// ある抽出の終了時刻を次の抽出の開始時刻にしても同じサンプルが両方に含まれることはない
+///
+/// 終了時刻がトラックの末尾より後ろの場合は `None` を返す
+fn end_sample_by_timestamp<T: AsRef<StblBox>>(
+    sample_table: &SampleTableAccessor<T>,
+    end_timestamp: u64,
+) -> Option<SampleAccessor<'_, T>> {
+    sample_table.get_sample_by_timestamp(end_timestamp.saturating_sub(1))
+}
+
+/// 次にサンプルを書き込むトラック（経過時間が最小のもの。同時刻ならトラック順）を選ぶ
+fn next_track_index(sample_iterators: &[SampleIterator]) -> Option<usize> {
+    sample_iterators
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, iter)| Some((iter.next_normalized_timestamp()?, idx)))
+        .min()
+        .map(|(_, idx)| idx)
+}
+
+/// タイムスタンプを正規化（ナノ秒単位に変換）
+fn normalize_timestamp(timestamp: u64, timescale: u32) -> u64 {
+    timestamp * 1_000_000_000 / timescale as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::test_util::{Mp4Builder, test_sample_entry};
+
+    /// `input` を一時ファイルに書き出して `range` の範囲を抽出し、出力ファイルを解析した結果を返す
+    ///
+    /// `configure` で抽出の設定をデフォルトから変更できる
+    fn run_extract(
+        input: &[u8],
+        range: ExtractRange,
+        configure: impl FnOnce(&mut ExtractOptions),
+    ) -> Result<InputMp4, String> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let prefix = format!(
+            "mp4util-test-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        );
+        let input_path = std::env::temp_dir().join(format!("{prefix}-input.mp4"));
+        let output_path = std::env::temp_dir().join(format!("{prefix}-output.mp4"));
+        std::fs::write(&input_path, input).unwrap();
+
+        let mut options = ExtractOptions::with_defaults(
+            Some(input_path.display().to_string()),
+            Some(output_path.display().to_string()),
+            range,
+        )
+        .unwrap();
+        configure(&mut options);
+        let result = extract(options).map_err(|e| format!("{e:?}"));
+        let output = std::fs::read(&output_path);
+        let _ = std::fs::remove_file(&input_path);
+        let _ = std::fs::remove_file(&output_path);
+        result?;
+        InputMp4::parse(&output.unwrap()[..])
+    }
+
+    /// トラックの種別（ハンドラー種別）とサンプル数の組
+    fn track_sample_counts(mp4: &InputMp4) -> Vec<([u8; 4], u32)> {
+        mp4.tracks()
+            .iter()
+            .map(|trak_box| {
+                let stbl_box = &trak_box.mdia_box.minf_box.stbl_box;
+                let sample_count = SampleTableAccessor::new(stbl_box).unwrap().sample_count();
+                (trak_box.mdia_box.hdlr_box.handler_type, sample_count)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_rescale_timestamp() {
+        let ts = |n| NonZeroU32::new(n).unwrap();
+        assert_eq!(rescale_timestamp(1001, ts(30000), ts(30000)), 1001);
+        assert_eq!(rescale_timestamp(1001, ts(30000), ts(90000)), 3003);
+        assert_eq!(rescale_timestamp(-3003, ts(90000), ts(30000)), -1001);
+
+        // 経過時刻を変換して差分を取るので、長さを丸めても誤差が積み重ならない
+        let durations: Vec<i64> = (0..3)
+            .map(|i| {
+                rescale_timestamp((i + 1) * 1024, ts(44100), ts(1000))
+                    - rescale_timestamp(i * 1024, ts(44100), ts(1000))
+            })
+            .collect();
+        assert_eq!(durations, [23, 23, 24]);
+    }
+
+    #[test]
+    fn test_parse_language_code() {
+        assert_eq!(parse_language_code("jpn").unwrap(), *b"jpn");
+        assert_eq!(parse_language_code("und").unwrap(), *b"und");
+        assert!(parse_language_code("ja").is_err());
+        assert!(parse_language_code("JPN").is_err());
+        assert!(parse_language_code("日本").is_err());
+    }
+
+    #[test]
+    fn test_parse_preset() {
+        let web = parse_preset("web").unwrap();
+        assert!(web.faststart() && !web.copy_brand());
+        assert_eq!(web, Preset::default());
+
+        let archive = parse_preset("archive").unwrap();
+        assert!(!archive.faststart() && archive.copy_brand());
+
+        assert!(parse_preset("Web").is_err());
+    }
+
+    #[test]
+    fn test_container() {
+        assert_eq!(parse_container("m4a"), Ok(Container::M4a));
+        assert!(parse_container("m4v").is_err());
+        assert!(Container::M4a.is_m4a(&[]));
+        assert!(!Container::Mp4.is_m4a(&[]));
+        // トラックがない場合は自動では m4a にしない
+        assert!(!Container::Auto.is_m4a(&[]));
+
+        let bytes = m4a_ftyp_box().encode_to_vec().unwrap();
+        assert_eq!(&bytes[8..12], b"M4A ");
+        assert_eq!(bytes.len(), 8 + 8 + 4 * 4);
+    }
+
+    #[test]
+    fn test_extract_output_brand() {
+        let range = ExtractRange::Time {
+            start_sec: 0.0,
+            end_sec: None,
+        };
+        let major_brand = |output: &InputMp4| output.ftyp().expect("ftyp").major_brand.get();
+
+        // オーディオのみの出力は m4a のブランドになる
+        let audio = Mp4Builder::new()
+            .track(TrackKind::Audio, 50, &[1; 50], 1)
+            .build();
+        let output = run_extract(&audio, range, |_| {}).unwrap();
+        assert_eq!(&major_brand(&output), b"M4A ");
+
+        // --container mp4 を指定した場合とビデオを含む場合は isom になる
+        let output = run_extract(&audio, range, |options| {
+            options.container = Container::Mp4;
+        })
+        .unwrap();
+        assert_eq!(&major_brand(&output), b"isom");
+
+        let video = Mp4Builder::new()
+            .track(TrackKind::Video, 30, &[1; 30], 1)
+            .build();
+        let output = run_extract(&video, range, |_| {}).unwrap();
+        assert_eq!(&major_brand(&output), b"isom");
+    }
+
+    #[test]
+    fn test_resolve_percent_range() {
+        let mut mvhd_box = MvhdBox {
+            creation_time: shiguredo_mp4::Mp4FileTime::from_secs(0),
+            modification_time: shiguredo_mp4::Mp4FileTime::from_secs(0),
+            timescale: NonZeroU32::new(1000).unwrap(),
+            duration: 60_000,
+            rate: MvhdBox::DEFAULT_RATE,
+            volume: MvhdBox::DEFAULT_VOLUME,
+            matrix: MvhdBox::DEFAULT_MATRIX,
+            next_track_id: 1,
+        };
+        let range = ExtractRange::Percent {
+            start_pct: 25.0,
+            end_pct: Some(50.0),
+        };
+        assert!(matches!(
+            resolve_percent_range(range, &mvhd_box),
+            Ok(ExtractRange::Time {
+                start_sec: 15.0,
+                end_sec: Some(30.0)
+            })
+        ));
+
+        // 長さが不明なファイルでは変換できない
+        mvhd_box.duration = 0;
+        assert!(resolve_percent_range(range, &mvhd_box).is_err());
+    }
+
+    #[test]
+    fn test_resolve_frame_range() {
+        // 29.97fps（タイムスケール 30000、間隔 1001）のビデオ 100 フレーム
+        let build_moov_box = |last_delta: u32| {
+            let mut deltas = [1001; 100];
+            deltas[99] = last_delta;
+            Mp4Builder::new()
+                .track(TrackKind::Video, 30000, &deltas, 1)
+                .moov_box()
+        };
+
+        let moov_box = build_moov_box(1001);
+        let trak_box = &moov_box.trak_boxes[0];
+        let range = ExtractRange::Frames {
+            start: 37,
+            end: Some(73),
+        };
+        let Ok((
+            ExtractRange::Time {
+                start_sec,
+                end_sec: Some(end_sec),
+            },
+            Some(frame_timestamps),
+        )) = resolve_frame_range(range, &moov_box, None, None)
+        else {
+            panic!("not converted to a time range");
+        };
+        // 基準のトラックは開始フレームの先頭から終了フレームの末尾まで
+        assert_eq!(
+            frame_timestamps,
+            FrameTimestamps {
+                track_index: 0,
+                start_timestamp: 37 * 1001,
+                end_timestamp: Some(74 * 1001),
+            }
+        );
+        // 秒数から戻したときにも、開始フレームと終了フレームのサンプルになること
+        let sample_table = SampleTableAccessor::new(&trak_box.mdia_box.minf_box.stbl_box).unwrap();
+        let to_media = |sec| presentation_to_media_time(trak_box, moov_box.mvhd_box.timescale, sec);
+        let start_sample = sample_table
+            .get_sample_by_timestamp(to_media(start_sec))
+            .unwrap();
+        let end_sample = end_sample_by_timestamp(&sample_table, to_media(end_sec)).unwrap();
+        assert_eq!(
+            (start_sample.index().get(), end_sample.index().get()),
+            (38, 74)
+        );
+
+        let out_of_range = ExtractRange::Frames {
+            start: 100,
+            end: None,
+        };
+        assert!(resolve_frame_range(out_of_range, &moov_box, None, None).is_err());
+
+        // フレームの間隔が一定でない場合はエラー
+        let moov_box = build_moov_box(500);
+        assert!(resolve_frame_range(range, &moov_box, None, None).is_err());
+    }
+
+    #[test]
+    fn test_extract_frame_range() {
+        // 30fps（タイムスケール 30）のビデオ 300 フレームと、20 ms 間隔のオーディオ 500 サンプル
+        let input = Mp4Builder::new()
+            .track(TrackKind::Video, 30, &[1; 300], 30)
+            .track(TrackKind::Audio, 1000, &[20; 500], 1)
+            .build();
+
+        // 0 から 122 フレーム目まで（4.1 秒）は、秒数に丸め誤差があっても 123 フレームになる
+        let range = ExtractRange::Frames {
+            start: 0,
+            end: Some(122),
+        };
+        let output = run_extract(&input, range, |_| {}).unwrap();
+        let mut counts = track_sample_counts(&output);
+        counts.sort();
+        assert_eq!(counts, [(*b"soun", 205), (*b"vide", 123)]);
+    }
+
+    #[test]
+    fn test_count_only_sample_counts() {
+        // 30fps のビデオ 300 フレームと、20 ms 間隔のオーディオ 500 サンプルの 1 秒目から 3 秒目まで
+        let moov_box = Mp4Builder::new()
+            .track(TrackKind::Video, 30, &[1; 300], 30)
+            .track(TrackKind::Audio, 1000, &[20; 500], 1)
+            .moov_box();
+        let range = ExtractRange::Time {
+            start_sec: 1.0,
+            end_sec: Some(3.0),
+        };
+        let options = ExtractOptions::with_defaults(None, None, range).unwrap();
+        let mut counts: Vec<_> = select_tracks(&moov_box, &options)
+            .unwrap()
+            .iter()
+            .map(|info| {
+                let handler_type = info.trak_box.mdia_box.hdlr_box.handler_type;
+                (handler_type, info.sample_count())
+            })
+            .collect();
+        counts.sort();
+        assert_eq!(counts, [(*b"soun", 100), (*b"vide", 60)]);
+    }
+
+    #[test]
+    fn test_sha256_hex() {
+        assert_eq!(
+            sha256_hex(&b"abc"[..]).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_interleave_order_with_different_timescales() {
+        // ビデオ 1000（33 ms 間隔）とオーディオ 48000（1024 サンプル間隔）のトラック
+        let sample_entry = test_sample_entry();
+        let moov_box = Mp4Builder::new()
+            .track(TrackKind::Video, 1000, &[33; 30], 1)
+            .track(TrackKind::Audio, 48000, &[1024; 30], 1)
+            .moov_box();
+
+        let track_infos: Vec<TrackExtractInfo> = moov_box
+            .trak_boxes
+            .iter()
+            .enumerate()
+            .map(|(i, trak_box)| TrackExtractInfo {
+                track_kind: if trak_box.mdia_box.hdlr_box.handler_type == *b"vide" {
+                    TrackKind::Video
+                } else {
+                    TrackKind::Audio
+                },
+                timescale: trak_box.mdia_box.mdhd_box.timescale,
+                output_timescale: trak_box.mdia_box.mdhd_box.timescale,
+                sample_entry: sample_entry.clone(),
+                start_sample_index: NonZeroU32::MIN,
+                end_sample_index: NonZeroU32::new(30).unwrap(),
+                start_timestamp: 0,
+                start_presentation_timestamp: 0,
+                end_presentation_timestamp: 0,
+                source_track_number: i + 1,
+                movie_timescale: moov_box.mvhd_box.timescale,
+                trak_box,
+                sample_table: SampleTableAccessor::new(&trak_box.mdia_box.minf_box.stbl_box)
+                    .unwrap(),
+            })
+            .collect();
+        let mut sample_iterators: Vec<SampleIterator> = track_infos
+            .iter()
+            .map(|info| SampleIterator {
+                track_info: info,
+                current_index: info.start_sample_index,
+                base_timestamp: 0,
+                is_first_sample: true,
+            })
+            .collect();
+
+        // 書き込み順の経過時間（秒）が単調増加になっていること
+        let mut written = Vec::new();
+        while let Some(idx) = next_track_index(&sample_iterators) {
+            let iter = &mut sample_iterators[idx];
+            let sample = iter
+                .track_info
+                .sample_table
+                .get_sample(iter.current_index)
+                .unwrap();
+            written.push(sample.timestamp() as f64 / iter.track_info.timescale.get() as f64);
+            iter.current_index = iter.current_index.saturating_add(1);
+        }
+        assert_eq!(written.len(), 60);
+        assert!(written.windows(2).all(|w| w[0] <= w[1]), "{:?}", written);
+    }
+
+    #[test]
+    fn test_sample_boundaries_by_timestamp() {
+        // 長さ 100 のサンプルが 10 個（0, 100, ..., 900 に開始）
+        let moov_box = Mp4Builder::new()
+            .track(TrackKind::Video, 1000, &[100; 10], 1)
+            .moov_box();
+        let sample_table =
+            SampleTableAccessor::new(&moov_box.trak_boxes[0].mdia_box.minf_box.stbl_box).unwrap();
+        let start = |timestamp| {
+            sample_table
+                .get_sample_by_timestamp(timestamp)
+                .unwrap()
+                .index()
+                .get()
+        };
+        let end =
+            |timestamp| end_sample_by_timestamp(&sample_table, timestamp).map(|s| s.index().get());
+
+        // サンプルの先頭ちょうどの開始時刻はそのサンプルから、終了時刻はその直前のサンプルまで
+        assert_eq!((start(300), end(400)), (4, Some(4)));
+        assert_eq!((start(300), end(500)), (4, Some(5)));
+        // サンプルの途中の時刻は、開始・終了ともにそのサンプルを含める
+        assert_eq!((start(350), end(450)), (4, Some(5)));
+        // 終了時刻をそのまま次の開始時刻にすると、サンプルが重複せずに続く
+        assert_eq!(end(400).unwrap() + 1, start(400));
+        // トラックの末尾ちょうどは最後のサンプルまで、それより後ろは None
+        assert_eq!(end(1000), Some(10));
+        assert_eq!(end(1001), None);
+    }
+
+    #[test]
+    fn test_extract_end_at_keyframe() {
+        // 30fps のビデオ 300 フレーム（1 秒ごとにキーフレーム）
+        let input = Mp4Builder::new()
+            .track(TrackKind::Video, 30, &[1; 300], 30)
+            .build();
+        let range = |start_sec, end_sec| ExtractRange::Time {
+            start_sec,
+            end_sec: Some(end_sec),
+        };
+        let end_at_keyframe = |options: &mut ExtractOptions| options.end_at_keyframe = true;
+
+        // 終了位置は 2 秒目のキーフレームの直前になる
+        let output = run_extract(&input, range(0.5, 2.5), end_at_keyframe).unwrap();
+        assert_eq!(track_sample_counts(&output), [(*b"vide", 60)]);
+
+        // 開始位置と終了位置が同じ GOP 内の場合は、ビデオトラックを黙って除外せずにエラーにする
+        let Err(error) = run_extract(&input, range(1.2, 1.5), end_at_keyframe) else {
+            panic!("extracted without a complete GOP");
+        };
+        assert!(error.contains("GOP"), "{error}");
+    }
+
+    #[test]
+    fn test_extract_negative_composition_offsets() {
+        // I P B B の GOP が 2 つ（タイムスケール 1000、100 ms 間隔）。B フレームは負のオフセットを持つ
+        let input = Mp4Builder::new()
+            .track(TrackKind::Video, 1000, &[100; 8], 4)
+            .build_with_edited_moov_box(|moov_box| {
+                let ctts_box = build_ctts_box(&[0, 200, -100, -100, 0, 200, -100, -100])
+                    .unwrap()
+                    .unwrap();
+                moov_box.trak_boxes[0]
+                    .mdia_box
+                    .minf_box
+                    .stbl_box
+                    .unknown_boxes
+                    .push(ctts_box);
+            });
+        let range = ExtractRange::Time {
+            start_sec: 0.4,
+            end_sec: None,
+        };
+        let output = run_extract(&input, range, |_| {}).unwrap();
+
+        // 2 つ目の GOP の表示順（I B B P）が維持される
+        let stbl_box = &output.tracks()[0].mdia_box.minf_box.stbl_box;
+        let ctts_entries = parse_ctts_entries(stbl_box).unwrap();
+        let sample_table = SampleTableAccessor::new(stbl_box).unwrap();
+        let presentation_times: Vec<i64> = sample_table
+            .samples()
+            .zip(composition_offsets(&ctts_entries))
+            .map(|(sample, offset)| sample.timestamp() as i64 + offset)
+            .collect();
+        assert_eq!(presentation_times, [0, 300, 100, 200]);
+
+        // 初期化セグメントには ctts を残さない（オフセットはメディアセグメントの trun が持つ）
+        let init_segment = build_init_segment(&[], output.moov()).unwrap();
+        assert!(!init_segment.windows(4).any(|w| w == b"ctts"));
+    }
+
+    #[test]
+    fn test_extract_positive_composition_offsets() {
+        // I B B P の GOP が 2 つ（タイムスケール 1000、100 ms 間隔）。I フレームも 100 ms 遅れて表示される
+        let input = Mp4Builder::new()
+            .track(TrackKind::Video, 1000, &[100; 8], 4)
+            .build_with_edited_moov_box(|moov_box| {
+                let ctts_box = build_ctts_box(&[100, 300, 0, 0, 100, 300, 0, 0])
+                    .unwrap()
+                    .unwrap();
+                moov_box.trak_boxes[0]
+                    .mdia_box
+                    .minf_box
+                    .stbl_box
+                    .unknown_boxes
+                    .push(ctts_box);
+            });
+        let range = ExtractRange::Time {
+            start_sec: 0.4,
+            end_sec: None,
+        };
+        let first_presentation_time = |pts_offset| {
+            let output =
+                run_extract(&input, range, |options| options.pts_offset = pts_offset).unwrap();
+            let trak_box = &output.tracks()[0];
+            let stbl_box = &trak_box.mdia_box.minf_box.stbl_box;
+            let first_offset = composition_offsets(&parse_ctts_entries(stbl_box).unwrap())
+                .next()
+                .unwrap();
+            assert_eq!(first_offset, 100);
+            media_to_presentation_time(trak_box, output.mvhd().timescale, first_offset)
+        };
+
+        // zero ではコンポジションオフセットの分を編集リストで詰めて、先頭のフレームを 0 秒に表示する
+        assert_eq!(first_presentation_time(PtsOffset::Zero), 0.0);
+        // preserve では入力ファイル上の表示時刻を維持する
+        assert_eq!(first_presentation_time(PtsOffset::Preserve), 0.5);
+    }
+
+    #[test]
+    fn test_end_time_not_exactly_representable() {
+        // 30fps のビデオ 300 フレームで --end 4.1 を指定した場合
+        let moov_box = Mp4Builder::new()
+            .track(TrackKind::Video, 30, &[1; 300], 30)
+            .moov_box();
+        let trak_box = &moov_box.trak_boxes[0];
+        let sample_table = SampleTableAccessor::new(&trak_box.mdia_box.minf_box.stbl_box).unwrap();
+        let end_timestamp = presentation_to_media_time(trak_box, moov_box.mvhd_box.timescale, 4.1);
+        let end_sample = end_sample_by_timestamp(&sample_table, end_timestamp).unwrap();
+        // 4.1 秒ちょうどに始まるフレームの直前（0 から数えて 122 番目）までの 123 フレーム
+        assert_eq!(end_sample.index().get(), 123);
+    }
+
+    #[test]
+    fn test_preserve_timeline_edit_list() {
+        // 先頭サンプルの表示時刻: preserve では 90000 / 48000 秒 = ムービーのタイムスケール 1000 で 1875
+        let elst_box = preserve_timeline_edit_list(90000, 0, 48000, 96000, 1000);
+        assert_eq!(elst_box.entries.len(), 2);
+        assert_eq!(elst_box.entries[0].media_time, -1);
+        assert_eq!(elst_box.entries[0].edit_duration, 1875);
+        assert_eq!(elst_box.entries[1].media_time, 0);
+        assert_eq!(elst_box.entries[1].edit_duration, 2000);
+
+        // zero 相当（開始が 0）の場合は空の編集を入れない
+        let elst_box = preserve_timeline_edit_list(0, 0, 48000, 96000, 1000);
+        assert_eq!(elst_box.entries.len(), 1);
+        assert_eq!(elst_box.entries[0].media_time, 0);
+
+        // 先頭のサンプルにコンポジションオフセットがある場合は、その表示時刻から編集を始める
+        let elst_box = preserve_timeline_edit_list(90000, 2000, 48000, 96000, 1000);
+        assert_eq!(elst_box.entries[1].media_time, 2000);
+        assert_eq!(elst_box.entries[1].edit_duration, 1958);
+
+        assert_eq!(parse_pts_offset("zero"), Ok(PtsOffset::Zero));
+        assert_eq!(parse_pts_offset("preserve"), Ok(PtsOffset::Preserve));
+        assert!(parse_pts_offset("keep").is_err());
+    }
+
+    #[test]
+    fn test_extract_with_empty_and_media_edits() {
+        // 30fps のビデオ 300 フレーム（1 秒ごとにキーフレーム）に、1 秒の空の編集と、
+        // メディアの 0.5 秒目から末尾までを表示する編集を持たせる
+        let input = Mp4Builder::new()
+            .track(TrackKind::Video, 30, &[1; 300], 30)
+            .build_with_edited_moov_box(|moov_box| {
+                let movie_timescale = moov_box.mvhd_box.timescale.get() as u64;
+                let normal_rate = FixedPointNumber::new(1, 0);
+                moov_box.trak_boxes[0].edts_box = Some(EdtsBox {
+                    elst_box: Some(ElstBox {
+                        entries: vec![
+                            ElstEntry {
+                                edit_duration: movie_timescale,
+                                media_time: -1,
+                                media_rate: normal_rate,
+                            },
+                            ElstEntry {
+                                edit_duration: 0,
+                                media_time: 15,
+                                media_rate: normal_rate,
+                            },
+                        ],
+                    }),
+                    unknown_boxes: Vec::new(),
+                });
+            });
+
+        // 表示上の 3 秒目はメディアの 2.5 秒目（75 フレーム目）なので、その直前の 60 フレーム目から抽出する。
+        // 最初の編集だけを見て 3 秒目をそのままメディア時刻とすると 90 フレーム目からになる
+        let range = ExtractRange::Time {
+            start_sec: 3.0,
+            end_sec: None,
+        };
+        let output = run_extract(&input, range, |_| {}).unwrap();
+        assert_eq!(track_sample_counts(&output), [(*b"vide", 240)]);
+    }
+
+    #[test]
+    fn test_extract_output_timescale() {
+        // タイムスケール 30 で 1 フレームずつ進む 30 フレーム
+        let input = Mp4Builder::new()
+            .track(TrackKind::Video, 30, &[1; 30], 1)
+            .build();
+        let range = ExtractRange::Time {
+            start_sec: 0.0,
+            end_sec: None,
+        };
+        let video_timescale = |timescale: u32| {
+            move |options: &mut ExtractOptions| {
+                options.output_timescales.video = NonZeroU32::new(timescale);
+            }
+        };
+
+        // 1/10 秒単位ではフレームの長さが 0 に丸められるものがあるので、エラーにする
+        let Err(error) = run_extract(&input, range, video_timescale(10)) else {
+            panic!("extracted with a timescale coarser than the frame duration");
+        };
+        assert!(error.contains("タイムスケール 10"), "{error}");
+
+        // 90000 ではドリフトなく 1 フレーム 3000 になる
+        let output = run_extract(&input, range, video_timescale(90000)).unwrap();
+        let mdia_box = &output.tracks()[0].mdia_box;
+        assert_eq!(mdia_box.mdhd_box.timescale.get(), 90000);
+        let sample_table = SampleTableAccessor::new(&mdia_box.minf_box.stbl_box).unwrap();
+        assert_eq!(sample_table.sample_count(), 30);
+        assert!(
+            sample_table
+                .samples()
+                .all(|sample| sample.duration() == 3000)
+        );
+    }
+
+    #[test]
+    fn test_extract_audio_lang() {
+        // 英語（20 ms 間隔の 500 サンプル）と日本語（1/30 秒間隔の 300 サンプル）の 2 つのオーディオトラック。
+        // Muxer は種別ごとに 1 トラックしか扱えないため、ビデオトラックをオーディオトラックに書き換える
+        let input = Mp4Builder::new()
+            .track(TrackKind::Audio, 1000, &[20; 500], 1)
+            .track(TrackKind::Video, 30, &[1; 300], 1)
+            .build_with_edited_moov_box(|moov_box| {
+                for trak_box in &mut moov_box.trak_boxes {
+                    let mdia_box = &mut trak_box.mdia_box;
+                    mdia_box.mdhd_box.language = if mdia_box.hdlr_box.handler_type == *b"vide" {
+                        *b"jpn"
+                    } else {
+                        *b"eng"
+                    };
+                    mdia_box.hdlr_box.handler_type = *b"soun";
+                }
+            });
+        let range = ExtractRange::Time {
+            start_sec: 0.0,
+            end_sec: None,
+        };
+        let audio_lang = |lang: &[u8; 3]| {
+            let lang = *lang;
+            move |options: &mut ExtractOptions| options.audio_lang = Some(lang)
+        };
+
+        let output = run_extract(&input, range, audio_lang(b"jpn")).unwrap();
+        assert_eq!(track_sample_counts(&output), [(*b"soun", 300)]);
+        let output = run_extract(&input, range, audio_lang(b"eng")).unwrap();
+        assert_eq!(track_sample_counts(&output), [(*b"soun", 500)]);
+
+        // 一致するトラックがない場合は、利用可能な言語を一覧表示する
+        let Err(error) = run_extract(&input, range, audio_lang(b"fra")) else {
+            panic!("extracted without a matching audio track");
+        };
+        assert!(error.contains("fra"), "{error}");
+        assert!(error.contains("eng, jpn"), "{error}");
+    }
+
+    #[test]
+    fn test_extract_pts_offset() {
+        // 30fps のビデオ 300 フレーム（1 秒ごとにキーフレーム）の 2 秒目から抽出する
+        let input = Mp4Builder::new()
+            .track(TrackKind::Video, 30, &[1; 300], 30)
+            .build();
+        let range = ExtractRange::Time {
+            start_sec: 2.0,
+            end_sec: Some(4.0),
+        };
+        let first_presentation_time = |pts_offset| {
+            let output =
+                run_extract(&input, range, |options| options.pts_offset = pts_offset).unwrap();
+            let trak_box = &output.tracks()[0];
+            let sample_table =
+                SampleTableAccessor::new(&trak_box.mdia_box.minf_box.stbl_box).unwrap();
+            let first_sample = sample_table.samples().next().unwrap();
+            media_to_presentation_time(
+                trak_box,
+                output.mvhd().timescale,
+                first_sample.timestamp() as i64,
+            )
+        };
+
+        assert_eq!(first_presentation_time(PtsOffset::Zero), 0.0);
+        // preserve では入力ファイル上の時刻を維持する
+        assert_eq!(first_presentation_time(PtsOffset::Preserve), 2.0);
+    }
+
+    #[test]
+    fn test_parse_stream_map() {
+        let stream_map = parse_stream_map("v:0, a:1").unwrap();
+        assert_eq!(
+            stream_map,
+            vec![
+                StreamSpec {
+                    track_kind: TrackKind::Video,
+                    index: 0
+                },
+                StreamSpec {
+                    track_kind: TrackKind::Audio,
+                    index: 1
+                },
+            ]
+        );
+
+        assert!(parse_stream_map("x:0").is_err());
+        assert!(parse_stream_map("v").is_err());
+        assert!(parse_stream_map("a:-1").is_err());
+        assert!(parse_stream_map("a:0,a:1").is_err());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "サンプルの書き込み位置")]
+    fn test_write_sample_data_detects_offset_drift() {
+        let mut output = Cursor::new(vec![0; 32]);
+        output.seek(SeekFrom::End(0)).unwrap();
+        // ftyp の置き換えで 4 バイト増えた場合の位置は一致する
+        write_sample_data(&mut output, &[1, 2, 3], 28, 4).unwrap();
+        // 直前のサンプルのサイズ分だけずれた位置を渡すと検出される
+        write_sample_data(&mut output, &[4, 5, 6], 32, 4).unwrap();
+    }
+
+    #[test]
+    fn test_write_finalized_boxes_mdat_size() {
+        use shiguredo_mp4::{
+            BoxSize, BoxType,
+            boxes::{RootBox, UnknownBox},
+        };
+
+        let sample_sizes = [100, 50, 75];
+        let mut muxer = Mp4FileMuxer::with_options(Mp4FileMuxerOptions {
+            reserved_moov_box_size: estimate_maximum_moov_box_size(&[sample_sizes.len()]),
+            ..Default::default()
+        })
+        .unwrap();
+        let mut output = Cursor::new(muxer.initial_boxes_bytes().to_vec());
+        output.seek(SeekFrom::End(0)).unwrap();
+        for (i, size) in sample_sizes.into_iter().enumerate() {
+            let data_offset = output.position();
+            write_sample_data(&mut output, &vec![i as u8; size], data_offset, 0).unwrap();
+            muxer
+                .append_sample(&Sample {
+                    track_kind: TrackKind::Video,
+                    sample_entry: Some(SampleEntry::Unknown(UnknownBox {
+                        box_type: BoxType::Normal(*b"test"),
+                        box_size: BoxSize::U32(8),
+                        payload: Vec::new(),
+                    })),
+                    keyframe: true,
+                    timescale: NonZeroU32::new(30).unwrap(),
+                    duration: 1,
+                    data_offset,
+                    data_size: size,
+                })
+                .unwrap();
+        }
+
+        let finalized = muxer.finalize().unwrap();
+        assert!(finalized.is_faststart_enabled());
+        let moov_bytes = finalized.moov_box().encode_to_vec().unwrap();
+        write_finalized_boxes(&mut output, finalized, &moov_bytes, 0).unwrap();
+
+        // 予約領域の残りが free ボックスで埋められ、ファイル全体を解析できること
+        let mp4_file = crate::mp4::decode_mp4_file(output.get_ref()).unwrap();
+        let mdat_payload_size = mp4_file
+            .boxes
+            .iter()
+            .find_map(|b| match b {
+                RootBox::Mdat(mdat_box) => Some(mdat_box.payload.len()),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(mdat_payload_size, sample_sizes.iter().sum::<usize>());
+    }
+
+    #[test]
+    fn test_verify_output_samples() {
+        let (data, moov_box) = Mp4Builder::new()
+            .track(TrackKind::Audio, 1000, &[20; 3], 1)
+            .build_with_moov_box();
+
+        let trak_box = &moov_box.trak_boxes[0];
+        let track_infos = [TrackExtractInfo {
+            track_kind: TrackKind::Audio,
+            timescale: trak_box.mdia_box.mdhd_box.timescale,
+            output_timescale: trak_box.mdia_box.mdhd_box.timescale,
+            sample_entry: test_sample_entry(),
+            start_sample_index: NonZeroU32::MIN,
+            end_sample_index: NonZeroU32::new(3).unwrap(),
+            start_timestamp: 0,
+            start_presentation_timestamp: 0,
+            end_presentation_timestamp: 60,
+            source_track_number: 1,
+            movie_timescale: moov_box.mvhd_box.timescale,
+            trak_box,
+            sample_table: SampleTableAccessor::new(&trak_box.mdia_box.minf_box.stbl_box).unwrap(),
+        }];
+
+        let mut source = Cursor::new(data.clone());
+        let mut output = Cursor::new(data);
+        assert_eq!(
+            verify_output_samples(&mut source, &mut output, &track_infos),
+            Ok(3)
+        );
+
+        // 2 番目のサンプルのデータを 1 バイト書き換えると不一致になる
+        let offset = track_infos[0]
+            .sample_table
+            .get_sample(NonZeroU32::new(2).unwrap())
+            .unwrap()
+            .data_offset();
+        output.get_mut()[offset as usize] ^= 0xff;
+        let error = verify_output_samples(&mut source, &mut output, &track_infos).unwrap_err();
+        assert!(error.contains("サンプル 2"), "{}", error);
+    }
 }
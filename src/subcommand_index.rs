@@ -0,0 +1,360 @@
+use std::{
+    io::{BufWriter, Write},
+    num::NonZeroU32,
+};
+
+use shiguredo_mp4::{aux::SampleTableAccessor, boxes::StblBox};
+
+use crate::io::{InputSource, OutputSink, READ_CACHE_OPT, parse_byte_size};
+use crate::mp4::{
+    CttsEntry, composition_offsets, parse_ctts_entries, read_moov_box, read_prft_boxes,
+    scan_root_boxes,
+};
+use crate::tr;
+
+const TRACK_OPT: noargs::OptSpec = noargs::opt("track")
+    .short('t')
+    .doc("インデックスを作成するトラックの番号（info で表示される 1 始まりの番号）")
+    .ty("TRACK")
+    .example("1");
+
+const OUTPUT_OPT: noargs::OptSpec = noargs::opt("output")
+    .short('o')
+    .doc("出力ファイルパス（省略時は stdout）")
+    .ty("PATH")
+    .example("index.csv");
+
+const KEYFRAMES_ONLY_FLAG: noargs::FlagSpec =
+    noargs::flag("keyframes-only").doc("キーフレーム（同期サンプル）のみを出力します");
+
+const UTC_FLAG: noargs::FlagSpec = noargs::flag("utc")
+    .doc("prft ボックスの時刻情報から求めた UTC の絶対時刻を utc 列に出力します");
+
+const SIDX_FLAG: noargs::FlagSpec = noargs::flag("sidx").doc(
+    "CSV の代わりに、キーフレームごとのセグメントのバイト範囲と長さを記述した sidx ボックスを外部インデックスとして出力します",
+);
+
+pub fn run(mut args: noargs::RawArgs) -> noargs::Result<()> {
+    let input_file_arg: Option<String> = noargs::arg("[INPUT_FILE]")
+        .example("/path/to/input.mp4")
+        .doc("インデックスを作成する MP4 ファイル（省略時は stdin から読み込み）")
+        .take(&mut args)
+        .then(|a| a.value().parse())
+        .ok();
+
+    let track_number: NonZeroU32 = TRACK_OPT.take(&mut args).then(|o| o.value().parse())?;
+
+    let output_file_arg: Option<String> =
+        OUTPUT_OPT.take(&mut args).then(|o| o.value().parse()).ok();
+
+    let keyframes_only = KEYFRAMES_ONLY_FLAG.take(&mut args).is_present();
+    let utc = UTC_FLAG.take(&mut args).is_present();
+    let sidx = SIDX_FLAG.take(&mut args).is_present();
+
+    let read_cache_size: u64 = READ_CACHE_OPT
+        .take(&mut args)
+        .then(|o| parse_byte_size(o.value()))?;
+    if let Some(help) = args.finish()? {
+        print!("{help}");
+        return Ok(());
+    }
+
+    let input_source = match InputSource::from_arg(input_file_arg) {
+        Some(source) => source,
+        None => {
+            eprintln!(
+                "{}",
+                tr!(
+                    "エラー: 入力ファイルを指定するか、パイプで入力してください",
+                    "Error: specify an input file or pipe the input"
+                )
+            );
+            eprintln!(
+                "{}",
+                tr!(
+                    "使用例: {}",
+                    "Example: {}",
+                    "mp4-util index input.mp4 -t 1 --keyframes-only"
+                )
+            );
+            eprintln!(
+                "{}",
+                tr!(
+                    "使用例: {}",
+                    "Example: {}",
+                    "cat input.mp4 | mp4-util index -t 1 > index.csv"
+                )
+            );
+            std::process::exit(1);
+        }
+    };
+
+    if sidx && (keyframes_only || utc) {
+        return Err(tr!(
+            "--sidx は --keyframes-only や --utc と同時に指定できません",
+            "--sidx cannot be used with --keyframes-only or --utc"
+        )
+        .into());
+    }
+
+    // テキスト出力なので TTY への出力も許可する（sidx はバイナリなので許可しない）
+    let output_sink = OutputSink::from_arg(output_file_arg, !sidx)?;
+
+    let mut reader = input_source.cached_reader(read_cache_size).map_err(|e| {
+        tr!(
+            "入力を開けません ({}): {}",
+            "Cannot open the input ({}): {}",
+            input_source.description(),
+            e
+        )
+    })?;
+    let moov_box = read_moov_box(&mut reader)?;
+
+    let trak_box = moov_box
+        .trak_boxes
+        .get(track_number.get() as usize - 1)
+        .ok_or_else(|| {
+            tr!(
+                "トラック {} が見つかりません（トラック数: {}）",
+                "Track {} not found (number of tracks: {})",
+                track_number,
+                moov_box.trak_boxes.len()
+            )
+        })?;
+    let stbl_box = &trak_box.mdia_box.minf_box.stbl_box;
+    let sample_table = SampleTableAccessor::new(stbl_box).map_err(|e| {
+        tr!(
+            "サンプルテーブルの解析に失敗しました: {}",
+            "Failed to parse the sample table: {}",
+            e
+        )
+    })?;
+    let timescale = trak_box.mdia_box.mdhd_box.timescale.get() as f64;
+    let ctts_entries = parse_ctts_entries(stbl_box).unwrap_or_default();
+    let mut offsets = composition_offsets(&ctts_entries);
+
+    if sidx {
+        let sidx_box = build_sidx_box(
+            trak_box.tkhd_box.track_id,
+            trak_box.mdia_box.mdhd_box.timescale.get(),
+            &sample_table,
+            &ctts_entries,
+        )?;
+        let mut writer = output_sink.writer().map_err(|e| {
+            tr!(
+                "出力先を開けません ({}): {}",
+                "Cannot open the output ({}): {}",
+                output_sink.description(),
+                e
+            )
+        })?;
+        writer.write_all(&sidx_box)?;
+        writer.flush()?;
+        return Ok(());
+    }
+
+    // 絶対時刻の基準となる、このトラックを参照する prft ボックス
+    let reference_time = if utc {
+        let root_boxes = scan_root_boxes(&mut reader)?;
+        let reference_time = read_prft_boxes(&mut reader, &root_boxes)?
+            .into_iter()
+            .find(|prft| prft.reference_track_id == trak_box.tkhd_box.track_id);
+        if reference_time.is_none() {
+            log::warn!(
+                "{}",
+                tr!(
+                    "トラック {} を参照する prft ボックスがないため、utc 列は空になります",
+                    "No prft box refers to track {}, so the utc column is left empty",
+                    track_number
+                )
+            );
+        }
+        reference_time
+    } else {
+        None
+    };
+
+    let mut writer = BufWriter::new(output_sink.writer().map_err(|e| {
+        tr!(
+            "出力先を開けません ({}): {}",
+            "Cannot open the output ({}): {}",
+            output_sink.description(),
+            e
+        )
+    })?);
+
+    if utc {
+        writeln!(writer, "pts_seconds,utc,byte_offset,is_keyframe")?;
+    } else {
+        writeln!(writer, "pts_seconds,byte_offset,is_keyframe")?;
+    }
+    for sample in sample_table.samples() {
+        // 表示時刻はデコード時刻にコンポジションオフセットを加えたもの
+        let pts = sample.timestamp() as i64 + offsets.next().unwrap_or(0);
+        let is_keyframe = sample.is_sync_sample();
+        if keyframes_only && !is_keyframe {
+            continue;
+        }
+        write!(writer, "{:.6},", pts as f64 / timescale)?;
+        if utc {
+            if let Some(prft) = &reference_time {
+                let elapsed = (pts as f64 - prft.media_time as f64) / timescale;
+                write!(writer, "{}", format_utc(prft.unix_time() + elapsed))?;
+            }
+            write!(writer, ",")?;
+        }
+        writeln!(writer, "{},{}", sample.data_offset(), is_keyframe)?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// キーフレームから次のキーフレームの直前までを一つのセグメントとして、sidx ボックス（バージョン 1）を組み立てる
+///
+/// 非フラグメント MP4 ではセグメントのサンプルが他のトラックのサンプルと交互に並ぶため、
+/// セグメントのバイト範囲は先頭サンプルの位置から次のセグメントの先頭サンプルの位置まで（最後のセグメントは
+/// 最後のサンプルの末尾まで）とする。外部ファイルなので first_offset は MP4 ファイル先頭からの絶対位置を表す
+fn build_sidx_box<T: AsRef<StblBox>>(
+    track_id: u32,
+    timescale: u32,
+    sample_table: &SampleTableAccessor<T>,
+    ctts_entries: &[CttsEntry],
+) -> Result<Vec<u8>, String> {
+    // (先頭サンプルの位置, 長さ) のセグメント一覧
+    let mut segments: Vec<(u64, u64)> = Vec::new();
+    let mut data_end = 0;
+    for sample in sample_table.samples() {
+        match segments.last_mut() {
+            Some((_, duration)) if !sample.is_sync_sample() => {
+                *duration += sample.duration() as u64;
+            }
+            _ => segments.push((sample.data_offset(), sample.duration() as u64)),
+        }
+        data_end = data_end.max(sample.data_offset() + sample.data_size() as u64);
+    }
+    let Some(&(first_offset, _)) = segments.first() else {
+        return Err(tr!(
+            "トラックにサンプルがありません",
+            "The track has no samples"
+        ));
+    };
+    let earliest_presentation_time = composition_offsets(ctts_entries)
+        .next()
+        .map_or(0, |offset| offset.max(0) as u64)
+        + sample_table
+            .get_sample(NonZeroU32::MIN)
+            .map_or(0, |sample| sample.timestamp());
+    let reference_count = u16::try_from(segments.len()).map_err(|_| {
+        tr!(
+            "セグメント数 {} が多すぎます",
+            "Too many segments ({})",
+            segments.len()
+        )
+    })?;
+
+    let mut payload = vec![1, 0, 0, 0];
+    payload.extend_from_slice(&track_id.to_be_bytes());
+    payload.extend_from_slice(&timescale.to_be_bytes());
+    payload.extend_from_slice(&earliest_presentation_time.to_be_bytes());
+    payload.extend_from_slice(&first_offset.to_be_bytes());
+    payload.extend_from_slice(&0u16.to_be_bytes());
+    payload.extend_from_slice(&reference_count.to_be_bytes());
+    for (i, &(offset, duration)) in segments.iter().enumerate() {
+        let next_offset = segments.get(i + 1).map_or(data_end, |&(next, _)| next);
+        let size = next_offset
+            .checked_sub(offset)
+            .filter(|&size| size < 1 << 31)
+            .ok_or_else(|| tr!("サンプルデータがキーフレームの順に並んでいないため、セグメントのバイト範囲を求められません", "Cannot determine the byte ranges of the segments because the sample data is not ordered by keyframe"))?;
+        let duration = u32::try_from(duration).map_err(|_| {
+            tr!(
+                "セグメントの長さ {} が大きすぎます",
+                "Segment duration {} is too large",
+                duration
+            )
+        })?;
+        // reference_type は 0（メディア）、各セグメントは SAP タイプ 1 で始まる
+        payload.extend_from_slice(&(size as u32).to_be_bytes());
+        payload.extend_from_slice(&duration.to_be_bytes());
+        payload.extend_from_slice(&0x9000_0000u32.to_be_bytes());
+    }
+
+    let mut sidx_box = (8 + payload.len() as u32).to_be_bytes().to_vec();
+    sidx_box.extend_from_slice(b"sidx");
+    sidx_box.extend_from_slice(&payload);
+    Ok(sidx_box)
+}
+
+/// UNIX 時刻（秒）を ISO 8601 形式の UTC 文字列（マイクロ秒精度）に変換する
+fn format_utc(unix_time: f64) -> String {
+    let micros = (unix_time * 1_000_000.0).round() as i64;
+    let seconds = micros.div_euclid(1_000_000);
+    let days = seconds.div_euclid(86_400);
+    let seconds_of_day = seconds.rem_euclid(86_400);
+
+    // 1970-01-01 からの日数をグレゴリオ暦の年月日に変換する
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z",
+        year,
+        month,
+        day,
+        seconds_of_day / 3600,
+        seconds_of_day / 60 % 60,
+        seconds_of_day % 60,
+        micros.rem_euclid(1_000_000)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_sidx_box() {
+        use crate::test_util::Mp4Builder;
+        use shiguredo_mp4::TrackKind;
+
+        // 1 秒ごとにキーフレームがある 3 秒間のビデオ
+        let mut builder = Mp4Builder::new();
+        let data_offset = builder.data_offset();
+        for i in 0..90 {
+            builder = builder.sample(TrackKind::Video, 30, 1, i % 30 == 0, &[0; 2]);
+        }
+        let moov_box = builder.moov_box();
+        let stbl_box = &moov_box.trak_boxes[0].mdia_box.minf_box.stbl_box;
+        let sample_table = SampleTableAccessor::new(stbl_box).unwrap();
+
+        let sidx_box = build_sidx_box(1, 30, &sample_table, &[]).unwrap();
+        assert_eq!(&sidx_box[4..8], b"sidx");
+        assert_eq!(sidx_box.len(), 8 + 32 + 3 * 12);
+        assert_eq!(
+            u64::from_be_bytes(sidx_box[28..36].try_into().unwrap()),
+            data_offset
+        );
+        assert_eq!(u16::from_be_bytes([sidx_box[38], sidx_box[39]]), 3);
+        for reference in sidx_box[40..].chunks_exact(12) {
+            // 各セグメントは 60 バイト、30 フレーム（1 秒）
+            assert_eq!(u32::from_be_bytes(reference[..4].try_into().unwrap()), 60);
+            assert_eq!(u32::from_be_bytes(reference[4..8].try_into().unwrap()), 30);
+        }
+    }
+
+    #[test]
+    fn test_format_utc() {
+        assert_eq!(format_utc(0.0), "1970-01-01T00:00:00.000000Z");
+        assert_eq!(format_utc(951_782_400.25), "2000-02-29T00:00:00.250000Z");
+        assert_eq!(format_utc(1_791_072_000.5), "2026-10-04T00:00:00.500000Z");
+        assert_eq!(format_utc(-0.5), "1969-12-31T23:59:59.500000Z");
+    }
+}
@@ -1,5 +1,42 @@
-use crate::io::InputSource;
-use crate::mp4::InputMp4;
+use std::io::Read;
+
+use crate::compat;
+use crate::io::{InputSource, parse_byte_size};
+use crate::mp4::{
+    EncoderDelay, InputMp4, MediaHeader, ResourceLimits, TrackInfo, parameter_sets,
+    unknown_box_paths,
+};
+use crate::tr;
+
+const PROBE_ONLY_FLAG: noargs::FlagSpec = noargs::flag("probe-only")
+    .doc("情報を表示せず、解析に成功したかどうかを終了コードのみで返します（成功: 0、失敗: 1）");
+
+const VERBOSE_FLAG: noargs::FlagSpec = noargs::flag("verbose")
+    .short('v')
+    .doc("詳細な情報を表示します（--probe-only と併用した場合は結果を valid / invalid として表示します）");
+
+const STRICT_FLAG: noargs::FlagSpec = noargs::flag("strict").doc(
+    "moov ボックス内に解釈できないボックスや未対応のサンプルエントリーがある場合に、それらを列挙してエラーにします",
+);
+
+const SUMMARY_ONLY_FLAG: noargs::FlagSpec = noargs::flag("summary-only")
+    .doc("トラックごとの詳細を表示せず、トラック数とトラックの概要を 1 行で表示します");
+
+const TABLE_FLAG: noargs::FlagSpec = noargs::flag("table")
+    .doc("トラックごとの情報を、項目を行・トラックを列とする桁をそろえた表で表示します");
+
+const SHOW_SPS_FLAG: noargs::FlagSpec = noargs::flag("show-sps")
+    .doc("avcC / hvcC に格納されているパラメータセット（VPS / SPS / PPS）を NAL ユニットタイプ付きの 16 進数で表示します");
+
+const COMPAT_CHECK_OPT: noargs::OptSpec = noargs::opt("compat-check")
+    .doc("指定したプラットフォーム（safari / chrome / firefox）で既知の再生制限に該当するトラックを警告します")
+    .ty("PLATFORM")
+    .example("safari");
+
+const MAX_MEMORY_OPT: noargs::OptSpec = noargs::opt("max-memory")
+    .doc("解析時に読み込むデータ量の上限（K/M/G の接尾辞に対応）。指定時は moov ボックスのみを読み込みます")
+    .ty("SIZE")
+    .example("64M");
 
 pub fn run(mut args: noargs::RawArgs) -> noargs::Result<()> {
     let input_file_arg: Option<String> = noargs::arg("[INPUT_FILE]")
@@ -8,6 +45,19 @@ pub fn run(mut args: noargs::RawArgs) -> noargs::Result<()> {
         .take(&mut args)
         .then(|a| a.value().parse())
         .ok();
+    let max_memory: Option<u64> = MAX_MEMORY_OPT
+        .take(&mut args)
+        .present_and_then(|o| parse_byte_size(o.value()))?;
+    let limits = ResourceLimits::take(&mut args)?;
+    let probe_only = PROBE_ONLY_FLAG.take(&mut args).is_present();
+    let verbose = VERBOSE_FLAG.take(&mut args).is_present();
+    let summary_only = SUMMARY_ONLY_FLAG.take(&mut args).is_present();
+    let table = TABLE_FLAG.take(&mut args).is_present();
+    let strict = STRICT_FLAG.take(&mut args).is_present();
+    let show_sps = SHOW_SPS_FLAG.take(&mut args).is_present();
+    let compat_platform: Option<String> = COMPAT_CHECK_OPT
+        .take(&mut args)
+        .present_and_then(|o| o.value().parse())?;
     if let Some(help) = args.finish()? {
         print!("{help}");
         return Ok(());
@@ -17,55 +67,871 @@ pub fn run(mut args: noargs::RawArgs) -> noargs::Result<()> {
         Some(source) => source,
         None => {
             // stdin が TTY で引数もない場合はヘルプを表示
-            eprintln!("エラー: 入力ファイルを指定するか、パイプで入力してください");
-            eprintln!("使用例: mp4-util info input.mp4");
-            eprintln!("使用例: cat input.mp4 | mp4-util info");
+            eprintln!(
+                "{}",
+                tr!(
+                    "エラー: 入力ファイルを指定するか、パイプで入力してください",
+                    "Error: specify an input file or pipe the input"
+                )
+            );
+            eprintln!(
+                "{}",
+                tr!("使用例: {}", "Example: {}", "mp4-util info input.mp4")
+            );
+            eprintln!(
+                "{}",
+                tr!("使用例: {}", "Example: {}", "cat input.mp4 | mp4-util info")
+            );
             std::process::exit(1);
         }
     };
 
-    let reader = input_source
-        .reader()
-        .map_err(|e| format!("入力を開けません ({}): {}", input_source.description(), e))?;
+    let compat_label = compat_platform
+        .as_deref()
+        .map(compat::platform_label)
+        .transpose()?;
+
+    if probe_only {
+        // 入力を開けない場合も不正なファイルとして扱う
+        let valid = input_source.reader().is_ok_and(|reader| {
+            parse_input(reader, max_memory, limits)
+                .is_ok_and(|mp4| !strict || unknown_box_paths(mp4.moov()).is_empty())
+        });
+        if verbose {
+            println!("{}", if valid { "valid" } else { "invalid" });
+        }
+        if !valid {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let reader = input_source.reader().map_err(|e| {
+        tr!(
+            "入力を開けません ({}): {}",
+            "Cannot open input ({}): {}",
+            input_source.description(),
+            e
+        )
+    })?;
+
+    let input_mp4 = parse_input(reader, max_memory, limits)?;
+    if strict {
+        let paths = unknown_box_paths(input_mp4.moov());
+        if !paths.is_empty() {
+            return Err(tr!(
+                "解釈できないボックスがあります（--strict）:\n  {}",
+                "Found boxes that could not be decoded (--strict):\n  {}",
+                paths.join("\n  ")
+            )
+            .into());
+        }
+    }
+    if summary_only {
+        let tracks = input_mp4.get_track_infos().unwrap_or_default();
+        println!("{}", summary_line(&tracks));
+    } else {
+        print_mp4_info(&input_mp4, verbose, table);
+    }
+    if show_sps {
+        print_parameter_sets(&input_mp4);
+    }
 
-    let input_mp4 = InputMp4::parse(reader)?;
-    print_mp4_info(&input_mp4);
+    if let (Some(platform), Some(label)) = (&compat_platform, compat_label) {
+        let tracks = input_mp4.get_track_infos().unwrap_or_default();
+        println!(
+            "\n{}",
+            tr!("互換性チェック ({}):", "Compatibility check ({}):", label)
+        );
+        let warnings = compat::check(platform, &tracks);
+        if warnings.is_empty() {
+            println!(
+                "{}",
+                tr!(
+                    "既知の再生制限は見つかりませんでした",
+                    "No known playback limitations found"
+                )
+            );
+        }
+        for (track_number, message) in warnings {
+            println!(
+                "{}",
+                tr!(
+                    "警告: トラック {}: {}",
+                    "Warning: track {}: {}",
+                    track_number,
+                    message
+                )
+            );
+        }
+    }
     Ok(())
 }
 
-fn print_mp4_info(mp4: &InputMp4) {
+/// メモリ上限が指定されている場合は moov ボックスのみを読み込むストリーミング解析を行う
+///
+/// トラック数やサンプル数が `limits` を超える場合は、トラックごとの処理に進まずにエラーを返す
+fn parse_input(
+    reader: impl Read,
+    max_memory: Option<u64>,
+    limits: ResourceLimits,
+) -> Result<InputMp4, String> {
+    let mp4 = match max_memory {
+        Some(max_memory) => InputMp4::parse_streaming(reader, max_memory)?,
+        None => InputMp4::parse(reader)?,
+    };
+    limits.check(mp4.moov())?;
+    Ok(mp4)
+}
+
+/// info サブコマンドと同じ形式で MP4 ファイルの情報を表示する
+///
+/// `table` の場合、トラックごとの情報を項目を揃えた表で表示する（既定は 1 行 1 項目の形式）
+pub fn print_mp4_info(mp4: &InputMp4, verbose: bool, table: bool) {
     let tracks = match mp4.get_track_infos() {
         Some(tracks) => tracks,
         None => {
-            println!("トラック情報が取得できませんでした。");
+            println!(
+                "{}",
+                tr!(
+                    "トラック情報が取得できませんでした。",
+                    "Could not read track information."
+                )
+            );
             return;
         }
     };
 
-    println!("MP4ファイル情報：");
-    println!("トラック数: {}", tracks.len());
+    println!("{}", tr!("MP4ファイル情報：", "MP4 file information:"));
+    let mvhd = mp4.mvhd();
+    let movie_duration = mvhd.duration as f64 / mvhd.timescale.get() as f64;
+    println!(
+        "{}",
+        tr!(
+            "ムービーの長さ (mvhd): {}",
+            "Movie duration (mvhd): {}",
+            format_duration(movie_duration)
+        )
+    );
+    println!("{}", tr!("トラック数: {}", "Tracks: {}", tracks.len()));
+    if tracks.is_empty() {
+        println!("{}", tr!("トラックが含まれていません", "No tracks found"));
+        return;
+    }
+    if let Some(longest) = longest_track_duration(&tracks)
+        && (movie_duration - longest).abs() > MOVIE_DURATION_TOLERANCE_SEC
+    {
+        log::warn!(
+            "{}",
+            tr!(
+                "ムービーの長さ (mvhd: {:.3}秒) が最も長いトラックの長さ ({:.3}秒) と一致しません（編集リストによるものか、長さの情報が更新されていない可能性があります）",
+                "Movie duration (mvhd: {:.3}s) does not match the longest track ({:.3}s); this may be due to an edit list or a stale duration",
+                movie_duration,
+                longest
+            )
+        );
+    }
+    if tracks.iter().any(|t| t.sample_count.is_some()) {
+        let total_samples: u64 = tracks
+            .iter()
+            .filter_map(|t| t.sample_count)
+            .map(u64::from)
+            .sum();
+        println!(
+            "{}",
+            tr!("総サンプル数: {}", "Total samples: {}", total_samples)
+        );
+    }
+    if tracks.iter().any(|t| t.chunk_count.is_some()) {
+        let total_chunks: u64 = tracks
+            .iter()
+            .filter_map(|t| t.chunk_count)
+            .map(u64::from)
+            .sum();
+        println!(
+            "{}",
+            tr!("総チャンク数: {}", "Total chunks: {}", total_chunks)
+        );
+    }
+
+    if let Some(difference) = av_duration_difference(&tracks)
+        && difference > AV_DURATION_TOLERANCE_SEC
+    {
+        log::warn!(
+            "{}",
+            tr!(
+                "音声と映像の長さが {:.1} 秒ずれています",
+                "Audio and video durations differ by {:.1} seconds",
+                difference
+            )
+        );
+    }
+
+    let mut audio_number = 0;
+    let track_fields: Vec<Vec<TrackField>> = tracks
+        .iter()
+        .map(|track| {
+            let audio_number = (track.media_type == "オーディオ").then(|| {
+                audio_number += 1;
+                audio_number
+            });
+            track_fields(track, audio_number, verbose)
+        })
+        .collect();
+
+    if table {
+        for (i, track) in tracks.iter().enumerate() {
+            warn_external_data_reference(i, track);
+        }
+        println!("\n{}", format_table(&track_table_rows(&track_fields)));
+        return;
+    }
+    for (i, (track, fields)) in tracks.iter().zip(&track_fields).enumerate() {
+        println!("\n{}", tr!("トラック {}:", "Track {}:", i + 1));
+        // 外部のメディアデータを参照している場合の警告は、コーデックの項目の直後に出す
+        let codec_labels = [
+            tr!("コーデック", "Codec"),
+            tr!("コーデック詳細", "Codec details"),
+        ];
+        let warning_position = fields
+            .iter()
+            .rposition(|field| {
+                matches!(field, TrackField::Value(label, _) if codec_labels.contains(label))
+            })
+            .map_or(fields.len(), |position| position + 1);
+        for (j, field) in fields.iter().enumerate() {
+            if j == warning_position {
+                warn_external_data_reference(i, track);
+            }
+            println!("{}", field.line());
+        }
+        if warning_position == fields.len() {
+            warn_external_data_reference(i, track);
+        }
+    }
+}
+
+/// info で表示するトラックの情報の 1 項目
+enum TrackField {
+    /// 「項目名: 値」の形式で表示する項目
+    Value(String, String),
+    /// エンコーダ遅延とパディング（1 行形式では 1 行にまとめ、表では別々の項目に分ける）
+    EncoderDelay(EncoderDelay),
+    /// メディア長と、それと異なる表示長（秒。1 行形式では 1 行にまとめ、表では別々の項目に分ける）
+    PresentationDuration { media: f64, presentation: f64 },
+}
+
+impl TrackField {
+    /// 1 行形式で表示する行
+    fn line(&self) -> String {
+        match self {
+            Self::Value(label, value) => format!("{}: {}", label, value),
+            Self::EncoderDelay(encoder_delay) => tr!(
+                "エンコーダ遅延: {} samples, パディング: {} samples",
+                "Encoder delay: {} samples, padding: {} samples",
+                encoder_delay.delay,
+                encoder_delay.padding
+            ),
+            Self::PresentationDuration {
+                media,
+                presentation,
+            } => tr!(
+                "メディア長: {:.2}s / 表示長: {:.2}s",
+                "Media duration: {:.2}s / Presentation duration: {:.2}s",
+                media,
+                presentation
+            ),
+        }
+    }
+
+    /// 表で表示する項目名と値の組
+    fn table_values(&self) -> Vec<(String, String)> {
+        match self {
+            Self::Value(label, value) => vec![(label.clone(), value.clone())],
+            Self::EncoderDelay(encoder_delay) => vec![
+                (
+                    tr!("エンコーダ遅延", "Encoder delay"),
+                    format!("{} samples", encoder_delay.delay),
+                ),
+                (
+                    tr!("パディング", "Padding"),
+                    format!("{} samples", encoder_delay.padding),
+                ),
+            ],
+            Self::PresentationDuration {
+                media,
+                presentation,
+            } => vec![
+                (tr!("再生時間", "Duration"), format_duration(*media)),
+                (
+                    tr!("表示長", "Presentation duration"),
+                    format_duration(*presentation),
+                ),
+            ],
+        }
+    }
+}
+
+/// トラックの情報を表示する項目を生成する
+///
+/// `audio_number` はオーディオトラックの場合の 1 から始まる通し番号
+fn track_fields(track: &TrackInfo, audio_number: Option<usize>, verbose: bool) -> Vec<TrackField> {
+    let mut fields = vec![
+        TrackField::Value(tr!("トラックID", "Track ID"), track.track_id.to_string()),
+        TrackField::Value(
+            tr!("メディアタイプ", "Media type"),
+            media_type_label(&track.media_type),
+        ),
+    ];
+    if let Some(audio_number) = audio_number {
+        fields.push(TrackField::Value(
+            tr!("表示名", "Label"),
+            audio_track_label(audio_number, track),
+        ));
+    }
+    if let Some(handler_name) = &track.handler_name {
+        fields.push(TrackField::Value(
+            tr!("ハンドラ名", "Handler name"),
+            handler_name.clone(),
+        ));
+    }
+    // 表示長はメディアのタイムスケールの整数で比較し、メディア長と異なる場合のみ併記する
+    match track
+        .presentation_duration
+        .filter(|&duration| duration != track.media_duration)
+    {
+        Some(presentation_duration) => fields.push(TrackField::PresentationDuration {
+            media: track.duration,
+            presentation: presentation_duration as f64 / track.timescale as f64,
+        }),
+        None => fields.push(TrackField::Value(
+            tr!("再生時間", "Duration"),
+            format_duration(track.duration),
+        )),
+    }
+    if let Some(presentation_start) = track.presentation_start {
+        fields.push(TrackField::Value(
+            tr!("プレゼンテーション開始", "Presentation start"),
+            tr!("{:.3}秒", "{:.3}s", presentation_start),
+        ));
+    }
+    fields.push(TrackField::Value(
+        tr!("コーデック", "Codec"),
+        track.codec.clone(),
+    ));
+    if let Some(codec_details) = &track.codec_details {
+        fields.push(TrackField::Value(
+            tr!("コーデック詳細", "Codec details"),
+            format!("{} {}", track.codec, codec_details),
+        ));
+    }
+    if let Some(encoder_delay) = track.encoder_delay {
+        fields.push(TrackField::EncoderDelay(encoder_delay));
+    }
+    if let Some(sample_count) = track.sample_count {
+        fields.push(TrackField::Value(
+            tr!("サンプル数", "Samples"),
+            sample_count.to_string(),
+        ));
+    }
+    if let Some(chunk_count) = track.chunk_count {
+        fields.push(TrackField::Value(
+            tr!("チャンク数", "Chunks"),
+            chunk_count.to_string(),
+        ));
+    }
+
+    if verbose {
+        match &track.media_header {
+            Some(MediaHeader::Video {
+                graphics_mode,
+                opcolor,
+            }) => {
+                fields.push(TrackField::Value(
+                    tr!("グラフィックモード (vmhd)", "Graphics mode (vmhd)"),
+                    graphics_mode.to_string(),
+                ));
+                fields.push(TrackField::Value(
+                    "opcolor (vmhd)".to_string(),
+                    format!("({}, {}, {})", opcolor[0], opcolor[1], opcolor[2]),
+                ));
+            }
+            Some(MediaHeader::Sound { balance }) => {
+                fields.push(TrackField::Value(
+                    tr!("バランス (smhd)", "Balance (smhd)"),
+                    format!("{:.3}", balance),
+                ));
+            }
+            None => {}
+        }
+        fields.push(TrackField::Value(
+            tr!(
+                "負のコンポジションオフセット",
+                "Negative composition offsets"
+            ),
+            if track.has_negative_cts_offsets {
+                tr!("あり", "yes")
+            } else {
+                tr!("なし", "no")
+            },
+        ));
+    }
+    fields
+}
+
+/// 各トラックのコーデック設定ボックスに格納されているパラメータセットを 16 進数で表示する
+fn print_parameter_sets(mp4: &InputMp4) {
+    println!("\n{}", tr!("パラメータセット:", "Parameter sets:"));
+    for (i, trak) in mp4.tracks().iter().enumerate() {
+        let sets = trak
+            .mdia_box
+            .minf_box
+            .stbl_box
+            .stsd_box
+            .entries
+            .first()
+            .map(parameter_sets)
+            .unwrap_or_default();
+        if sets.is_empty() {
+            continue;
+        }
+        println!("{}", tr!("トラック {}:", "Track {}:", i + 1));
+        for set in sets {
+            println!(
+                "{}",
+                tr!(
+                    "  {} (NAL タイプ {}, {} bytes): {}",
+                    "  {} (NAL type {}, {} bytes): {}",
+                    set.name,
+                    set.nal_unit_type,
+                    set.data.len(),
+                    to_hex(&set.data)
+                )
+            );
+        }
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// ムービーの長さとトラックの長さの不一致を警告する閾値（秒）
+///
+/// タイムスケールの違いによる丸め誤差は警告しないようにする
+const MOVIE_DURATION_TOLERANCE_SEC: f64 = 0.1;
+
+/// 最も長いトラックの長さ（秒）を返す（トラックがない場合は `None`）
+fn longest_track_duration(tracks: &[TrackInfo]) -> Option<f64> {
+    tracks
+        .iter()
+        .map(|t| t.duration)
+        .filter(|duration| duration.is_finite())
+        .reduce(f64::max)
+}
+
+/// 音声と映像の長さのずれを警告する閾値（秒）
+const AV_DURATION_TOLERANCE_SEC: f64 = 0.5;
+
+/// 最も長いビデオトラックと最も長いオーディオトラックの長さの差（秒）を返す
+///
+/// ビデオとオーディオの両方のトラックがない場合は `None` を返す
+fn av_duration_difference(tracks: &[TrackInfo]) -> Option<f64> {
+    let longest = |media_type: &str| {
+        tracks
+            .iter()
+            .filter(|t| t.media_type == media_type && t.duration.is_finite())
+            .map(|t| t.duration)
+            .reduce(f64::max)
+    };
+    Some((longest("ビデオ")? - longest("オーディオ")?).abs())
+}
+
+/// トラック数と各トラックの種類・コーデック・再生時間を 1 行にまとめた概要を生成する
+fn summary_line(tracks: &[TrackInfo]) -> String {
+    let overviews: Vec<String> = tracks
+        .iter()
+        .map(|track| {
+            tr!(
+                "{} {} ({:.2}秒)",
+                "{} {} ({:.2}s)",
+                media_type_label(&track.media_type),
+                track.codec,
+                track.duration
+            )
+        })
+        .collect();
+    if overviews.is_empty() {
+        return tr!("0 トラック", "0 tracks");
+    }
+    tr!(
+        "{} トラック: {}",
+        "{} tracks: {}",
+        tracks.len(),
+        overviews.join(&tr!("、", ", "))
+    )
+}
 
-    for (i, track) in tracks.iter().enumerate() {
-        println!("\nトラック {}:", i + 1);
-        println!("メディアタイプ: {}", track.media_type);
-        println!("再生時間: {}", format_duration(track.duration));
-        println!("コーデック: {}", track.codec);
+/// トラックのメディアデータが外部のファイルを参照している場合に警告する
+fn warn_external_data_reference(i: usize, track: &TrackInfo) {
+    if let Some(reference) = &track.external_data_reference {
+        log::warn!(
+            "{}",
+            tr!(
+                "トラック {} は外部のメディアデータを参照しています: {}",
+                "Track {} references external media data: {}",
+                i + 1,
+                reference
+            )
+        );
+    }
+}
 
-        if let Some(sample_count) = track.sample_count {
-            println!("サンプル数: {}", sample_count);
+/// `--table` で表示する行（先頭の列は項目名、以降はトラックごとの値）を生成する
+///
+/// 項目は各トラックの項目の順序を保つように並べ、値がないトラックは「-」とする
+fn track_table_rows(track_fields: &[Vec<TrackField>]) -> Vec<Vec<String>> {
+    let track_fields: Vec<Vec<(String, String)>> = track_fields
+        .iter()
+        .map(|fields| fields.iter().flat_map(TrackField::table_values).collect())
+        .collect();
+    let mut labels: Vec<&str> = Vec::new();
+    for fields in &track_fields {
+        let mut position = 0;
+        for (label, _) in fields {
+            match labels.iter().position(|l| l == label) {
+                Some(i) => position = i + 1,
+                None => {
+                    labels.insert(position, label);
+                    position += 1;
+                }
+            }
         }
-        if let Some(chunk_count) = track.chunk_count {
-            println!("チャンク数: {}", chunk_count);
+    }
+
+    let header = std::iter::once(String::new())
+        .chain((1..=track_fields.len()).map(|n| tr!("トラック {}", "Track {}", n)))
+        .collect();
+    let rows = labels.into_iter().map(|label| {
+        std::iter::once(label.to_string())
+            .chain(track_fields.iter().map(|fields| {
+                fields
+                    .iter()
+                    .find(|(l, _)| l == label)
+                    .map_or_else(|| "-".to_string(), |(_, value)| value.clone())
+            }))
+            .collect()
+    });
+    std::iter::once(header).chain(rows).collect()
+}
+
+/// 各列を最も幅の広い値に合わせて空白で埋め、列の間を 2 文字空けた表を生成する（末尾の空白は付けない）
+fn format_table(rows: &[Vec<String>]) -> String {
+    let mut widths: Vec<usize> = Vec::new();
+    for row in rows {
+        for (i, cell) in row.iter().enumerate() {
+            let width = display_width(cell);
+            match widths.get_mut(i) {
+                Some(w) => *w = (*w).max(width),
+                None => widths.push(width),
+            }
         }
     }
+    rows.iter()
+        .map(|row| {
+            let mut line = String::new();
+            for (cell, width) in row.iter().zip(&widths) {
+                line.push_str(cell);
+                line.extend(std::iter::repeat_n(' ', width - display_width(cell) + 2));
+            }
+            line.trim_end().to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// 端末上での表示幅（日本語などの全角文字を 2、それ以外を 1 として数える）
+fn display_width(s: &str) -> usize {
+    s.chars()
+        .map(|c| match c as u32 {
+            0x1100..=0x115F
+            | 0x2E80..=0x303E
+            | 0x3041..=0x33FF
+            | 0x3400..=0x4DBF
+            | 0x4E00..=0x9FFF
+            | 0xAC00..=0xD7A3
+            | 0xF900..=0xFAFF
+            | 0xFE30..=0xFE4F
+            | 0xFF00..=0xFF60
+            | 0xFFE0..=0xFFE6 => 2,
+            _ => 1,
+        })
+        .sum()
+}
+
+/// `TrackInfo::media_type`（「ビデオ」「オーディオ」「不明」）を表示言語に合わせる
+fn media_type_label(media_type: &str) -> String {
+    match media_type {
+        "ビデオ" => tr!("ビデオ", "Video"),
+        "オーディオ" => tr!("オーディオ", "Audio"),
+        _ => tr!("不明", "Unknown"),
+    }
+}
+
+/// エンコーダーなどが既定で設定するハンドラ名（トラックの区別には使えないので表示名に含めない）
+const DEFAULT_SOUND_HANDLER_NAMES: &[&str] = &[
+    "SoundHandler",
+    "Sound Media Handler",
+    "Apple Sound Media Handler",
+    "Core Media Audio",
+    "GPAC ISO Audio Handler",
+    "L-SMASH Audio Handler",
+];
+
+/// 複数のオーディオトラックを区別しやすいよう、言語・コーデック・チャンネル構成をまとめた表示名を生成する
+///
+/// `audio_number` はオーディオトラックの中での 1 始まりの番号。
+/// ハンドラ名が既定の名前でない場合は末尾に括弧付きで添える
+fn audio_track_label(audio_number: usize, track: &TrackInfo) -> String {
+    let mut parts = Vec::new();
+    if let Some(language) = &track.language {
+        parts.push(language_label(language));
+    }
+    parts.push(match track.sample_entry_type.as_deref() {
+        Some("mp4a") => "AAC".to_string(),
+        Some("Opus") => "Opus".to_string(),
+        Some("fLaC") => "FLAC".to_string(),
+        _ => track.codec.clone(),
+    });
+    if let Some(channel_count) = track.channel_count {
+        parts.push(channel_layout_label(channel_count));
+    }
+    let mut label = tr!("音声{}: {}", "Audio {}: {}", audio_number, parts.join(" "));
+    if let Some(handler_name) = track
+        .handler_name
+        .as_deref()
+        .filter(|name| !DEFAULT_SOUND_HANDLER_NAMES.contains(name))
+    {
+        label.push_str(&format!(" ({})", handler_name));
+    }
+    label
+}
+
+/// ISO 639-2 の言語コードを言語名にする（主要な言語以外はコードのまま）
+fn language_label(language: &str) -> String {
+    match language {
+        "jpn" => tr!("日本語", "Japanese"),
+        "eng" => tr!("英語", "English"),
+        "zho" | "chi" => tr!("中国語", "Chinese"),
+        "kor" => tr!("韓国語", "Korean"),
+        "fra" | "fre" => tr!("フランス語", "French"),
+        "deu" | "ger" => tr!("ドイツ語", "German"),
+        "spa" => tr!("スペイン語", "Spanish"),
+        "ita" => tr!("イタリア語", "Italian"),
+        "por" => tr!("ポルトガル語", "Portuguese"),
+        "rus" => tr!("ロシア語", "Russian"),
+        _ => language.to_string(),
+    }
+}
+
+fn channel_layout_label(channel_count: u16) -> String {
+    match channel_count {
+        1 => tr!("モノラル", "Mono"),
+        2 => tr!("ステレオ", "Stereo"),
+        6 => "5.1ch".to_string(),
+        8 => "7.1ch".to_string(),
+        n => format!("{}ch", n),
+    }
 }
 
 /// 秒数から「分:秒」形式の文字列を生成する
+///
+/// NaN や無限大の場合は「不明」を返す。負の値（不正な編集リストなど）は符号を付けて表示する
 fn format_duration(duration_seconds: f64) -> String {
-    let minutes = (duration_seconds / 60.0).floor();
-    let seconds = duration_seconds % 60.0;
-    format!(
-        "{:.0}分{:.1}秒 ({:.2}秒)",
-        minutes, seconds, duration_seconds
+    if !duration_seconds.is_finite() {
+        return tr!("不明", "unknown");
+    }
+    let sign = if duration_seconds < 0.0 { "-" } else { "" };
+    let minutes = (duration_seconds.abs() / 60.0).floor();
+    let seconds = duration_seconds.abs() % 60.0;
+    tr!(
+        "{}{:.0}分{:.1}秒 ({:.2}秒)",
+        "{}{:.0}m{:.1}s ({:.2}s)",
+        sign,
+        minutes,
+        seconds,
+        duration_seconds
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary_line() {
+        let track = TrackInfo {
+            track_id: 1,
+            media_type: "ビデオ".to_string(),
+            duration: 10.0,
+            codec: "AVC(H.264)".to_string(),
+            sample_entry_type: Some("avc1".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            summary_line(std::slice::from_ref(&track)),
+            "1 トラック: ビデオ AVC(H.264) (10.00秒)"
+        );
+        assert_eq!(summary_line(&[]), "0 トラック");
+    }
+
+    #[test]
+    fn test_av_duration_difference() {
+        let track = |media_type: &str, duration: f64| TrackInfo {
+            track_id: 1,
+            media_type: media_type.to_string(),
+            duration,
+            ..Default::default()
+        };
+        let difference =
+            av_duration_difference(&[track("ビデオ", 10.0), track("オーディオ", 8.8)]).unwrap();
+        assert!((difference - 1.2).abs() < 1e-9);
+        assert_eq!(av_duration_difference(&[track("ビデオ", 10.0)]), None);
+    }
+
+    #[test]
+    fn test_audio_track_label() {
+        let mut track = TrackInfo {
+            track_id: 3,
+            media_type: "オーディオ".to_string(),
+            duration: 10.0,
+            codec: "MPEG AAC Audio (mp4a)".to_string(),
+            sample_entry_type: Some("mp4a".to_string()),
+            handler_name: Some("SoundHandler".to_string()),
+            channel_count: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(audio_track_label(2, &track), "音声2: AAC ステレオ");
+
+        track.language = Some("jpn".to_string());
+        assert_eq!(audio_track_label(2, &track), "音声2: 日本語 AAC ステレオ");
+
+        track.handler_name = Some("Commentary".to_string());
+        track.channel_count = Some(6);
+        assert_eq!(
+            audio_track_label(1, &track),
+            "音声1: 日本語 AAC 5.1ch (Commentary)"
+        );
+    }
+
+    #[test]
+    fn test_format_table() {
+        let rows = [
+            vec![
+                String::new(),
+                "トラック 1".to_string(),
+                "トラック 2".to_string(),
+            ],
+            vec!["ID".to_string(), "1".to_string(), "2".to_string()],
+            vec![
+                "コーデック".to_string(),
+                "Opus".to_string(),
+                "-".to_string(),
+            ],
+        ];
+        assert_eq!(
+            format_table(&rows),
+            [
+                "            トラック 1  トラック 2",
+                "ID          1           2",
+                "コーデック  Opus        -",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn test_track_table_rows() {
+        let fields = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|&(label, value)| TrackField::Value(label.to_string(), value.to_string()))
+                .collect::<Vec<_>>()
+        };
+        let mut audio_fields = fields(&[("ID", "1"), ("表示名", "音声1"), ("バランス", "0.000")]);
+        audio_fields.insert(
+            2,
+            TrackField::EncoderDelay(EncoderDelay {
+                delay: 312,
+                padding: 0,
+            }),
+        );
+        let track_fields = [
+            audio_fields,
+            fields(&[("ID", "2"), ("表示長", "1秒"), ("グラフィックモード", "0")]),
+        ];
+        // トラックごとの項目の順序を保ち、値がない項目は「-」になる
+        let rows = track_table_rows(&track_fields);
+        let rows: Vec<Vec<&str>> = rows
+            .iter()
+            .skip(1)
+            .map(|row| row.iter().map(String::as_str).collect())
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                ["ID", "1", "2"],
+                ["表示長", "-", "1秒"],
+                ["グラフィックモード", "-", "0"],
+                ["表示名", "音声1", "-"],
+                ["エンコーダ遅延", "312 samples", "-"],
+                ["パディング", "0 samples", "-"],
+                ["バランス", "0.000", "-"],
+            ]
+        );
+        // 1 行形式ではエンコーダ遅延とパディングを 1 行にまとめる
+        assert_eq!(
+            track_fields[0][2].line(),
+            "エンコーダ遅延: 312 samples, パディング: 0 samples"
+        );
+    }
+
+    #[test]
+    fn test_track_fields_presentation_duration() {
+        let track = TrackInfo {
+            track_id: 1,
+            duration: 60.0,
+            timescale: 1000,
+            media_duration: 60000,
+            presentation_duration: Some(59960),
+            ..Default::default()
+        };
+        // 表示長がメディア長と異なる場合は 1 行にまとめて表示する
+        let lines: Vec<String> = track_fields(&track, None, false)
+            .iter()
+            .map(TrackField::line)
+            .collect();
+        assert!(lines.contains(&"メディア長: 60.00s / 表示長: 59.96s".to_string()));
+
+        // 表示長がメディア長と一致する場合は再生時間のみを表示する
+        let track = TrackInfo {
+            presentation_duration: Some(60000),
+            ..track
+        };
+        let lines: Vec<String> = track_fields(&track, None, false)
+            .iter()
+            .map(TrackField::line)
+            .collect();
+        assert!(lines.iter().any(|line| line.starts_with("再生時間: ")));
+        assert!(!lines.iter().any(|line| line.contains("表示長")));
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(65.5), "1分5.5秒 (65.50秒)");
+        assert_eq!(format_duration(-5.0), "-0分5.0秒 (-5.00秒)");
+        assert_eq!(format_duration(f64::NAN), "不明");
+        assert_eq!(format_duration(f64::INFINITY), "不明");
+        assert_eq!(format_duration(f64::NEG_INFINITY), "不明");
+    }
+}
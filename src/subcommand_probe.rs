@@ -0,0 +1,241 @@
+use shiguredo_mp4::boxes::{SampleEntry, VisualSampleEntryFields};
+
+use crate::io::InputSource;
+use crate::mp4::{InputMp4, audio_fields};
+use crate::tr;
+
+pub fn run(mut args: noargs::RawArgs) -> noargs::Result<()> {
+    let input_file_arg: Option<String> = noargs::arg("[INPUT_FILE]")
+        .example("/path/to/input.mp4")
+        .doc("情報を取得する MP4 ファイル（省略時は stdin から読み込み）")
+        .take(&mut args)
+        .then(|a| a.value().parse())
+        .ok();
+    if let Some(help) = args.finish()? {
+        print!("{help}");
+        return Ok(());
+    }
+
+    let input_source = match InputSource::from_arg(input_file_arg) {
+        Some(source) => source,
+        None => {
+            eprintln!(
+                "{}",
+                tr!(
+                    "エラー: 入力ファイルを指定するか、パイプで入力してください",
+                    "Error: specify an input file or pipe the input"
+                )
+            );
+            eprintln!(
+                "{}",
+                tr!("使用例: {}", "Example: {}", "mp4-util probe input.mp4")
+            );
+            eprintln!(
+                "{}",
+                tr!(
+                    "使用例: {}",
+                    "Example: {}",
+                    "cat input.mp4 | mp4-util probe"
+                )
+            );
+            std::process::exit(1);
+        }
+    };
+
+    let reader = input_source.reader().map_err(|e| {
+        tr!(
+            "入力を開けません ({}): {}",
+            "Cannot open the input ({}): {}",
+            input_source.description(),
+            e
+        )
+    })?;
+    let mp4 = InputMp4::parse(reader)?;
+    for (key, value) in probe_entries(&mp4) {
+        println!("{}={}", key, sanitize_value(&value));
+    }
+    Ok(())
+}
+
+/// 出力する `(キー, 値)` の一覧を生成する
+///
+/// キーは `duration` や `track.1.codec` のような固定の英語名で、値が不明な項目は出力しない。
+/// 表示言語の設定に関わらず、数値は単位なし（秒・ピクセル・Hz）で出力する
+fn probe_entries(mp4: &InputMp4) -> Vec<(String, String)> {
+    let mut entries = Vec::new();
+    let mut push = |key: String, value: String| entries.push((key, value));
+
+    if let Some(ftyp) = mp4.ftyp() {
+        push(
+            "brand".to_string(),
+            String::from_utf8_lossy(&ftyp.major_brand.get()).into_owned(),
+        );
+    }
+    let mvhd = mp4.mvhd();
+    if let Some(duration) = seconds(mvhd.duration as f64 / mvhd.timescale.get() as f64) {
+        push("duration".to_string(), duration);
+    }
+
+    let tracks = mp4.get_track_infos().unwrap_or_default();
+    push("tracks".to_string(), tracks.len().to_string());
+    for (i, (track, trak)) in tracks.iter().zip(mp4.tracks()).enumerate() {
+        let key = |name: &str| format!("track.{}.{}", i + 1, name);
+        push(key("id"), track.track_id.to_string());
+        let media_type = match &trak.mdia_box.hdlr_box.handler_type {
+            b"vide" => "video",
+            b"soun" => "audio",
+            _ => "unknown",
+        };
+        push(key("type"), media_type.to_string());
+        if let Some(sample_entry_type) = &track.sample_entry_type {
+            push(key("codec"), codec_id(sample_entry_type).to_string());
+            push(key("sample_entry"), sample_entry_type.clone());
+        }
+        if let Some(duration) = seconds(track.duration) {
+            push(key("duration"), duration);
+        }
+        if let Some(sample_count) = track.sample_count {
+            push(key("samples"), sample_count.to_string());
+        }
+
+        let sample_entry = trak.mdia_box.minf_box.stbl_box.stsd_box.entries.first();
+        if let Some(visual) = sample_entry.and_then(visual_fields) {
+            push(key("width"), visual.width.to_string());
+            push(key("height"), visual.height.to_string());
+        }
+        if let Some(audio) = sample_entry.and_then(audio_fields) {
+            push(key("sample_rate"), audio.samplerate.integer.to_string());
+            push(key("channels"), audio.channelcount.to_string());
+        }
+        if let Some(encoder_delay) = track.encoder_delay {
+            push(key("encoder_delay"), encoder_delay.delay.to_string());
+            push(key("padding"), encoder_delay.padding.to_string());
+        }
+        if let Some(handler_name) = &track.handler_name {
+            push(key("handler_name"), handler_name.clone());
+        }
+    }
+    entries
+}
+
+/// サンプルエントリーのボックス種別を、シェルで扱いやすい小文字のコーデック名に変換する
+///
+/// 対応していないサンプルエントリーの場合はボックス種別をそのまま返す
+fn codec_id(sample_entry_type: &str) -> &str {
+    match sample_entry_type {
+        "avc1" | "avc3" => "h264",
+        "hev1" | "hvc1" => "hevc",
+        "vp08" => "vp8",
+        "vp09" => "vp9",
+        "av01" => "av1",
+        "Opus" => "opus",
+        "mp4a" => "aac",
+        "fLaC" => "flac",
+        other => other,
+    }
+}
+
+fn visual_fields(sample_entry: &SampleEntry) -> Option<&VisualSampleEntryFields> {
+    match sample_entry {
+        SampleEntry::Avc1(b) => Some(&b.visual),
+        SampleEntry::Hev1(b) => Some(&b.visual),
+        SampleEntry::Vp08(b) => Some(&b.visual),
+        SampleEntry::Vp09(b) => Some(&b.visual),
+        SampleEntry::Av01(b) => Some(&b.visual),
+        _ => None,
+    }
+}
+
+/// 秒数をミリ秒の精度に丸めて、末尾の 0 を付けない文字列にする（NaN や無限大は `None`）
+fn seconds(value: f64) -> Option<String> {
+    value
+        .is_finite()
+        .then(|| ((value * 1000.0).round() / 1000.0).to_string())
+}
+
+/// 1 行 1 項目の形式が崩れないよう、値の中の改行などの制御文字を空白に置き換える
+fn sanitize_value(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_value_formatting() {
+        assert_eq!(codec_id("avc1"), "h264");
+        assert_eq!(codec_id("hvc1"), "hevc");
+        assert_eq!(codec_id("mp4v"), "mp4v");
+
+        assert_eq!(seconds(60.04).as_deref(), Some("60.04"));
+        assert_eq!(seconds(10.0).as_deref(), Some("10"));
+        assert_eq!(seconds(1.23456).as_deref(), Some("1.235"));
+        assert_eq!(seconds(f64::NAN), None);
+
+        assert_eq!(sanitize_value("Video\nHandler\t1"), "Video Handler 1");
+    }
+
+    #[test]
+    fn test_probe_entries() {
+        use crate::test_util::Mp4Builder;
+        use shiguredo_mp4::{
+            FixedPointNumber, TrackKind,
+            boxes::{AudioSampleEntryFields, DopsBox, OpusBox},
+        };
+
+        // タイムスケール 30 で 45 フレーム（1.5 秒）のビデオと、20 ms 間隔で 50 サンプル（1 秒）の Opus
+        let opus = SampleEntry::Opus(OpusBox {
+            audio: AudioSampleEntryFields {
+                data_reference_index: AudioSampleEntryFields::DEFAULT_DATA_REFERENCE_INDEX,
+                channelcount: 2,
+                samplesize: AudioSampleEntryFields::DEFAULT_SAMPLESIZE,
+                samplerate: FixedPointNumber::new(48000, 0),
+            },
+            dops_box: DopsBox {
+                output_channel_count: 2,
+                pre_skip: 312,
+                input_sample_rate: 48000,
+                output_gain: 0,
+            },
+            unknown_boxes: Vec::new(),
+        });
+        let data = Mp4Builder::new()
+            .track(TrackKind::Video, 30, &[1; 45], 15)
+            .sample_entry(opus)
+            .track(TrackKind::Audio, 48000, &[960; 50], 1)
+            .build();
+        let mp4 = InputMp4::parse(&data[..]).unwrap();
+        let entries = probe_entries(&mp4);
+        let entries: Vec<(&str, &str)> = entries
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect();
+        // Muxer はオーディオトラックを先に出力する
+        assert_eq!(
+            entries,
+            [
+                ("brand", "isom"),
+                ("duration", "1.5"),
+                ("tracks", "2"),
+                ("track.1.id", "1"),
+                ("track.1.type", "audio"),
+                ("track.1.codec", "opus"),
+                ("track.1.sample_entry", "Opus"),
+                ("track.1.duration", "1"),
+                ("track.1.samples", "50"),
+                ("track.1.sample_rate", "48000"),
+                ("track.1.channels", "2"),
+                ("track.2.id", "2"),
+                ("track.2.type", "video"),
+                ("track.2.codec", "test"),
+                ("track.2.sample_entry", "test"),
+                ("track.2.duration", "1.5"),
+                ("track.2.samples", "45"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,282 @@
+use std::{
+    io::{BufWriter, Read, Seek, SeekFrom, Write},
+    num::NonZeroU32,
+};
+
+use shiguredo_mp4::{aux::SampleTableAccessor, boxes::SampleEntry};
+
+use crate::io::{InputSource, OutputSink};
+use crate::mp4::{external_data_reference, read_moov_box};
+use crate::tr;
+
+const TRACK_OPT: noargs::OptSpec = noargs::opt("track")
+    .short('t')
+    .doc("書き出すトラックの番号（info で表示される 1 始まりの番号）")
+    .ty("TRACK")
+    .example("1");
+
+const OUTPUT_OPT: noargs::OptSpec = noargs::opt("output")
+    .short('o')
+    .doc("出力ファイルパス（省略時は stdout）")
+    .ty("PATH")
+    .example("output.h264");
+
+const FRAMED_FLAG: noargs::FlagSpec = noargs::flag("framed")
+    .doc("各サンプルの前に 4 バイト（ビッグエンディアン）のサイズを付加します");
+
+const ANNEXB_FLAG: noargs::FlagSpec = noargs::flag("annexb")
+    .doc("AVC/HEVC トラックを Annex-B 形式（スタートコード区切り）で書き出します");
+
+/// Annex-B のスタートコード
+const START_CODE: [u8; 4] = [0, 0, 0, 1];
+
+pub fn run(mut args: noargs::RawArgs) -> noargs::Result<()> {
+    let input_file_arg: Option<String> = noargs::arg("[INPUT_FILE]")
+        .example("/path/to/input.mp4")
+        .doc("書き出し元の MP4 ファイル（省略時は stdin から読み込み）")
+        .take(&mut args)
+        .then(|a| a.value().parse())
+        .ok();
+
+    let track_number: NonZeroU32 = TRACK_OPT.take(&mut args).then(|o| o.value().parse())?;
+
+    let output_file_arg: Option<String> =
+        OUTPUT_OPT.take(&mut args).then(|o| o.value().parse()).ok();
+
+    let framed = FRAMED_FLAG.take(&mut args).is_present();
+    let annexb = ANNEXB_FLAG.take(&mut args).is_present();
+
+    if let Some(help) = args.finish()? {
+        print!("{help}");
+        return Ok(());
+    }
+
+    if framed && annexb {
+        return Err(tr!(
+            "--framed と --annexb は同時に指定できません",
+            "--framed and --annexb cannot be used together"
+        )
+        .into());
+    }
+
+    // 入力ソースを決定
+    let input_source = match InputSource::from_arg(input_file_arg) {
+        Some(source) => source,
+        None => {
+            eprintln!(
+                "{}",
+                tr!(
+                    "エラー: 入力ファイルを指定するか、パイプで入力してください",
+                    "Error: specify an input file or pipe the input"
+                )
+            );
+            eprintln!(
+                "{}",
+                tr!(
+                    "使用例: {}",
+                    "Example: {}",
+                    "mp4-util raw input.mp4 -t 1 -o output.h264"
+                )
+            );
+            eprintln!(
+                "{}",
+                tr!(
+                    "使用例: {}",
+                    "Example: {}",
+                    "cat input.mp4 | mp4-util raw -t 2 > output.opus"
+                )
+            );
+            std::process::exit(1);
+        }
+    };
+
+    // 出力先を決定（バイナリ出力なので TTY は不可）
+    let output_sink = OutputSink::from_arg(output_file_arg, false)?;
+
+    let mut reader = input_source.seekable_reader().map_err(|e| {
+        tr!(
+            "入力を開けません ({}): {}",
+            "Cannot open the input ({}): {}",
+            input_source.description(),
+            e
+        )
+    })?;
+    let moov_box = read_moov_box(&mut reader)?;
+
+    let trak_box = moov_box
+        .trak_boxes
+        .get(track_number.get() as usize - 1)
+        .ok_or_else(|| {
+            tr!(
+                "トラック {} が見つかりません（トラック数: {}）",
+                "Track {} not found (number of tracks: {})",
+                track_number,
+                moov_box.trak_boxes.len()
+            )
+        })?;
+    if let Some(reference) = external_data_reference(trak_box) {
+        return Err(tr!(
+            "トラック {} のサンプルデータは外部のファイル ({}) にあるため書き出せません",
+            "Cannot write track {} because its sample data is in an external file ({})",
+            track_number,
+            reference
+        )
+        .into());
+    }
+    let sample_table =
+        SampleTableAccessor::new(&trak_box.mdia_box.minf_box.stbl_box).map_err(|e| {
+            tr!(
+                "サンプルテーブルの解析に失敗しました: {}",
+                "Failed to parse the sample table: {}",
+                e
+            )
+        })?;
+
+    let mut writer = BufWriter::new(output_sink.writer().map_err(|e| {
+        tr!(
+            "出力先を開けません ({}): {}",
+            "Cannot open the output ({}): {}",
+            output_sink.description(),
+            e
+        )
+    })?);
+
+    let mut sample_data = Vec::new();
+    let mut total_bytes: u64 = 0;
+    for sample in sample_table.samples() {
+        sample_data.resize(sample.data_size() as usize, 0);
+        reader.seek(SeekFrom::Start(sample.data_offset()))?;
+        reader.read_exact(&mut sample_data)?;
+
+        if annexb {
+            let parameter_sets =
+                nal_parameter_sets(sample.chunk().sample_entry()).ok_or_else(|| {
+                    tr!(
+                        "--annexb は AVC/HEVC トラックでのみ使用できます",
+                        "--annexb can only be used with AVC/HEVC tracks"
+                    )
+                })?;
+
+            // デコーダーが途中から再生を開始できるよう、キーフレームの前にパラメータセットを挿入する
+            if sample.is_sync_sample() {
+                for nal_unit in parameter_sets.nal_units {
+                    writer.write_all(&START_CODE)?;
+                    writer.write_all(nal_unit)?;
+                    total_bytes += (START_CODE.len() + nal_unit.len()) as u64;
+                }
+            }
+            for nal_unit in
+                split_length_prefixed_nal_units(&sample_data, parameter_sets.length_size)?
+            {
+                writer.write_all(&START_CODE)?;
+                writer.write_all(nal_unit)?;
+                total_bytes += (START_CODE.len() + nal_unit.len()) as u64;
+            }
+        } else {
+            if framed {
+                writer.write_all(&(sample_data.len() as u32).to_be_bytes())?;
+                total_bytes += 4;
+            }
+            writer.write_all(&sample_data)?;
+            total_bytes += sample_data.len() as u64;
+        }
+    }
+    writer.flush()?;
+
+    log::info!(
+        "{}",
+        tr!(
+            "{} サンプル、{} バイトを書き込みました: {}",
+            "Wrote {} samples, {} bytes: {}",
+            sample_table.sample_count(),
+            total_bytes,
+            output_sink.description()
+        )
+    );
+
+    Ok(())
+}
+
+/// Annex-B 変換に必要なパラメータセット情報
+struct NalParameterSets<'a> {
+    /// サンプル内の NAL ユニット長フィールドのバイト数
+    length_size: usize,
+    /// VPS/SPS/PPS などのパラメータセット
+    nal_units: Vec<&'a [u8]>,
+}
+
+/// サンプルエントリーから NAL ユニットのパラメータセットを取得する
+///
+/// AVC/HEVC 以外のサンプルエントリーの場合は `None` を返す
+fn nal_parameter_sets(sample_entry: &SampleEntry) -> Option<NalParameterSets<'_>> {
+    match sample_entry {
+        SampleEntry::Avc1(avc1) => {
+            let avcc = &avc1.avcc_box;
+            Some(NalParameterSets {
+                length_size: avcc.length_size_minus_one.get() as usize + 1,
+                nal_units: avcc
+                    .sps_list
+                    .iter()
+                    .chain(&avcc.pps_list)
+                    .map(|n| n.as_slice())
+                    .collect(),
+            })
+        }
+        SampleEntry::Hev1(hev1) => {
+            let hvcc = &hev1.hvcc_box;
+            Some(NalParameterSets {
+                length_size: hvcc.length_size_minus_one.get() as usize + 1,
+                nal_units: hvcc
+                    .nalu_arrays
+                    .iter()
+                    .flat_map(|a| &a.nalus)
+                    .map(|n| n.as_slice())
+                    .collect(),
+            })
+        }
+        _ => None,
+    }
+}
+
+/// 長さプレフィックス形式のサンプルデータを NAL ユニットに分割する
+fn split_length_prefixed_nal_units(data: &[u8], length_size: usize) -> Result<Vec<&[u8]>, String> {
+    let mut nal_units = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        let length_bytes = data.get(offset..offset + length_size).ok_or_else(|| {
+            tr!(
+                "NAL ユニットの長さフィールドが不正です",
+                "Invalid NAL unit length field"
+            )
+        })?;
+        let length = length_bytes
+            .iter()
+            .fold(0usize, |acc, b| (acc << 8) | *b as usize);
+        offset += length_size;
+
+        let nal_unit = data.get(offset..offset + length).ok_or_else(|| {
+            tr!(
+                "NAL ユニットの長さがサンプルサイズを超えています",
+                "The NAL unit length exceeds the sample size"
+            )
+        })?;
+        nal_units.push(nal_unit);
+        offset += length;
+    }
+    Ok(nal_units)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_length_prefixed_nal_units() {
+        let data = [0, 0, 0, 2, 0x65, 0x88, 0, 0, 0, 1, 0x06];
+        let nal_units = split_length_prefixed_nal_units(&data, 4).unwrap();
+        assert_eq!(nal_units, vec![&[0x65, 0x88][..], &[0x06][..]]);
+
+        // 長さフィールドがサンプル末尾を超える場合はエラー
+        assert!(split_length_prefixed_nal_units(&[0, 0, 0, 5, 0x65], 4).is_err());
+    }
+}
@@ -0,0 +1,270 @@
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+
+use shiguredo_mp4::{
+    Either, Encode,
+    aux::SampleTableAccessor,
+    boxes::{MdatBox, MoovBox},
+};
+
+use crate::io::{InputSource, OutputSink};
+use crate::mp4::{RootBoxLocation, decode_moov_box, scan_root_boxes};
+use crate::tr;
+
+const OUTPUT_OPT: noargs::OptSpec = noargs::opt("output")
+    .short('o')
+    .doc("出力ファイルパス（省略時は stdout）")
+    .ty("PATH")
+    .example("repaired.mp4");
+
+pub fn run(mut args: noargs::RawArgs) -> noargs::Result<()> {
+    let input_file_arg: Option<String> = noargs::arg("[INPUT_FILE]")
+        .example("/path/to/input.mp4")
+        .doc("修復する MP4 ファイル（省略時は stdin から読み込み）")
+        .take(&mut args)
+        .then(|a| a.value().parse())
+        .ok();
+
+    let output_file_arg: Option<String> =
+        OUTPUT_OPT.take(&mut args).then(|o| o.value().parse()).ok();
+
+    if let Some(help) = args.finish()? {
+        print!("{help}");
+        return Ok(());
+    }
+
+    let input_source = match InputSource::from_arg(input_file_arg) {
+        Some(source) => source,
+        None => {
+            eprintln!(
+                "{}",
+                tr!(
+                    "エラー: 入力ファイルを指定するか、パイプで入力してください",
+                    "Error: specify an input file or pipe the input"
+                )
+            );
+            eprintln!(
+                "{}",
+                tr!(
+                    "使用例: {}",
+                    "Example: {}",
+                    "mp4-util repair input.mp4 -o repaired.mp4"
+                )
+            );
+            eprintln!(
+                "{}",
+                tr!(
+                    "使用例: {}",
+                    "Example: {}",
+                    "cat input.mp4 | mp4-util repair > repaired.mp4"
+                )
+            );
+            std::process::exit(1);
+        }
+    };
+
+    // 出力先を決定（バイナリ出力なので TTY は不可）
+    let output_sink = OutputSink::from_arg(output_file_arg, false)?;
+
+    let mut reader = input_source.seekable_reader().map_err(|e| {
+        tr!(
+            "入力を開けません ({}): {}",
+            "Cannot open the input ({}): {}",
+            input_source.description(),
+            e
+        )
+    })?;
+    let root_boxes = scan_root_boxes(&mut reader)?;
+
+    let moov_location = root_boxes
+        .iter()
+        .find(|b| b.box_type == MoovBox::TYPE)
+        .ok_or_else(|| tr!("moov ボックスが見つかりません", "moov box not found"))?;
+    let mdat_location = root_boxes
+        .iter()
+        .find(|b| b.box_type == MdatBox::TYPE)
+        .ok_or_else(|| tr!("mdat ボックスが見つかりません", "mdat box not found"))?;
+    if root_boxes
+        .iter()
+        .filter(|b| b.box_type == MdatBox::TYPE)
+        .count()
+        > 1
+    {
+        return Err(tr!(
+            "mdat ボックスが複数あるファイルには対応していません",
+            "Files with multiple mdat boxes are not supported"
+        )
+        .into());
+    }
+
+    let mut moov_bytes = vec![0; moov_location.box_size as usize];
+    reader.seek(SeekFrom::Start(moov_location.offset))?;
+    reader.read_exact(&mut moov_bytes)?;
+    let mut moov_box = decode_moov_box(&moov_bytes).map_err(|e| {
+        tr!(
+            "moov ボックスの解析に失敗しました: {}",
+            "Failed to parse the moov box: {}",
+            e
+        )
+    })?;
+
+    // 再エンコードで moov のサイズが変わる場合、mdat が後ろにあれば位置がずれる
+    let encoded_moov_size = encode_moov_box(&moov_box)?.len() as u64;
+    let mdat_payload_offset = if moov_location.offset < mdat_location.offset {
+        mdat_location.payload_offset() + encoded_moov_size - moov_location.box_size
+    } else {
+        mdat_location.payload_offset()
+    };
+    let mdat_payload_size = mdat_location.box_size - mdat_location.header_size;
+    let chunk_count = repair_chunk_offsets(&mut moov_box, mdat_payload_offset, mdat_payload_size)?;
+    let moov_bytes = encode_moov_box(&moov_box)?;
+
+    // moov 以外のボックスはそのままコピーする
+    let mut writer = BufWriter::new(output_sink.writer().map_err(|e| {
+        tr!(
+            "出力先を開けません ({}): {}",
+            "Cannot open the output ({}): {}",
+            output_sink.description(),
+            e
+        )
+    })?);
+    for location in &root_boxes {
+        if location == moov_location {
+            writer.write_all(&moov_bytes)?;
+        } else {
+            copy_root_box(&mut reader, location, &mut writer)?;
+        }
+    }
+    writer.flush()?;
+
+    log::info!(
+        "{}",
+        tr!(
+            "{} チャンクのオフセットを再計算しました: {}",
+            "Recalculated the offsets of {} chunks: {}",
+            chunk_count,
+            output_sink.description()
+        )
+    );
+
+    Ok(())
+}
+
+fn encode_moov_box(moov_box: &MoovBox) -> Result<Vec<u8>, String> {
+    moov_box.encode_to_vec().map_err(|e| {
+        tr!(
+            "moov ボックスのエンコードに失敗しました: {}",
+            "Failed to encode the moov box: {}",
+            e
+        )
+    })
+}
+
+fn copy_root_box<R: Read + Seek, W: Write>(
+    reader: &mut R,
+    location: &RootBoxLocation,
+    writer: &mut W,
+) -> std::io::Result<()> {
+    reader.seek(SeekFrom::Start(location.offset))?;
+    let copied = std::io::copy(&mut reader.by_ref().take(location.box_size), writer)?;
+    if copied < location.box_size {
+        return Err(std::io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(())
+}
+
+/// サンプルデータが mdat のペイロード先頭から隙間なく並んでいるとみなして、全トラックのチャンクオフセットを再計算する
+///
+/// チャンクの並び順は既存のオフセットの大小関係を維持する。
+/// 再計算したチャンクの数を返す
+fn repair_chunk_offsets(
+    moov_box: &mut MoovBox,
+    mdat_payload_offset: u64,
+    mdat_payload_size: u64,
+) -> Result<usize, String> {
+    // (既存のオフセット, トラック番号, チャンク番号, チャンクのサイズ)
+    let mut chunks = Vec::new();
+    for (track_index, trak_box) in moov_box.trak_boxes.iter().enumerate() {
+        let sample_table =
+            SampleTableAccessor::new(&trak_box.mdia_box.minf_box.stbl_box).map_err(|e| {
+                tr!(
+                    "サンプルテーブルの解析に失敗しました: {}",
+                    "Failed to parse the sample table: {}",
+                    e
+                )
+            })?;
+        for (chunk_index, chunk) in sample_table.chunks().enumerate() {
+            let chunk_size: u64 = chunk.samples().map(|s| s.data_size() as u64).sum();
+            chunks.push((chunk.offset(), track_index, chunk_index, chunk_size));
+        }
+    }
+    chunks.sort_by_key(|&(offset, track_index, chunk_index, _)| (offset, track_index, chunk_index));
+
+    let total_size: u64 = chunks.iter().map(|&(.., size)| size).sum();
+    if total_size > mdat_payload_size {
+        return Err(tr!(
+            "サンプルデータの合計サイズ ({} バイト) が mdat のサイズ ({} バイト) を超えています",
+            "The total sample data size ({} bytes) exceeds the mdat size ({} bytes)",
+            total_size,
+            mdat_payload_size
+        ));
+    }
+
+    let mut offset = mdat_payload_offset;
+    for &(_, track_index, chunk_index, chunk_size) in &chunks {
+        let stbl_box = &mut moov_box.trak_boxes[track_index].mdia_box.minf_box.stbl_box;
+        match &mut stbl_box.stco_or_co64_box {
+            Either::A(stco_box) => {
+                stco_box.chunk_offsets[chunk_index] = u32::try_from(offset).map_err(|_| {
+                    tr!(
+                        "チャンクオフセットが stco で表現可能な範囲を超えました",
+                        "A chunk offset exceeds the range representable by stco"
+                    )
+                })?;
+            }
+            Either::B(co64_box) => co64_box.chunk_offsets[chunk_index] = offset,
+        }
+        offset += chunk_size;
+    }
+    Ok(chunks.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use shiguredo_mp4::TrackKind;
+
+    use super::*;
+    use crate::test_util::Mp4Builder;
+
+    #[test]
+    fn test_repair_chunk_offsets() {
+        let mut builder = Mp4Builder::new();
+        let mdat_payload_offset = builder.data_offset();
+        for (i, track_kind) in [TrackKind::Video, TrackKind::Audio, TrackKind::Video]
+            .into_iter()
+            .enumerate()
+        {
+            builder = builder.sample(track_kind, 30, 1, true, &vec![0; 10 + i]);
+        }
+        let expected = builder.moov_box();
+
+        // mdat を 100 バイト後ろに移動したのにオフセットが更新されていないファイルを想定
+        let mut moov_box = expected.clone();
+        let chunk_count =
+            repair_chunk_offsets(&mut moov_box, mdat_payload_offset + 100, 1000).unwrap();
+        assert_eq!(chunk_count, 3);
+        for (repaired, original) in moov_box.trak_boxes.iter().zip(&expected.trak_boxes) {
+            let offsets = |trak_box: &shiguredo_mp4::boxes::TrakBox| {
+                SampleTableAccessor::new(&trak_box.mdia_box.minf_box.stbl_box)
+                    .unwrap()
+                    .chunks()
+                    .map(|c| c.offset())
+                    .collect::<Vec<_>>()
+            };
+            let expected_offsets: Vec<_> = offsets(original).iter().map(|o| o + 100).collect();
+            assert_eq!(offsets(repaired), expected_offsets);
+        }
+
+        // mdat に収まらない場合はエラー
+        assert!(repair_chunk_offsets(&mut moov_box, mdat_payload_offset, 10).is_err());
+    }
+}
@@ -0,0 +1,210 @@
+use std::collections::BTreeMap;
+
+use shiguredo_mp4::aux::SampleTableAccessor;
+
+use crate::io::{InputSource, READ_CACHE_OPT, parse_byte_size};
+use crate::mp4::{max_interleave_seek_distance, read_moov_box, timestamp_gaps};
+use crate::tr;
+
+const REPORT_GAPS_FLAG: noargs::FlagSpec = noargs::flag("report-gaps").doc(
+    "トラックごとに、stts のサンプル間隔が通常より長い箇所（タイムスタンプの欠落）を一覧表示します",
+);
+
+pub fn run(mut args: noargs::RawArgs) -> noargs::Result<()> {
+    let input_file_arg: Option<String> = noargs::arg("[INPUT_FILE]")
+        .example("/path/to/input.mp4")
+        .doc("統計情報を取得する MP4 ファイル（省略時は stdin から読み込み）")
+        .take(&mut args)
+        .then(|a| a.value().parse())
+        .ok();
+    let read_cache_size: u64 = READ_CACHE_OPT
+        .take(&mut args)
+        .then(|o| parse_byte_size(o.value()))?;
+    let report_gaps = REPORT_GAPS_FLAG.take(&mut args).is_present();
+    if let Some(help) = args.finish()? {
+        print!("{help}");
+        return Ok(());
+    }
+
+    let input_source = match InputSource::from_arg(input_file_arg) {
+        Some(source) => source,
+        None => {
+            eprintln!(
+                "{}",
+                tr!(
+                    "エラー: 入力ファイルを指定するか、パイプで入力してください",
+                    "Error: specify an input file or pipe the input"
+                )
+            );
+            eprintln!(
+                "{}",
+                tr!("使用例: {}", "Example: {}", "mp4-util stats input.mp4")
+            );
+            eprintln!(
+                "{}",
+                tr!(
+                    "使用例: {}",
+                    "Example: {}",
+                    "cat input.mp4 | mp4-util stats"
+                )
+            );
+            std::process::exit(1);
+        }
+    };
+
+    let mut reader = input_source.cached_reader(read_cache_size).map_err(|e| {
+        tr!(
+            "入力を開けません ({}): {}",
+            "Cannot open the input ({}): {}",
+            input_source.description(),
+            e
+        )
+    })?;
+    let moov_box = read_moov_box(&mut reader)?;
+
+    println!("{}", tr!("MP4ファイル統計：", "MP4 file statistics:"));
+    match max_interleave_seek_distance(&moov_box) {
+        Ok(distance) => println!(
+            "{}",
+            tr!(
+                "再生時の最大シーク距離: {} バイト",
+                "Maximum seek distance during playback: {} bytes",
+                distance
+            )
+        ),
+        Err(e) => println!(
+            "{}",
+            tr!(
+                "再生時の最大シーク距離: - ({})",
+                "Maximum seek distance during playback: - ({})",
+                e
+            )
+        ),
+    }
+    for (i, trak_box) in moov_box.trak_boxes.iter().enumerate() {
+        println!("\n{}", tr!("トラック {}:", "Track {}:", i + 1));
+        println!(
+            "{}",
+            tr!(
+                "ハンドラー: {}",
+                "Handler: {}",
+                String::from_utf8_lossy(&trak_box.mdia_box.hdlr_box.handler_type)
+            )
+        );
+
+        let sample_table = match SampleTableAccessor::new(&trak_box.mdia_box.minf_box.stbl_box) {
+            Ok(sample_table) => sample_table,
+            Err(e) => {
+                println!(
+                    "{}",
+                    tr!(
+                        "サンプルテーブルの解析に失敗しました: {}",
+                        "Failed to parse the sample table: {}",
+                        e
+                    )
+                );
+                continue;
+            }
+        };
+
+        println!(
+            "{}",
+            tr!("チャンク数: {}", "Chunks: {}", sample_table.chunk_count())
+        );
+        match SamplesPerChunkStats::from_counts(sample_table.chunks().map(|c| c.sample_count())) {
+            Some(stats) => println!(
+                "{}",
+                tr!(
+                    "チャンクあたりのサンプル数: 最小 {} / 最大 {} / 最頻 {}",
+                    "Samples per chunk: min {} / max {} / mode {}",
+                    stats.min,
+                    stats.max,
+                    stats.mode
+                )
+            ),
+            None => println!(
+                "{}",
+                tr!("チャンクあたりのサンプル数: -", "Samples per chunk: -")
+            ),
+        }
+
+        if report_gaps {
+            let gaps = timestamp_gaps(&trak_box.mdia_box.minf_box.stbl_box.stts_box);
+            if gaps.is_empty() {
+                println!(
+                    "{}",
+                    tr!("タイムスタンプの欠落: なし", "Timestamp gaps: none")
+                );
+            } else {
+                println!(
+                    "{}",
+                    tr!(
+                        "タイムスタンプの欠落: {} 箇所",
+                        "Timestamp gaps: {}",
+                        gaps.len()
+                    )
+                );
+                let timescale = trak_box.mdia_box.mdhd_box.timescale.get();
+                for gap in &gaps {
+                    println!("  {}", gap.describe(timescale));
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// チャンクあたりのサンプル数の統計
+#[derive(Debug, PartialEq, Eq)]
+struct SamplesPerChunkStats {
+    min: u32,
+    max: u32,
+    /// 最頻値（同数の場合は小さい方）
+    mode: u32,
+}
+
+impl SamplesPerChunkStats {
+    /// 各チャンクのサンプル数から統計を求める
+    ///
+    /// チャンクが一つもない場合は `None` を返す
+    fn from_counts(counts: impl Iterator<Item = u32>) -> Option<Self> {
+        let mut histogram = BTreeMap::new();
+        for count in counts {
+            *histogram.entry(count).or_insert(0usize) += 1;
+        }
+
+        let min = *histogram.keys().next()?;
+        let max = *histogram.keys().next_back()?;
+        let max_frequency = *histogram.values().max()?;
+        let mode = *histogram
+            .iter()
+            .find(|(_, frequency)| **frequency == max_frequency)?
+            .0;
+        Some(Self { min, max, mode })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_samples_per_chunk_stats() {
+        let stats = SamplesPerChunkStats::from_counts([5, 1, 5, 3, 1, 5].into_iter()).unwrap();
+        assert_eq!(
+            stats,
+            SamplesPerChunkStats {
+                min: 1,
+                max: 5,
+                mode: 5
+            }
+        );
+
+        // 最頻値が同数の場合は小さい方
+        let stats = SamplesPerChunkStats::from_counts([2, 4].into_iter()).unwrap();
+        assert_eq!(stats.mode, 2);
+
+        assert_eq!(SamplesPerChunkStats::from_counts(std::iter::empty()), None);
+    }
+}
@@ -0,0 +1,96 @@
+use crate::subcommand_extract;
+use crate::tr;
+
+const FROM_OPT: noargs::OptSpec = noargs::opt("from")
+    .doc("開始位置（秒数、「分:秒」または「時:分:秒」。省略時はファイルの先頭）")
+    .ty("TIME")
+    .example("00:10");
+
+const TO_OPT: noargs::OptSpec = noargs::opt("to")
+    .doc("終了位置（秒数、「分:秒」または「時:分:秒」。省略時はファイルの末尾）")
+    .ty("TIME")
+    .example("00:20");
+
+const OUTPUT_OPT: noargs::OptSpec = noargs::opt("output")
+    .short('o')
+    .doc("出力ファイルパス（省略時は stdout）")
+    .ty("PATH")
+    .example("output.mp4");
+
+pub fn run(mut args: noargs::RawArgs) -> noargs::Result<()> {
+    let input_file_arg: Option<String> = noargs::arg("[INPUT_FILE]")
+        .example("/path/to/input.mp4")
+        .doc("切り出し元の MP4 ファイル（省略時は stdin から読み込み）")
+        .take(&mut args)
+        .then(|a| a.value().parse())
+        .ok();
+    let from: Option<f64> = FROM_OPT
+        .take(&mut args)
+        .present_and_then(|o| parse_timecode(o.value()))?;
+    let to: Option<f64> = TO_OPT
+        .take(&mut args)
+        .present_and_then(|o| parse_timecode(o.value()))?;
+    let output_file_arg: Option<String> =
+        OUTPUT_OPT.take(&mut args).then(|o| o.value().parse()).ok();
+    if let Some(help) = args.finish()? {
+        print!("{help}");
+        return Ok(());
+    }
+
+    let from = from.unwrap_or(0.0);
+    if to.is_some_and(|to| to <= from) {
+        return Err(tr!(
+            "--to には --from より後の時刻を指定してください",
+            "--to must be later than --from"
+        )
+        .into());
+    }
+
+    // 切り出し自体は extract と同じ処理で行う
+    subcommand_extract::trim(input_file_arg, output_file_arg, from, to)
+}
+
+/// 「10.5」「01:30」「1:02:03.5」のような時刻の指定を秒数に変換する
+///
+/// 分・秒の部分は上位の単位がある場合は 60 未満である必要がある
+fn parse_timecode(value: &str) -> Result<f64, String> {
+    let invalid = || tr!("時刻の指定が不正です: {}", "Invalid time: {}", value);
+    let parts: Vec<&str> = value.split(':').collect();
+    if parts.len() > 3 {
+        return Err(invalid());
+    }
+
+    let (seconds, units) = parts.split_last().ok_or_else(invalid)?;
+    let seconds: f64 = seconds.parse().map_err(|_| invalid())?;
+    if !seconds.is_finite() || seconds < 0.0 || (!units.is_empty() && seconds >= 60.0) {
+        return Err(invalid());
+    }
+    let mut total = 0.0;
+    for (i, unit) in units.iter().enumerate() {
+        let unit: u64 = unit.parse().map_err(|_| invalid())?;
+        // 時の後ろの分は 60 未満
+        if i > 0 && unit >= 60 {
+            return Err(invalid());
+        }
+        total = total * 60.0 + unit as f64;
+    }
+    Ok(total * 60.0 + seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_timecode() {
+        assert_eq!(parse_timecode("12.5"), Ok(12.5));
+        assert_eq!(parse_timecode("00:10"), Ok(10.0));
+        assert_eq!(parse_timecode("1:02:03.5"), Ok(3723.5));
+        assert_eq!(parse_timecode("90:00"), Ok(5400.0));
+        assert!(parse_timecode("00:60").is_err());
+        assert!(parse_timecode("1:60:00").is_err());
+        assert!(parse_timecode("-1").is_err());
+        assert!(parse_timecode("1:2:3:4").is_err());
+        assert!(parse_timecode("abc").is_err());
+    }
+}
@@ -0,0 +1,529 @@
+use shiguredo_mp4::boxes::{MoovBox, SttsBox, TrakBox};
+
+use crate::i18n::{Lang, lang};
+use crate::io::{InputSource, READ_CACHE_OPT, parse_byte_size};
+use crate::json;
+use crate::mp4::{
+    RootBoxLocation, max_interleave_seek_distance, read_moov_box, scan_root_boxes, timestamp_gaps,
+    unknown_box_paths, unknown_root_box_types,
+};
+use crate::tr;
+
+/// 再生時のシーク距離がこれを超える場合にインターリーブ不良として警告する
+const MAX_INTERLEAVE_SEEK_DISTANCE: u64 = 4 * 1024 * 1024;
+
+const STRICT_FLAG: noargs::FlagSpec = noargs::flag("strict").doc(
+    "トップレベルや moov ボックス内の解釈できないボックス、未対応のサンプルエントリーをエラーとして報告します",
+);
+
+const JSON_FLAG: noargs::FlagSpec = noargs::flag("json").doc(
+    "検証結果を、検証項目ごとの {check, status, detail, track} の一覧として JSON で出力します",
+);
+
+const LIST_CHECKS_FLAG: noargs::FlagSpec =
+    noargs::flag("list-checks").doc("検証項目の名前と説明を一覧表示して終了します");
+
+/// 検証項目
+struct Check {
+    /// 検証項目の名前（JSON の `check`）
+    name: &'static str,
+    description: &'static str,
+    /// `description` の英語版
+    description_en: &'static str,
+    /// 問題が見つかった場合の状態（`Info` は報告のみで、終了コードに影響しない）
+    problem_status: Status,
+    /// `--strict` 指定時のみ検証するか
+    strict_only: bool,
+    run: CheckFn,
+}
+
+/// 検証の処理。問題ごとの説明を返す
+enum CheckFn {
+    /// ファイル全体（トラック間）の検証。トップレベルのボックスの位置も渡す
+    File(fn(&MoovBox, &[RootBoxLocation]) -> Vec<String>),
+    /// トラック単位の検証
+    Track(fn(&MoovBox, &TrakBox) -> Vec<String>),
+}
+
+/// 検証項目の一覧（実行順）
+const CHECKS: &[Check] = &[
+    Check {
+        name: "unknown-boxes",
+        description: "トップレベルや moov ボックス内に解釈できないボックスや未対応のサンプルエントリーがないこと（--strict 指定時のみ）",
+        description_en: "There are no unknown boxes at the top level or in the moov box, and no unsupported sample entries (--strict only)",
+        problem_status: Status::Error,
+        strict_only: true,
+        run: CheckFn::File(check_unknown_boxes),
+    },
+    Check {
+        name: "track-duration",
+        description: "tkhd の duration がサンプルの合計時間と一致すること（編集リストがあるトラックは対象外）",
+        description_en: "The tkhd duration matches the total sample duration (tracks with an edit list are skipped)",
+        problem_status: Status::Warning,
+        strict_only: false,
+        run: CheckFn::Track(check_track_duration),
+    },
+    Check {
+        name: "timestamp-gaps",
+        description: "stts のサンプル間隔が通常より長い箇所（フレーム落ちやパケットロスによる欠落）を報告する（情報のみで、終了コードに影響しない）",
+        description_en: "Reports stts sample deltas longer than usual (gaps from dropped frames or packet loss); informational only and does not affect the exit code",
+        problem_status: Status::Info,
+        strict_only: false,
+        run: CheckFn::Track(check_timestamp_gaps),
+    },
+    Check {
+        name: "interleave",
+        description: "再生時の最大シーク距離が閾値以下で、トラックのサンプルが十分にインターリーブされていること",
+        description_en: "The maximum seek distance during playback is within the threshold and the track samples are sufficiently interleaved",
+        problem_status: Status::Warning,
+        strict_only: false,
+        run: CheckFn::File(check_interleave),
+    },
+];
+
+impl Check {
+    /// 表示言語に合わせた説明
+    fn description(&self) -> &'static str {
+        match lang() {
+            Lang::Ja => self.description,
+            Lang::En => self.description_en,
+        }
+    }
+}
+
+/// 検証結果の状態
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Pass,
+    Warning,
+    Error,
+    /// 報告のみで、検証の失敗とはみなさない
+    Info,
+    /// 実行しなかった（`--strict` 未指定時の `strict_only` の検証項目）
+    Skipped,
+}
+
+impl Status {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Pass => "pass",
+            Self::Warning => "warning",
+            Self::Error => "error",
+            Self::Info => "info",
+            Self::Skipped => "skipped",
+        }
+    }
+}
+
+/// 一つの検証項目の結果
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct CheckResult {
+    check: &'static str,
+    status: Status,
+    detail: Option<String>,
+    /// 1 始まりのトラック番号（ファイル全体の検証項目の場合は `None`）
+    track: Option<usize>,
+}
+
+impl CheckResult {
+    fn to_json(&self) -> String {
+        format!(
+            r#"{{"check":{},"status":{},"detail":{},"track":{}}}"#,
+            json::string(self.check),
+            json::string(self.status.as_str()),
+            self.detail
+                .as_deref()
+                .map_or_else(|| "null".to_string(), json::string),
+            self.track
+                .map_or_else(|| "null".to_string(), |track| track.to_string())
+        )
+    }
+}
+
+pub fn run(mut args: noargs::RawArgs) -> noargs::Result<()> {
+    // --list-checks は入力ファイルなしで指定できるよう、位置引数より先に取り出す
+    let list_checks = LIST_CHECKS_FLAG.take(&mut args).is_present();
+    let json = JSON_FLAG.take(&mut args).is_present();
+    let input_file_arg: Option<String> = noargs::arg("[INPUT_FILE]")
+        .example("/path/to/input.mp4")
+        .doc("検証する MP4 ファイル（省略時は stdin から読み込み）")
+        .take(&mut args)
+        .then(|a| a.value().parse())
+        .ok();
+    let strict = STRICT_FLAG.take(&mut args).is_present();
+    let read_cache_size: u64 = READ_CACHE_OPT
+        .take(&mut args)
+        .then(|o| parse_byte_size(o.value()))?;
+    if let Some(help) = args.finish()? {
+        print!("{help}");
+        return Ok(());
+    }
+
+    if list_checks {
+        if json {
+            let checks: Vec<String> = CHECKS
+                .iter()
+                .map(|check| {
+                    format!(
+                        r#"{{"check":{},"description":{}}}"#,
+                        json::string(check.name),
+                        json::string(check.description())
+                    )
+                })
+                .collect();
+            println!("[{}]", checks.join(","));
+        } else {
+            for check in CHECKS {
+                println!("{}: {}", check.name, check.description());
+            }
+        }
+        return Ok(());
+    }
+
+    let input_source = match InputSource::from_arg(input_file_arg) {
+        Some(source) => source,
+        None => {
+            eprintln!(
+                "{}",
+                tr!(
+                    "エラー: 入力ファイルを指定するか、パイプで入力してください",
+                    "Error: specify an input file or pipe the input"
+                )
+            );
+            eprintln!(
+                "{}",
+                tr!("使用例: {}", "Example: {}", "mp4-util validate input.mp4")
+            );
+            eprintln!(
+                "{}",
+                tr!(
+                    "使用例: {}",
+                    "Example: {}",
+                    "cat input.mp4 | mp4-util validate"
+                )
+            );
+            std::process::exit(1);
+        }
+    };
+
+    // 問題が見つかった場合は process::exit で終了するため、stdin を退避した一時ファイルが
+    // 削除されるよう、読み込みが終わった時点でリーダーを破棄する
+    let (moov_box, root_boxes) = {
+        let mut reader = input_source.cached_reader(read_cache_size).map_err(|e| {
+            tr!(
+                "入力を開けません ({}): {}",
+                "Cannot open the input ({}): {}",
+                input_source.description(),
+                e
+            )
+        })?;
+        let moov_box = read_moov_box(&mut reader)?;
+        (moov_box, scan_root_boxes(&mut reader)?)
+    };
+
+    let results = run_checks(&moov_box, &root_boxes, strict);
+    let count = |status| results.iter().filter(|r| r.status == status).count();
+    let error_count = count(Status::Error);
+    let warning_count = count(Status::Warning);
+
+    if json {
+        let results: Vec<String> = results.iter().map(CheckResult::to_json).collect();
+        println!(
+            r#"{{"valid":{},"errors":{},"warnings":{},"results":[{}]}}"#,
+            error_count == 0 && warning_count == 0,
+            error_count,
+            warning_count,
+            results.join(",")
+        );
+    } else {
+        for result in &results {
+            let label = match result.status {
+                Status::Error => tr!("エラー", "Error"),
+                Status::Warning => tr!("警告", "Warning"),
+                Status::Info => tr!("情報", "Info"),
+                Status::Pass | Status::Skipped => continue,
+            };
+            let detail = result.detail.as_deref().unwrap_or_default();
+            match result.track {
+                Some(track) => println!(
+                    "{}",
+                    tr!(
+                        "{}: トラック {}: {}",
+                        "{}: track {}: {}",
+                        label,
+                        track,
+                        detail
+                    )
+                ),
+                None => println!("{}: {}", label, detail),
+            }
+        }
+        if warning_count == 0 && error_count == 0 {
+            println!("{}", tr!("問題は見つかりませんでした", "No problems found"));
+        }
+        if error_count > 0 {
+            println!(
+                "{}",
+                tr!("{} 件のエラーがあります", "{} error(s)", error_count)
+            );
+        }
+        if warning_count > 0 {
+            println!(
+                "{}",
+                tr!("{} 件の警告があります", "{} warning(s)", warning_count)
+            );
+        }
+    }
+
+    if warning_count > 0 || error_count > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// すべての検証項目を実行し、結果を返す
+///
+/// 問題が見つからなかった検証項目は、ファイル全体またはトラックごとに `Status::Pass` の結果を一つ返す
+fn run_checks(
+    moov_box: &MoovBox,
+    root_boxes: &[RootBoxLocation],
+    strict: bool,
+) -> Vec<CheckResult> {
+    let mut results = Vec::new();
+    for check in CHECKS {
+        let problem_status = check.problem_status;
+        let mut push_results = |track: Option<usize>, problems: Vec<String>| {
+            if problems.is_empty() {
+                results.push(CheckResult {
+                    check: check.name,
+                    status: Status::Pass,
+                    detail: None,
+                    track,
+                });
+            }
+            for detail in problems {
+                results.push(CheckResult {
+                    check: check.name,
+                    status: problem_status,
+                    detail: Some(detail),
+                    track,
+                });
+            }
+        };
+
+        if check.strict_only && !strict {
+            results.push(CheckResult {
+                check: check.name,
+                status: Status::Skipped,
+                detail: None,
+                track: None,
+            });
+            continue;
+        }
+        match check.run {
+            CheckFn::File(run) => push_results(None, run(moov_box, root_boxes)),
+            CheckFn::Track(run) => {
+                for (i, trak_box) in moov_box.trak_boxes.iter().enumerate() {
+                    push_results(Some(i + 1), run(moov_box, trak_box));
+                }
+            }
+        }
+    }
+    results
+}
+
+/// トップレベルや moov ボックス内の解釈できないボックス、未対応のサンプルエントリーを列挙する
+fn check_unknown_boxes(moov_box: &MoovBox, root_boxes: &[RootBoxLocation]) -> Vec<String> {
+    unknown_root_box_types(root_boxes)
+        .into_iter()
+        .chain(unknown_box_paths(moov_box))
+        .map(|path| {
+            tr!(
+                "解釈できないボックスがあります: {}",
+                "Unknown box: {}",
+                path
+            )
+        })
+        .collect()
+}
+
+/// 再生時の最大シーク距離を確認する
+fn check_interleave(moov_box: &MoovBox, _root_boxes: &[RootBoxLocation]) -> Vec<String> {
+    match max_interleave_seek_distance(moov_box) {
+        Ok(distance) if distance > MAX_INTERLEAVE_SEEK_DISTANCE => vec![tr!(
+            "インターリーブが不十分です（再生時の最大シーク距離: {} バイト、閾値: {} バイト）",
+            "Insufficient interleaving (maximum seek distance during playback: {} bytes, threshold: {} bytes)",
+            distance,
+            MAX_INTERLEAVE_SEEK_DISTANCE
+        )],
+        Ok(_) => Vec::new(),
+        Err(e) => vec![e],
+    }
+}
+
+/// tkhd の duration とサンプルの合計時間を比較する
+fn check_track_duration(moov_box: &MoovBox, trak_box: &TrakBox) -> Vec<String> {
+    // 編集リストがある場合、tkhd の duration は編集リストの長さを表すため比較しない
+    if trak_box.edts_box.is_some() {
+        return Vec::new();
+    }
+    let Some((declared, computed)) = track_duration_mismatch(
+        trak_box.tkhd_box.duration,
+        moov_box.mvhd_box.timescale.get(),
+        &trak_box.mdia_box.minf_box.stbl_box.stts_box,
+        trak_box.mdia_box.mdhd_box.timescale.get(),
+    ) else {
+        return Vec::new();
+    };
+    let movie_timescale = moov_box.mvhd_box.timescale.get() as f64;
+    vec![tr!(
+        "tkhd の duration ({} = {:.3}秒) がサンプルの合計時間 ({} = {:.3}秒) と一致しません",
+        "The tkhd duration ({} = {:.3}s) does not match the total sample duration ({} = {:.3}s)",
+        declared,
+        declared as f64 / movie_timescale,
+        computed,
+        computed as f64 / movie_timescale
+    )]
+}
+
+/// stts のサンプル間隔が通常より長い箇所（タイムスタンプの欠落）を報告する
+fn check_timestamp_gaps(_moov_box: &MoovBox, trak_box: &TrakBox) -> Vec<String> {
+    let timescale = trak_box.mdia_box.mdhd_box.timescale.get();
+    timestamp_gaps(&trak_box.mdia_box.minf_box.stbl_box.stts_box)
+        .iter()
+        .map(|gap| gap.describe(timescale))
+        .collect()
+}
+
+/// tkhd の duration と stts のサンプル時間の合計を比較する
+///
+/// 両者の差が最長のサンプル一つ分を超える場合に、ムービーのタイムスケールでの
+/// `(宣言された duration, サンプルから計算した duration)` を返す
+fn track_duration_mismatch(
+    declared_duration: u64,
+    movie_timescale: u32,
+    stts_box: &SttsBox,
+    media_timescale: u32,
+) -> Option<(u64, u64)> {
+    let to_movie_timescale = |duration: u64| {
+        (duration as u128 * movie_timescale as u128 / media_timescale as u128) as u64
+    };
+
+    let sample_duration_sum: u64 = stts_box
+        .entries
+        .iter()
+        .map(|e| e.sample_count as u64 * e.sample_delta as u64)
+        .sum();
+    let max_sample_delta = stts_box
+        .entries
+        .iter()
+        .map(|e| e.sample_delta as u64)
+        .max()
+        .unwrap_or(0);
+
+    let computed_duration = to_movie_timescale(sample_duration_sum);
+    let tolerance = to_movie_timescale(max_sample_delta);
+    (declared_duration.abs_diff(computed_duration) > tolerance)
+        .then_some((declared_duration, computed_duration))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_track_duration_mismatch() {
+        // 30fps で 300 サンプル（10 秒）
+        let stts_box = SttsBox::from_sample_deltas(std::iter::repeat_n(1, 300));
+        assert_eq!(track_duration_mismatch(10_000, 1000, &stts_box, 30), None);
+
+        // 1 サンプル分のずれは許容する
+        assert_eq!(track_duration_mismatch(10_033, 1000, &stts_box, 30), None);
+
+        assert_eq!(
+            track_duration_mismatch(12_000, 1000, &stts_box, 30),
+            Some((12_000, 10_000))
+        );
+    }
+
+    #[test]
+    fn test_run_checks() {
+        use crate::test_util::Mp4Builder;
+        use shiguredo_mp4::TrackKind;
+
+        let mut moov_box = Mp4Builder::new()
+            .track(TrackKind::Video, 30, &[1; 30], 1)
+            .moov_box();
+
+        let results = run_checks(&moov_box, &[], false);
+        let statuses: Vec<_> = results.iter().map(|r| (r.check, r.status)).collect();
+        assert_eq!(
+            statuses,
+            [
+                ("unknown-boxes", Status::Skipped),
+                ("track-duration", Status::Pass),
+                ("timestamp-gaps", Status::Pass),
+                ("interleave", Status::Pass),
+            ]
+        );
+
+        moov_box.trak_boxes[0].tkhd_box.duration *= 2;
+        let results = run_checks(&moov_box, &[], true);
+        let warning = results
+            .iter()
+            .find(|r| r.status == Status::Warning)
+            .unwrap();
+        assert_eq!((warning.check, warning.track), ("track-duration", Some(1)));
+        // テスト用のサンプルエントリーは解釈できないボックスとして報告される
+        assert_eq!(
+            results[0].to_json(),
+            r#"{"check":"unknown-boxes","status":"error","detail":"解釈できないボックスがあります: moov/trak[1]/mdia/minf/stbl/stsd/test","track":null}"#
+        );
+    }
+
+    #[test]
+    fn test_unknown_root_boxes() {
+        use crate::test_util::Mp4Builder;
+        use shiguredo_mp4::TrackKind;
+
+        // ファイル末尾に未知のトップレベルのボックスと free ボックスを追加する
+        let (mut file, moov_box) = Mp4Builder::new()
+            .track(TrackKind::Video, 30, &[1; 30], 1)
+            .build_with_moov_box();
+        file.extend_from_slice(&[0, 0, 0, 8]);
+        file.extend_from_slice(b"abcd");
+        file.extend_from_slice(&[0, 0, 0, 8]);
+        file.extend_from_slice(b"free");
+        let root_boxes = scan_root_boxes(&mut std::io::Cursor::new(file)).unwrap();
+
+        assert_eq!(
+            check_unknown_boxes(&moov_box, &root_boxes),
+            [
+                "解釈できないボックスがあります: abcd",
+                "解釈できないボックスがあります: moov/trak[1]/mdia/minf/stbl/stsd/test",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_timestamp_gaps_are_informational() {
+        use crate::test_util::Mp4Builder;
+        use shiguredo_mp4::TrackKind;
+
+        // 4 サンプル目の間隔だけが通常の 3 倍
+        let moov_box = Mp4Builder::new()
+            .track(TrackKind::Video, 30, &[1, 1, 1, 3, 1, 1], 1)
+            .moov_box();
+        let results = run_checks(&moov_box, &[], false);
+        let gap = results
+            .iter()
+            .find(|r| r.check == "timestamp-gaps")
+            .unwrap();
+        assert_eq!((gap.status, gap.track), (Status::Info, Some(1)));
+        assert!(
+            results
+                .iter()
+                .all(|r| !matches!(r.status, Status::Error | Status::Warning))
+        );
+    }
+}
@@ -0,0 +1,128 @@
+//! テスト用の MP4 ファイルを組み立てるヘルパー
+use std::num::NonZeroU32;
+
+use shiguredo_mp4::{
+    BoxSize, BoxType, Encode, TrackKind,
+    boxes::{MoovBox, SampleEntry, UnknownBox},
+    mux::{Mp4FileMuxer, Sample},
+};
+
+/// 中身を持たないテスト用のサンプルエントリー
+pub fn test_sample_entry() -> SampleEntry {
+    SampleEntry::Unknown(UnknownBox {
+        box_type: BoxType::Normal(*b"test"),
+        box_size: BoxSize::U32(8),
+        payload: Vec::new(),
+    })
+}
+
+/// サンプルデータを mdat に書きながらトラックを追加していく MP4 ファイルのビルダー
+pub struct Mp4Builder {
+    muxer: Mp4FileMuxer,
+    buffer: Vec<u8>,
+    sample_entry: SampleEntry,
+}
+
+impl Mp4Builder {
+    pub fn new() -> Self {
+        let muxer = Mp4FileMuxer::new().unwrap();
+        let buffer = muxer.initial_boxes_bytes().to_vec();
+        Self {
+            muxer,
+            buffer,
+            sample_entry: test_sample_entry(),
+        }
+    }
+
+    /// 以降に追加するサンプルのサンプルエントリーを変更する
+    pub fn sample_entry(mut self, sample_entry: SampleEntry) -> Self {
+        self.sample_entry = sample_entry;
+        self
+    }
+
+    /// 次に追加するサンプルのデータのファイル内での位置
+    pub fn data_offset(&self) -> u64 {
+        self.buffer.len() as u64
+    }
+
+    /// `deltas` の長さのサンプルを追加する（`keyframe_interval` サンプルごとにキーフレーム）
+    ///
+    /// 各サンプルのデータはサンプル番号の下位 1 バイト。
+    pub fn track(
+        mut self,
+        track_kind: TrackKind,
+        timescale: u32,
+        deltas: &[u32],
+        keyframe_interval: usize,
+    ) -> Self {
+        for (i, &duration) in deltas.iter().enumerate() {
+            self = self.sample(
+                track_kind,
+                timescale,
+                duration,
+                i % keyframe_interval == 0,
+                &[i as u8],
+            );
+        }
+        self
+    }
+
+    /// 任意のデータを持つサンプルを一つ追加する
+    pub fn sample(
+        mut self,
+        track_kind: TrackKind,
+        timescale: u32,
+        duration: u32,
+        keyframe: bool,
+        data: &[u8],
+    ) -> Self {
+        self.muxer
+            .append_sample(&Sample {
+                track_kind,
+                sample_entry: Some(self.sample_entry.clone()),
+                keyframe,
+                timescale: NonZeroU32::new(timescale).unwrap(),
+                duration,
+                data_offset: self.buffer.len() as u64,
+                data_size: data.len(),
+            })
+            .unwrap();
+        self.buffer.extend_from_slice(data);
+        self
+    }
+
+    pub fn moov_box(mut self) -> MoovBox {
+        self.muxer.finalize().unwrap().moov_box().clone()
+    }
+
+    /// moov ボックスを末尾に置いた MP4 ファイル全体のバイト列を返す
+    pub fn build(self) -> Vec<u8> {
+        self.build_with_moov_box().0
+    }
+
+    /// [`Mp4Builder::build()`] と同じバイト列と、その moov ボックスを返す
+    pub fn build_with_moov_box(mut self) -> (Vec<u8>, MoovBox) {
+        let finalized = self.muxer.finalize().unwrap();
+        for (offset, bytes) in finalized.offset_and_bytes_pairs() {
+            let offset = offset as usize;
+            if self.buffer.len() < offset + bytes.len() {
+                self.buffer.resize(offset + bytes.len(), 0);
+            }
+            self.buffer[offset..offset + bytes.len()].copy_from_slice(bytes);
+        }
+        (self.buffer, finalized.moov_box().clone())
+    }
+
+    /// moov ボックスを `edit` で書き換えた MP4 ファイル全体のバイト列を返す
+    ///
+    /// moov ボックスは mdat ボックスより後ろにあるため、書き換えてもサンプルデータの位置は変わらない
+    pub fn build_with_edited_moov_box(self, edit: impl FnOnce(&mut MoovBox)) -> Vec<u8> {
+        let (mut bytes, mut moov_box) = self.build_with_moov_box();
+        let moov_offset = bytes.len() - moov_box.encode_to_vec().unwrap().len();
+        assert_eq!(&bytes[moov_offset + 4..moov_offset + 8], b"moov");
+        bytes.truncate(moov_offset);
+        edit(&mut moov_box);
+        bytes.extend_from_slice(&moov_box.encode_to_vec().unwrap());
+        bytes
+    }
+}